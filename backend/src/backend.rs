@@ -2,7 +2,10 @@
 
 use std::collections::BTreeMap;
 
+use crate::cancel::CancelToken;
+use crate::config;
 use crate::controls;
+use crate::dem;
 use crate::error::Error;
 use crate::event;
 use crate::gpsdata;
@@ -14,10 +17,13 @@ use crate::osm;
 use crate::parameters::Parameters;
 use crate::parameters::ProfileIndication;
 use crate::parameters::UserStepsOptions;
+use crate::part_order::PartOrdering;
 use crate::pdf;
 use crate::profile;
+use crate::projectfile;
 use crate::render;
 use crate::segment::SegmentData;
+use crate::speed;
 use crate::track::SharedTrack;
 use crate::track::Track;
 use crate::track_projection::is_close_to_track;
@@ -25,11 +31,13 @@ use crate::track_projection::ProjectionTrees;
 use crate::waypoint::Waypoint;
 use crate::waypoint::WaypointInfo;
 use crate::waypoint::Waypoints;
+use crate::wgs84point::WGS84Point;
 use crate::wheel;
 
 pub type Segment = crate::segment::Segment;
 pub type SegmentStatistics = crate::segment::SegmentStatistics;
-pub use crate::event::Sender;
+pub use crate::event::{Event, Sender, Stage};
+pub use crate::osm::{CacheConfig, CacheStats, OverpassConfig, Recording};
 use crate::zipexport;
 pub type SenderHandler = crate::event::SenderHandler;
 pub type SenderHandlerLock = crate::event::SenderHandlerLock;
@@ -38,11 +46,24 @@ pub struct BackendData {
     pub parameters: Parameters,
     pub track: SharedTrack,
     pub inputpoints: SharedPointMaps,
+    // the loaded file, kept for saving the project.
+    pub content: Vec<u8>,
+    // the order its parts were read in, also saved in the project.
+    pub part_ordering: PartOrdering,
 }
 
 pub struct Backend {
     backend_data: Option<BackendData>,
     pub sender: SenderHandlerLock,
+    elevation_source: Option<dem::SharedElevationSource>,
+    elevation_fill: dem::ElevationFill,
+    part_ordering: PartOrdering,
+    // read osm points from this file instead of downloading them.
+    osm_pbf_file: Option<String>,
+    overpass: OverpassConfig,
+    osm_cache: CacheConfig,
+    // the parameters of newly loaded tracks.
+    default_parameters: Parameters,
 }
 
 impl Backend {
@@ -50,6 +71,13 @@ impl Backend {
         Backend {
             backend_data: None,
             sender: std::sync::RwLock::new(None),
+            elevation_source: dem::default_source(),
+            elevation_fill: dem::ElevationFill::Missing,
+            part_ordering: PartOrdering::Geometry,
+            osm_pbf_file: None,
+            overpass: OverpassConfig::default(),
+            osm_cache: CacheConfig::default(),
+            default_parameters: Parameters::default(),
         }
     }
     pub fn loaded(&self) -> bool {
@@ -58,29 +86,197 @@ impl Backend {
     pub fn set_sink(&mut self, sink: SenderHandler) {
         self.sender = std::sync::RwLock::new(Some(sink));
     }
-    pub async fn send(&self, data: &String) {
-        log::trace!("event:{}", data);
+    pub fn set_elevation_source(
+        &mut self,
+        source: Option<dem::SharedElevationSource>,
+        fill: dem::ElevationFill,
+    ) {
+        self.elevation_source = source;
+        self.elevation_fill = fill;
+    }
+    pub fn set_dem_directory(&mut self, directory: &str, fill: dem::ElevationFill) {
+        let source = dem::HgtDirectory::new(std::path::Path::new(directory));
+        self.set_elevation_source(Some(std::sync::Arc::new(source)), fill);
+    }
+    pub async fn send(&self, event: &Event) {
+        log::trace!("event:{}", event);
         if self.sender.read().unwrap().is_none() {
             return;
         }
-        event::send_worker(&self.sender, data).await
+        event::send_worker(&self.sender, event).await
+    }
+    async fn start(&self, stage: Stage) {
+        self.send(&Event::StageStarted { stage }).await;
+    }
+    async fn finish(&self, stage: Stage) {
+        self.send(&Event::StageFinished { stage }).await;
     }
 
-    pub async fn load_content(&mut self, content: &Vec<u8>) -> Result<(), Error> {
-        self.send(&"read gpx".to_string()).await;
-        let mut gpxdata = gpsdata::read_content(content)?;
-        let track_data = Track::from_tracks(&gpxdata.tracks)?;
+    // None: download the osm points.
+    pub fn set_osm_pbf_file(&mut self, filename: Option<String>) {
+        self.osm_pbf_file = filename;
+    }
+
+    pub fn get_overpass_config(&self) -> OverpassConfig {
+        self.overpass.clone()
+    }
+    pub fn set_overpass_config(&mut self, config: &OverpassConfig) {
+        self.overpass = config.clone();
+    }
+
+    pub fn get_cache_config(&self) -> CacheConfig {
+        self.osm_cache.clone()
+    }
+    pub fn set_cache_config(&mut self, config: &CacheConfig) {
+        self.osm_cache = config.clone();
+    }
+    pub fn get_default_parameters(&self) -> Parameters {
+        self.default_parameters.clone()
+    }
+    pub fn set_default_parameters(&mut self, parameters: &Parameters) -> Result<(), Error> {
+        parameters.validate()?;
+        self.default_parameters = parameters.clone();
+        Ok(())
+    }
+    // reads the default parameters from the user configuration file, false
+    // if there is none.
+    pub fn load_user_config(&mut self) -> Result<bool, Error> {
+        match config::read_user_config()? {
+            Some(parameters) => {
+                self.default_parameters = parameters;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    pub async fn osm_cache_stats(&self) -> CacheStats {
+        osm::cache_stats(&self.osm_cache).await
+    }
+    pub async fn clear_osm_cache(&self) {
+        osm::clear_cache().await
+    }
+    // downloads the osm points of the bounding box (min, max) in the cache.
+    pub async fn prefetch_osm(&self, min: &WGS84Point, max: &WGS84Point) -> Result<(), Error> {
+        let bbox = crate::track::WGS84BoundingBox::minmax(min.point2d(), max.point2d());
+        self.start(Stage::OSM).await;
+        let result = osm::download_for_bbox(
+            &bbox,
+            &self.overpass,
+            &self.osm_cache,
+            &CancelToken::new(),
+            &self.sender,
+        )
+        .await;
+        self.finish(Stage::OSM).await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                log::error!("could not prefetch osm points: {}", e);
+                Err(Error::OSMDownload)
+            }
+        }
+    }
+
+    // None: order the parts of the GPX file by geometry.
+    pub fn set_part_order(&mut self, order: Option<Vec<usize>>) {
+        self.part_ordering = part_ordering(order);
+    }
+
+    // reads the track (parts, elevations) and its gpx waypoints.
+    async fn read_track(
+        &self,
+        content: &[u8],
+        ordering: &PartOrdering,
+    ) -> Result<(SharedTrack, ProjectionTrees, gpsdata::GpxData), Error> {
+        self.start(Stage::ReadTrack).await;
+        let ret = self.read_track_data(content, ordering).await;
+        self.finish(Stage::ReadTrack).await;
+        ret
+    }
+
+    async fn read_track_data(
+        &self,
+        content: &[u8],
+        ordering: &PartOrdering,
+    ) -> Result<(SharedTrack, ProjectionTrees, gpsdata::GpxData), Error> {
+        let mut gpxdata = gpsdata::read_content_with_order(content, ordering)?;
+        for gap in gpxdata.part_order.large_gaps() {
+            let message = format!(
+                "gap of {:.1} km before part {}",
+                gap.distance / 1000f64,
+                gap.index + 1
+            );
+            log::warn!("{}", message);
+            self.send(&Event::Warning { message }).await;
+        }
+        if let Some(source) = &self.elevation_source {
+            let count = dem::fill(&mut gpxdata.tracks, source.as_ref(), &self.elevation_fill);
+            log::info!("{} elevations from DEM", count);
+        }
+        let mut track_data = Track::from_tracks(&gpxdata.tracks)?;
+        track_data.set_elevation_model(&self.default_parameters.elevation_model());
         let track = std::sync::Arc::new(track_data);
         let trees = ProjectionTrees::make(&track);
-        self.send(&"download osm data".to_string()).await;
+        Ok((track, trees, gpxdata))
+    }
+
+    /*
+     * With the token cancelled, the load stops with Error::Cancelled and
+     * the current data is kept. The osm tiles downloaded so far stay in
+     * the cache.
+     * Every started stage is finished, also when the load fails.
+     */
+    pub async fn load_content(
+        &mut self,
+        content: &[u8],
+        cancel: &CancelToken,
+    ) -> Result<(), Error> {
+        self.start(Stage::Load).await;
+        let ret = self.load_content_stages(content, cancel).await;
+        self.finish(Stage::Load).await;
+        ret
+    }
+
+    async fn load_content_stages(
+        &mut self,
+        content: &[u8],
+        cancel: &CancelToken,
+    ) -> Result<(), Error> {
+        let (track, trees, mut gpxdata) = self.read_track(content, &self.part_ordering).await?;
+        cancel.check()?;
+        self.start(Stage::OSM).await;
         let mut inputpoints_map = BTreeMap::new();
-        let mut osmpoints = match osm::download_for_track(&track, &self.sender).await {
-            Ok(p) => p,
-            Err(_) => {
-                // todo: handle osm download separately and handle error
-                InputPointMap::new()
+        let result = match &self.osm_pbf_file {
+            Some(filename) => {
+                osm::extract_for_track(&track, filename, &self.osm_cache, cancel).await
+            }
+            None => {
+                osm::download_for_track(
+                    &track,
+                    &self.overpass,
+                    &self.osm_cache,
+                    cancel,
+                    &self.sender,
+                )
+                .await
             }
         };
+        let osmpoints = match result {
+            Ok(p) => Ok(p),
+            Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::Cancelled)) => {
+                log::info!("load cancelled");
+                Err(Error::Cancelled)
+            }
+            Err(e) => {
+                log::error!("could not get osm points: {}", e);
+                let message = "OSM download failed, continuing without POIs".to_string();
+                self.send(&Event::Warning { message }).await;
+                Ok(InputPointMap::new())
+            }
+        };
+        self.finish(Stage::OSM).await;
+        let mut osmpoints = osmpoints?;
+        self.start(Stage::Controls).await;
         trees.iter_on(&mut osmpoints, &track);
         inputpoints_map.insert(InputType::OSM, osmpoints);
         trees.iter_on(&mut gpxdata.waypoints, &track);
@@ -120,46 +316,136 @@ impl Backend {
                 .insert(InputType::Control, InputPointMap::from_vector(&controls));
         }
 
-        let parameters = Parameters::default();
-        self.send(&"compute elevation".to_string()).await;
+        let parameters = self.default_parameters.clone();
         let data = BackendData {
             track,
             inputpoints,
             parameters,
+            content: content.to_vec(),
+            part_ordering: self.part_ordering.clone(),
         };
-        self.send(&"update waypoints".to_string()).await;
         self.backend_data = Some(data);
 
-        self.set_user_step_options(&self.get_parameters().user_steps_options);
-        self.send(&"done".to_string()).await;
-        Ok(())
+        let ret = self.set_user_step_options(&self.d().parameters.user_steps_options.clone());
+        self.finish(Stage::Controls).await;
+        ret
+    }
+
+    pub async fn load_project(&mut self, content: &[u8]) -> Result<(), Error> {
+        let mut project = projectfile::read(content)?;
+        // the host settings, not in the project.
+        let pdf = &self.default_parameters.pdf;
+        project.parameters.pdf.typst_binary = pdf.typst_binary.clone();
+        project.parameters.pdf.accept_invalid_certificates = pdf.accept_invalid_certificates;
+        project.parameters.validate()?;
+        self.start(Stage::Load).await;
+        let ret = self.load_project_stages(project).await;
+        self.finish(Stage::Load).await;
+        ret
+    }
+
+    async fn load_project_stages(&mut self, project: projectfile::Project) -> Result<(), Error> {
+        // the project's order, the one set for the next files is kept.
+        let part_ordering = part_ordering(project.part_order.clone());
+        let (track, trees, mut gpxdata) = self.read_track(&project.content, &part_ordering).await?;
+        let mut maps = project.points;
+        reproject(&mut maps, &track, &trees);
+        maps.entry(InputType::GPX).or_insert_with(|| {
+            trees.iter_on(&mut gpxdata.waypoints, &track);
+            gpxdata.waypoints
+        });
+        for kind in projectfile::KINDS {
+            maps.entry(kind).or_insert_with(InputPointMap::new);
+        }
+        let data = BackendData {
+            track,
+            inputpoints: SharedPointMaps::new(InputPointMaps { maps }.into()),
+            parameters: project.parameters.clone(),
+            content: project.content,
+            part_ordering,
+        };
+        self.backend_data = Some(data);
+        self.set_parameters(&project.parameters)
     }
 
     pub async fn load_filename(&mut self, filename: &str) -> Result<(), Error> {
-        let mut f = std::fs::File::open(filename).unwrap();
-        let mut buffer = Vec::new();
-        // read the whole file
-        use std::io::prelude::*;
-        f.read_to_end(&mut buffer).unwrap();
-        self.load_content(&buffer).await
+        let buffer = std::fs::read(filename).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::GPXNotFound,
+            _ => Error::IOError {
+                message: format!("could not read {}: {}", filename, e),
+            },
+        })?;
+        self.load_content(&buffer, &CancelToken::new()).await
     }
 
     pub async fn load_demo(&mut self) -> Result<(), Error> {
         let content = include_bytes!("../data/ref/roland-nowaypoints.gpx");
-        self.load_content(&content.to_vec()).await
+        self.load_content(content, &CancelToken::new()).await
+    }
+}
+
+// None: order the parts by geometry.
+fn part_ordering(order: Option<Vec<usize>>) -> PartOrdering {
+    match order {
+        Some(indices) => PartOrdering::Explicit(indices),
+        None => PartOrdering::Geometry,
+    }
+}
+
+/*
+ * The projections saved in a project were computed with the elevations of
+ * the session that saved it (DEM directory, fill mode). Compute them again
+ * on the track read now. Controls stay on their track points.
+ */
+fn reproject(
+    maps: &mut BTreeMap<InputType, InputPointMap>,
+    track: &Track,
+    trees: &ProjectionTrees,
+) {
+    for (kind, map) in maps.iter_mut() {
+        match kind {
+            InputType::Control => {
+                for point in map.iter_mut() {
+                    point.track_projections = point
+                        .track_projections
+                        .iter()
+                        .map(|proj| {
+                            let mut proj = proj.clone();
+                            proj.elevation = track.wgs84[proj.track_index].z();
+                            proj.distance_on_track_to_projection = track.distance(proj.track_index);
+                            proj
+                        })
+                        .collect();
+                }
+            }
+            _ => {
+                for point in map.iter_mut() {
+                    point.track_projections.clear();
+                }
+                trees.iter_on(map, track);
+            }
+        }
     }
 }
 
 // methods that access BackendData (should not be used in bridge)
+// the public ones return Error::NotLoaded before a track is loaded, the
+// pub(crate) ones must be called after check_loaded().
 impl Backend {
-    pub fn d(&self) -> &BackendData {
+    pub(crate) fn d(&self) -> &BackendData {
         self.backend_data.as_ref().unwrap()
     }
     fn dmut(&mut self) -> &mut BackendData {
         self.backend_data.as_mut().unwrap()
     }
+    fn check_loaded(&self) -> Result<(), Error> {
+        match self.backend_data {
+            Some(_) => Ok(()),
+            None => Err(Error::NotLoaded),
+        }
+    }
 
-    pub fn make_segment_data(&self, segment: &Segment) -> SegmentData {
+    pub(crate) fn make_segment_data(&self, segment: &Segment) -> SegmentData {
         SegmentData::new(
             segment,
             self.d().track.clone(),
@@ -168,40 +454,68 @@ impl Backend {
         )
     }
 
-    pub fn get_parameters(&self) -> Parameters {
-        self.d().parameters.clone()
+    pub(crate) fn timetable(&self) -> speed::TimeTable {
+        speed::make_timetable(
+            &self.d().track,
+            &self.d().parameters,
+            &self.d().inputpoints.read().unwrap(),
+        )
     }
 
-    pub fn set_parameters(&mut self, parameters: &Parameters) {
+    pub fn save_project(&self) -> Result<Vec<u8>, Error> {
+        self.check_loaded()?;
+        let part_order = match &self.d().part_ordering {
+            PartOrdering::Explicit(indices) => Some(indices.clone()),
+            _ => None,
+        };
+        let mut points = BTreeMap::new();
+        let locked = self.d().inputpoints.read().unwrap();
+        for kind in projectfile::KINDS {
+            if let Some(map) = locked.maps.get(&kind) {
+                points.insert(kind, map.clone());
+            }
+        }
+        Ok(projectfile::write(&projectfile::Project {
+            content: self.d().content.clone(),
+            parameters: self.d().parameters.clone(),
+            part_order,
+            points,
+        }))
+    }
+
+    // the OSM point categories that can be selected in the parameters.
+    pub fn get_osm_categories(&self) -> Vec<OSMCategory> {
+        osm::category::registry().to_vec()
+    }
+
+    pub fn get_parameters(&self) -> Result<Parameters, Error> {
+        self.check_loaded()?;
+        Ok(self.d().parameters.clone())
+    }
+
+    pub fn set_parameters(&mut self, parameters: &Parameters) -> Result<(), Error> {
+        self.check_loaded()?;
+        parameters.validate()?;
         self.dmut().parameters = parameters.clone();
-        if self.d().parameters.segment_overlap > self.d().parameters.segment_length {
-            assert!(false);
+        let model = parameters.elevation_model();
+        if self.d().track.elevation_model != model {
+            let mut track = (*self.d().track).clone();
+            track.set_elevation_model(&model);
+            self.dmut().track = std::sync::Arc::new(track);
         }
 
         // update user steps
-        {
-            let mut locked = self.d().inputpoints.write().unwrap();
-            locked
-                .maps
-                .insert(InputType::UserStep, InputPointMap::new());
-
-            // update user points
-            match locked.maps.get_mut(&InputType::UserStep) {
-                Some(user_steps_map) => {
-                    user_steps_map.clear();
-                    user_steps_map.sort_and_insert(&make_points::user_points(
-                        &self.d().track,
-                        &self.d().parameters.user_steps_options,
-                    ));
-                }
-                _ => {
-                    assert!(false);
-                }
-            }
-        }
+        let user_points =
+            make_points::user_points(&self.d().track, &self.d().parameters.user_steps_options);
+        let mut user_steps_map = InputPointMap::new();
+        user_steps_map.sort_and_insert(&user_points);
+        let mut locked = self.d().inputpoints.write().unwrap();
+        locked.maps.insert(InputType::UserStep, user_steps_map);
+        Ok(())
     }
 
-    pub fn get_points(&self, segment: &Segment, kinds: Kinds) -> Vec<InputPoint> {
+    // the points of the kinds close to the track in the segment.
+    pub(crate) fn get_points(&self, segment: &Segment, kinds: Kinds) -> Vec<InputPoint> {
         let mut points = Vec::new();
         let range = self.d().track.subrange(segment.start, segment.end);
         for kind in &kinds {
@@ -209,9 +523,12 @@ impl Backend {
                 Some(kpoints) => {
                     let mut copy = kpoints.as_vector();
                     copy.retain(|w| {
-                        assert!(kinds.contains(&w.kind()));
-                        assert!(is_close_to_track(&w));
-                        range.contains(&w.track_projections.first().unwrap().track_index)
+                        kinds.contains(&w.kind())
+                            && is_close_to_track(w)
+                            && w.track_projections
+                                .first()
+                                .is_some_and(|p| range.contains(&p.track_index))
+                            && self.d().parameters.shows(w)
                     });
                     points.extend_from_slice(&copy);
                 }
@@ -222,58 +539,196 @@ impl Backend {
         points
     }
 
-    pub fn export_points(&self, points: &Vec<InputPoint>) -> Waypoints {
+    pub(crate) fn export_points(&self, points: &Vec<InputPoint>) -> Waypoints {
         let mut ret = Waypoints::new();
         for p in points {
             ret.push(p.waypoint());
         }
-        WaypointInfo::make_waypoint_infos(&mut ret, &self.d().track, &self.d().parameters);
+        WaypointInfo::make_waypoint_infos(
+            &mut ret,
+            &self.d().track,
+            &self.timetable(),
+            &self.d().parameters,
+        );
+        ret
+    }
+
+    // controls sorted along the track, the index is the one used to edit them.
+    fn controls(&self) -> Vec<InputPoint> {
+        let mut ret = match self
+            .d()
+            .inputpoints
+            .read()
+            .unwrap()
+            .maps
+            .get(&InputType::Control)
+        {
+            Some(map) => map.as_vector(),
+            None => Vec::new(),
+        };
+        controls::sort_controls(&mut ret);
         ret
     }
 
-    pub fn get_waypoints(&self, segment: &Segment, kinds: Kinds) -> Vec<Waypoint> {
-        self.export_points(&self.get_points(&segment, kinds))
+    fn set_controls(&mut self, controls: &Vec<InputPoint>) {
+        let mut locked = self.d().inputpoints.write().unwrap();
+        locked
+            .maps
+            .insert(InputType::Control, InputPointMap::from_vector(controls));
     }
 
-    pub async fn generatePdf(&self) -> Vec<u8> {
-        let typbytes = render::make_typst_document(self);
-        let ret = pdf::compile(&typbytes, self.get_parameters().debug).await;
-        log::info!("generated {} pdf bytes", ret.len());
+    fn edit_control<F>(&mut self, index: usize, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&Track, &InputPoint) -> Result<InputPoint, Error>,
+    {
+        let mut controls = self.controls();
+        if index >= controls.len() {
+            return Err(Error::ControlNotFound { index });
+        }
+        controls[index] = f(&self.d().track, &controls[index])?;
+        self.set_controls(&controls);
+        Ok(())
+    }
+
+    pub fn get_controls(&self) -> Result<Vec<Waypoint>, Error> {
+        self.check_loaded()?;
+        Ok(self.export_points(&self.controls()))
+    }
+
+    pub fn add_control_at_distance(&mut self, distance: f64, name: &str) -> Result<(), Error> {
+        self.check_loaded()?;
+        let control = controls::control_at_distance(&self.d().track, distance, name, "")?;
+        let mut controls = self.controls();
+        controls.push(control);
+        self.set_controls(&controls);
+        Ok(())
+    }
+
+    pub fn add_control_near(&mut self, wgs84: &WGS84Point, name: &str) -> Result<(), Error> {
+        self.check_loaded()?;
+        let control = controls::control_near(&self.d().track, wgs84, name)?;
+        let mut controls = self.controls();
+        controls.push(control);
+        self.set_controls(&controls);
+        Ok(())
+    }
+
+    pub fn rename_control(&mut self, index: usize, name: &str) -> Result<(), Error> {
+        self.check_loaded()?;
+        self.edit_control(index, |_track, control| {
+            let mut ret = control.clone();
+            ret.tags.insert("name".to_string(), name.to_string());
+            Ok(ret)
+        })
+    }
+
+    pub fn move_control(&mut self, index: usize, distance: f64) -> Result<(), Error> {
+        self.check_loaded()?;
+        self.edit_control(index, |track, control| {
+            controls::control_at_distance(track, distance, &control.name(), &control.description())
+        })
+    }
+
+    pub fn delete_control(&mut self, index: usize) -> Result<(), Error> {
+        self.check_loaded()?;
+        let mut controls = self.controls();
+        if index >= controls.len() {
+            return Err(Error::ControlNotFound { index });
+        }
+        controls.remove(index);
+        self.set_controls(&controls);
+        Ok(())
+    }
+
+    pub fn get_sun_waypoints(&self, segment: &Segment) -> Result<Vec<Waypoint>, Error> {
+        self.check_loaded()?;
+        Ok(self.export_points(&self.make_segment_data(segment).sun_points()))
+    }
+
+    pub fn get_waypoints(&self, segment: &Segment, kinds: Kinds) -> Result<Vec<Waypoint>, Error> {
+        self.check_loaded()?;
+        Ok(self.export_points(&self.get_points(segment, kinds)))
+    }
+
+    pub async fn generatePdf(&self, cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+        self.check_loaded()?;
+        self.start(Stage::Pdf).await;
+        let ret = self.generate_pdf_stage(cancel).await;
+        self.finish(Stage::Pdf).await;
         ret
     }
-    pub fn generateGpx(&self) -> Vec<u8> {
+
+    async fn generate_pdf_stage(&self, cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+        let document = render::make_typst_document(self)?;
+        for overflow in &document.overflows {
+            let message = format!(
+                "{} points of segment {} continue on {} more pages",
+                overflow.points,
+                overflow.segment + 1,
+                overflow.pages
+            );
+            self.send(&Event::Warning { message }).await;
+        }
+        cancel.check()?;
+        self.send(&Event::Progress {
+            stage: Stage::Pdf,
+            fraction: 0.5,
+        })
+        .await;
+        let ret = pdf::compile(
+            &document.source,
+            &self.d().parameters.pdf,
+            self.d().parameters.debug,
+            cancel,
+        )
+        .await?;
+        log::info!("generated {} pdf bytes", ret.len());
+        Ok(ret)
+    }
+    // the problems of the templates of the directory, see render.rs.
+    pub fn check_templates(&self, directory: &str) -> Result<(), Error> {
+        render::Templates::load(std::path::Path::new(directory)).map(|_| ())
+    }
+
+    // writes the built-in templates, as a start for custom ones.
+    pub fn export_templates(&self, directory: &str) -> Result<(), Error> {
+        render::Templates::builtin().write(std::path::Path::new(directory))
+    }
+
+    pub fn generateGpx(&self) -> Result<Vec<u8>, Error> {
+        self.check_loaded()?;
         let mut gpxpoints = Vec::new();
         for kind in [InputType::UserStep] {
             match self.d().inputpoints.read().unwrap().maps.get(&kind) {
                 Some(p) => {
-                    let v = p.as_vector();
-                    v.iter().for_each(|p| {
-                        assert!(!p.track_projections.is_empty());
-                    });
-                    gpxpoints.extend_from_slice(&v);
+                    gpxpoints.extend_from_slice(&p.as_vector());
                 }
                 _ => {}
             }
         }
         let waypoints = self.export_points(&gpxpoints);
-        gpxexport::generate(&self.d().track, &waypoints)
+        Ok(gpxexport::generate(&self.d().track, &waypoints))
     }
-    pub async fn generateZip(&self) -> Vec<u8> {
-        let gpx = self.generateGpx();
-        let pdf = self.generatePdf().await;
-        zipexport::generate(&gpx, &pdf)
+    pub async fn generateZip(&self, cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+        let gpx = self.generateGpx()?;
+        let pdf = self.generatePdf(cancel).await?;
+        Ok(zipexport::generate(&gpx, &pdf))
     }
 
-    pub fn set_user_step_options(&mut self, options: &UserStepsOptions) {
+    pub fn set_user_step_options(&mut self, options: &UserStepsOptions) -> Result<(), Error> {
+        self.check_loaded()?;
+        options.validate()?;
         self.dmut().parameters.user_steps_options = options.clone();
         let new_points =
             make_points::user_points(&self.d().track, &self.d().parameters.user_steps_options);
         let mut lock = self.dmut().inputpoints.write().unwrap();
         lock.maps
             .insert(InputType::UserStep, InputPointMap::from_vector(&new_points));
+        Ok(())
     }
 
-    pub fn set_profile_indication(&mut self, p: &ProfileIndication) {
+    pub fn set_profile_indication(&mut self, p: &ProfileIndication) -> Result<(), Error> {
+        self.check_loaded()?;
         self.dmut()
             .parameters
             .profile_options
@@ -284,27 +739,44 @@ impl Backend {
             .profile_options
             .elevation_indicators
             .insert(p.clone());
+        Ok(())
     }
 
-    pub fn set_userstep_gpx_name_format(&mut self, format: &String) {
-        self.dmut().parameters.user_steps_options.gpx_name_format = format.clone();
+    pub fn set_userstep_gpx_name_format(&mut self, format: &str) -> Result<(), Error> {
+        let mut options = self.get_parameters()?.user_steps_options;
+        options.gpx_name_format = format.to_string();
+        options.validate()?;
+        self.dmut().parameters.user_steps_options = options;
+        Ok(())
     }
 
-    pub fn set_control_gpx_name_format(&mut self, format: &String) {
-        self.dmut().parameters.control_gpx_name_format = format.clone();
+    pub fn set_control_gpx_name_format(&mut self, format: &str) -> Result<(), Error> {
+        let mut parameters = self.get_parameters()?;
+        parameters.control_gpx_name_format = format.to_string();
+        parameters.validate()?;
+        self.dmut().parameters = parameters;
+        Ok(())
     }
 
-    pub fn setStartTime(&mut self, rfc3339: String) {
-        self.dmut().parameters.start_time = rfc3339;
+    // the setters below go through set_parameters for the validation.
+    pub fn setStartTime(&mut self, rfc3339: String) -> Result<(), Error> {
+        let mut parameters = self.get_parameters()?;
+        parameters.start_time = rfc3339;
+        self.set_parameters(&parameters)
     }
-    pub fn setSpeed(&mut self, s: f64) {
-        self.dmut().parameters.speed = s;
+    pub fn setSpeed(&mut self, s: f64) -> Result<(), Error> {
+        let mut parameters = self.get_parameters()?;
+        parameters.speed = s;
+        self.set_parameters(&parameters)
     }
-    pub fn setSegmentLength(&mut self, length: f64) {
-        self.dmut().parameters.segment_length = length;
+    pub fn setSegmentLength(&mut self, length: f64) -> Result<(), Error> {
+        let mut parameters = self.get_parameters()?;
+        parameters.segment_length = length;
+        self.set_parameters(&parameters)
     }
 
-    pub fn segments(&self) -> Vec<Segment> {
+    pub fn segments(&self) -> Result<Vec<Segment>, Error> {
+        self.check_loaded()?;
         let mut ret = Vec::new();
 
         let mut start = 0f64;
@@ -324,13 +796,14 @@ impl Backend {
                 start + self.d().parameters.segment_length - self.d().parameters.segment_overlap;
             k = k + 1;
         }
-        ret
+        Ok(ret)
     }
 
-    pub fn trackSegment(&self) -> Segment {
+    pub fn trackSegment(&self) -> Result<Segment, Error> {
+        self.check_loaded()?;
         let start = 0f64;
         let end = self.d().track.total_distance();
-        Segment { id: 0, start, end }
+        Ok(Segment { id: 0, start, end })
     }
 
     pub fn render_segment_what(
@@ -339,7 +812,8 @@ impl Backend {
         what: &String,
         size: &IntegerSize2D,
         kinds: Kinds,
-    ) -> String {
+    ) -> Result<String, Error> {
+        self.check_loaded()?;
         log::info!(
             "start - render_segment_what:{} {} size:{}x{}",
             segment.id,
@@ -354,32 +828,33 @@ impl Backend {
             "ylabels" => self.render_yaxis_labels_overlay(&segment, size),
             "wheel" => {
                 let time_parameters = wheel::model::TimeParameters {
-                    start: self.d().parameters.start_time.parse().unwrap(),
-                    speed: self.d().parameters.speed,
+                    start: self.d().parameters.start()?,
+                    timetable: data.timetable.clone(),
                     total_distance: self.d().track.total_distance(),
                 };
                 let mut model = wheel::model::WheelModel::new(&time_parameters);
                 model.add_points(&data, kinds);
+                model.add_daylight(&data);
                 wheel::render(size, &model)
             }
             "wheel/pages" => {
                 let time_parameters = wheel::model::TimeParameters {
-                    start: self.d().parameters.start_time.parse().unwrap(),
-                    speed: self.d().parameters.speed,
+                    start: self.d().parameters.start()?,
+                    timetable: data.timetable.clone(),
                     total_distance: self.d().track.total_distance(),
                 };
                 let mut model = wheel::model::WheelModel::new(&time_parameters);
                 model.add_points(&data, kinds);
-                model.add_pages(&self.segments());
+                model.add_daylight(&data);
+                model.add_pages(&self.segments()?);
                 wheel::render(size, &model)
             }
             _ => {
-                assert!(false);
-                String::new()
+                return Err(Error::UnknownRenderTarget { what: what.clone() });
             }
         };
         log::info!("done - render_segment_what:{} {}", segment.id, what);
-        ret
+        Ok(ret)
     }
 
     fn render_yaxis_labels_overlay(&mut self, segment: &Segment, size: &IntegerSize2D) -> String {
@@ -389,33 +864,41 @@ impl Backend {
             profile::ProfileView::init(&profile_bbox, size, &self.d().parameters.profile_options);
         profile.add_yaxis_labels_overlay();
         let ret = profile.render().svg;
-        if self.get_parameters().debug {
+        if self.d().parameters.debug {
             let filename = std::format!("/tmp/yaxis-{}.svg", segment.id);
             std::fs::write(filename, &ret).expect("Unable to write file");
         }
         ret
     }
 
-    pub fn segment_statistics(&self, segment: &Segment) -> SegmentStatistics {
+    pub fn segment_statistics(&self, segment: &Segment) -> Result<SegmentStatistics, Error> {
+        self.check_loaded()?;
         let range = self.d().track.subrange(segment.start, segment.end);
-        assert!(range.end > 0);
-        SegmentStatistics {
+        if range.is_empty() {
+            return Err(Error::DistanceOutOfTrack {
+                distance: segment.start,
+            });
+        }
+        Ok(SegmentStatistics {
             length: self.d().track.distance(range.end - 1) - self.d().track.distance(range.start),
             elevation_gain: self.d().track.elevation_gain_on_range(&range),
             distance_start: self.d().track.distance(range.start),
             distance_end: self.d().track.distance(range.end - 1),
-        }
+        })
     }
 
-    pub fn statistics(&self) -> SegmentStatistics {
+    pub fn statistics(&self) -> Result<SegmentStatistics, Error> {
+        self.check_loaded()?;
         let range = 0..self.d().track.len();
-        assert!(range.end > 0);
-        SegmentStatistics {
+        if range.is_empty() {
+            return Err(Error::GPXHasNoSegment);
+        }
+        Ok(SegmentStatistics {
             length: self.d().track.distance(range.end - 1) - self.d().track.distance(range.start),
             elevation_gain: self.d().track.elevation_gain_on_range(&range),
             distance_start: self.d().track.distance(range.start),
             distance_end: self.d().track.distance(range.end - 1),
-        }
+        })
     }
 }
 
@@ -423,10 +906,12 @@ impl Backend {
 mod tests {
     use crate::{
         backend::Backend,
+        dem,
+        error::Error,
         inputpoint::{self, InputType},
         math::IntegerSize2D,
         parameters::ProfileIndication,
-        wheel,
+        projectfile, wheel,
     };
     static START_TIME: &'static str = "1985-04-12T08:05:00.00Z";
 
@@ -439,16 +924,16 @@ mod tests {
             .await
             .expect("fail");
 
-        let mut parameters = backend.get_parameters();
+        let mut parameters = backend.get_parameters().unwrap();
         parameters.start_time = START_TIME.to_string();
         parameters.user_steps_options.step_distance = Some((10_000) as f64);
         parameters.profile_options.max_area_ratio = 0.1f64;
         parameters.profile_options.elevation_indicators =
             std::collections::HashSet::from([ProfileIndication::NumericSlope]);
 
-        backend.set_parameters(&parameters);
+        backend.set_parameters(&parameters).unwrap();
 
-        let fsegments = backend.segments();
+        let fsegments = backend.segments().unwrap();
         let segments: Vec<_> = fsegments
             .iter()
             .map(|f| backend.make_segment_data(&f))
@@ -485,24 +970,24 @@ mod tests {
             .load_filename("data/blackforest.gpx")
             .await
             .expect("fail");
-        let mut parameters = backend.get_parameters();
+        let mut parameters = backend.get_parameters().unwrap();
         parameters.start_time = START_TIME.to_string();
         parameters.user_steps_options.step_distance = Some((3_000) as f64);
         parameters.segment_length = 55000f64;
         parameters.segment_overlap = 5000f64;
-        backend.set_parameters(&parameters);
+        backend.set_parameters(&parameters).unwrap();
         let reffilename = std::format!("data/ref/segment-wheel.svg");
         let data = if std::fs::exists(&reffilename).unwrap() {
             std::fs::read_to_string(&reffilename).unwrap()
         } else {
             String::new()
         };
-        let segment = backend.trackSegment();
+        let segment = backend.trackSegment().unwrap();
         let sgdata = backend.make_segment_data(&segment);
-        let segments = backend.segments();
+        let segments = backend.segments().unwrap();
         let time_parameters = wheel::model::TimeParameters {
             start: parameters.start_time.parse().unwrap(),
-            speed: parameters.speed,
+            timetable: sgdata.timetable.clone(),
             total_distance: backend.d().track.total_distance(),
         };
         let mut model = wheel::model::WheelModel::new(&time_parameters);
@@ -523,13 +1008,13 @@ mod tests {
         let _ = env_logger::try_init();
         let mut backend = Backend::make();
         let _ = backend.load_demo().await;
-        let fseg = backend.trackSegment();
+        let fseg = backend.trackSegment().unwrap();
         let seg = backend.make_segment_data(&fseg);
         let controls = seg.points(&InputType::Control);
         let len = controls.len();
         assert!(len > 0);
         let kinds = std::collections::HashSet::from([InputType::Control]);
-        let waypoints = backend.get_waypoints(&fseg, kinds);
+        let waypoints = backend.get_waypoints(&fseg, kinds).unwrap();
         assert!(!waypoints.is_empty());
         for waypoint in waypoints {
             log::info!("gpx name={}", waypoint.info.unwrap().gpx_name);
@@ -544,13 +1029,13 @@ mod tests {
             .load_filename("data/blackforest.gpx")
             .await
             .expect("fail");
-        let mut parameters = backend.get_parameters();
+        let mut parameters = backend.get_parameters().unwrap();
         parameters.start_time = START_TIME.to_string();
         parameters.user_steps_options.step_distance = Some((10_000) as f64);
         parameters.map_options.max_area_ratio = 0.15f64;
-        backend.set_parameters(&parameters);
+        backend.set_parameters(&parameters).unwrap();
 
-        let fsegments = backend.segments();
+        let fsegments = backend.segments().unwrap();
         let segments: Vec<_> = fsegments
             .iter()
             .map(|f| backend.make_segment_data(&f))
@@ -581,4 +1066,335 @@ mod tests {
         }
         assert!(ok_count == segments.len());
     }
+
+    #[tokio::test]
+    async fn project_roundtrip() {
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let mut parameters = backend.get_parameters().unwrap();
+        parameters.start_time = START_TIME.to_string();
+        parameters.speed = 20f64 / 3.6;
+        parameters.user_steps_options.step_distance = Some(5_000f64);
+        backend.set_parameters(&parameters).unwrap();
+        let kinds =
+            inputpoint::Kinds::from([InputType::Control, InputType::UserStep, InputType::GPX]);
+        let segment = backend.trackSegment().unwrap();
+        let before = backend.get_waypoints(&segment, kinds.clone()).unwrap();
+        let project = backend.save_project().unwrap();
+
+        let mut copy = Backend::make();
+        copy.load_project(&project).await.expect("fail");
+        assert_eq!(copy.get_parameters().unwrap().speed, parameters.speed);
+        assert_eq!(
+            copy.get_parameters().unwrap().start_time,
+            parameters.start_time
+        );
+        let after = copy
+            .get_waypoints(&copy.trackSegment().unwrap(), kinds)
+            .unwrap();
+        assert_eq!(before.len(), after.len());
+        for (a, b) in before.iter().zip(after.iter()) {
+            let (a, b) = (a.info.as_ref().unwrap(), b.info.as_ref().unwrap());
+            assert_eq!(a.gpx_name, b.gpx_name);
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.track_index, b.track_index);
+        }
+        assert!(copy.load_project(b"garbage").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn project_part_order() {
+        let _ = env_logger::try_init();
+        let order: Vec<usize> = (0..6).collect();
+        let mut backend = Backend::make();
+        backend.set_part_order(Some(order.clone()));
+        backend
+            .load_filename("data/ref/karl-400.gpx")
+            .await
+            .expect("fail");
+        let project = backend.save_project().unwrap();
+
+        // the project's order is saved again, not used for the next files
+        let mut copy = Backend::make();
+        copy.load_project(&project).await.expect("fail");
+        let saved = projectfile::read(&copy.save_project().unwrap()).unwrap();
+        assert_eq!(saved.part_order, Some(order));
+        copy.load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let saved = projectfile::read(&copy.save_project().unwrap()).unwrap();
+        assert_eq!(saved.part_order, None);
+    }
+
+    struct FlatElevation;
+
+    impl dem::ElevationSource for FlatElevation {
+        fn elevation(&self, _lon: f64, _lat: f64) -> Option<f64> {
+            Some(1000f64)
+        }
+    }
+
+    #[tokio::test]
+    async fn project_other_dem() {
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        backend.set_elevation_source(None, dem::ElevationFill::Missing);
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let project = backend.save_project().unwrap();
+
+        // the projections follow the elevations of the loading session
+        let mut copy = Backend::make();
+        copy.set_elevation_source(
+            Some(std::sync::Arc::new(FlatElevation)),
+            dem::ElevationFill::All,
+        );
+        copy.load_project(&project).await.expect("fail");
+        let locked = copy.d().inputpoints.read().unwrap();
+        for kind in [InputType::Control, InputType::GPX] {
+            let points = locked.maps.get(&kind).unwrap().as_vector();
+            assert!(!points.is_empty());
+            for point in points {
+                for proj in &point.track_projections {
+                    assert_eq!(proj.elevation, 1000f64);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        assert!(matches!(backend.get_parameters(), Err(Error::NotLoaded)));
+        assert!(matches!(backend.statistics(), Err(Error::NotLoaded)));
+        assert!(matches!(
+            backend.load_filename("data/missing.gpx").await,
+            Err(Error::GPXNotFound)
+        ));
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let mut parameters = backend.get_parameters().unwrap();
+        parameters.segment_overlap = 2f64 * parameters.segment_length;
+        assert!(matches!(
+            backend.set_parameters(&parameters),
+            Err(Error::InvalidParameters { .. })
+        ));
+        assert!(backend.setStartTime("yesterday".to_string()).is_err());
+        assert!(backend.setSpeed(0f64).is_err());
+        // the rejected parameters are not applied
+        assert!(backend.get_parameters().unwrap().validate().is_ok());
+        backend.setSpeed(5f64).unwrap();
+        assert_eq!(backend.get_parameters().unwrap().speed, 5f64);
+        let segment = backend.trackSegment().unwrap();
+        let ret = backend.render_segment_what(
+            &segment,
+            &"teapot".to_string(),
+            &IntegerSize2D::new(100, 100),
+            inputpoint::allkinds(),
+        );
+        assert!(matches!(ret, Err(Error::UnknownRenderTarget { .. })));
+    }
+
+    #[tokio::test]
+    async fn waypoints_all_kinds() {
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        // the osm points far from the track are left out.
+        let segment = backend.trackSegment().unwrap();
+        let waypoints = backend
+            .get_waypoints(&segment, inputpoint::allkinds())
+            .unwrap();
+        assert!(!waypoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn edit_controls() {
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let count = backend.get_controls().unwrap().len();
+        let distance = |backend: &Backend, index: usize| -> f64 {
+            backend.get_controls().unwrap()[index]
+                .info
+                .as_ref()
+                .unwrap()
+                .distance
+        };
+        // start is the first control
+        backend
+            .add_control_at_distance(30_000f64, "Bakery")
+            .unwrap();
+        let controls = backend.get_controls().unwrap();
+        assert_eq!(controls.len(), count + 1);
+        let index = controls.iter().position(|w| w.name == "Bakery").unwrap();
+        assert!((distance(&backend, index) - 30_000f64).abs() < 100f64);
+        assert_eq!(
+            controls[index].info.as_ref().unwrap().origin,
+            InputType::Control
+        );
+
+        backend.rename_control(index, "Cafe").unwrap();
+        assert_eq!(backend.get_controls().unwrap()[index].name, "Cafe");
+
+        backend.move_control(index, 31_000f64).unwrap();
+        assert!((distance(&backend, index) - 31_000f64).abs() < 100f64);
+        assert_eq!(backend.get_controls().unwrap()[index].name, "Cafe");
+
+        // near a point of the track
+        let wgs = backend.d().track.wgs84[backend.d().track.len() / 2].clone();
+        backend.add_control_near(&wgs, "Middle").unwrap();
+        assert_eq!(backend.get_controls().unwrap().len(), count + 2);
+        let far = crate::wgs84point::WGS84Point::new(&0f64, &0f64, &0f64);
+        assert!(backend.add_control_near(&far, "Far").is_err());
+
+        backend.delete_control(index).unwrap();
+        assert_eq!(backend.get_controls().unwrap().len(), count + 1);
+        assert!(backend
+            .get_controls()
+            .unwrap()
+            .iter()
+            .all(|w| w.name != "Cafe"));
+        assert!(backend.delete_control(100).is_err());
+        assert!(backend.add_control_at_distance(-1f64, "Nowhere").is_err());
+    }
+
+    #[test]
+    fn controls_not_loaded() {
+        let mut backend = Backend::make();
+        let wgs = crate::wgs84point::WGS84Point::new(&0f64, &0f64, &0f64);
+        assert!(matches!(backend.get_controls(), Err(Error::NotLoaded)));
+        assert!(matches!(
+            backend.add_control_at_distance(1_000f64, "Bakery"),
+            Err(Error::NotLoaded)
+        ));
+        assert!(matches!(
+            backend.add_control_near(&wgs, "Bakery"),
+            Err(Error::NotLoaded)
+        ));
+        assert!(matches!(
+            backend.rename_control(0, "Cafe"),
+            Err(Error::NotLoaded)
+        ));
+        assert!(matches!(
+            backend.move_control(0, 1_000f64),
+            Err(Error::NotLoaded)
+        ));
+        assert!(matches!(backend.delete_control(0), Err(Error::NotLoaded)));
+    }
+
+    struct Recorder {
+        events: std::sync::Arc<std::sync::Mutex<Vec<super::Event>>>,
+    }
+
+    impl super::Sender for Recorder {
+        fn send(&mut self, event: &super::Event) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn load_events() {
+        use super::{Event, Stage};
+        let _ = env_logger::try_init();
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut backend = Backend::make();
+        backend.set_sink(Box::new(Recorder {
+            events: events.clone(),
+        }));
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events.first(),
+            Some(&Event::StageStarted { stage: Stage::Load })
+        );
+        assert_eq!(
+            events.last(),
+            Some(&Event::StageFinished { stage: Stage::Load })
+        );
+        for stage in [Stage::ReadTrack, Stage::OSM, Stage::Controls] {
+            let started = events.iter().position(|e| {
+                *e == Event::StageStarted {
+                    stage: stage.clone(),
+                }
+            });
+            let finished = events.iter().position(|e| {
+                *e == Event::StageFinished {
+                    stage: stage.clone(),
+                }
+            });
+            assert!(started.unwrap() < finished.unwrap());
+        }
+        let json = serde_json::to_string(&events[0]).unwrap();
+        assert_eq!(json, "{\"StageStarted\":{\"stage\":\"Load\"}}");
+    }
+
+    #[tokio::test]
+    async fn failed_load_events() {
+        use super::{Event, Stage};
+        let _ = env_logger::try_init();
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut backend = Backend::make();
+        backend.set_sink(Box::new(Recorder {
+            events: events.clone(),
+        }));
+        let content = b"<gpx></gpx>".to_vec();
+        let cancel = crate::cancel::CancelToken::new();
+        assert!(backend.load_content(&content, &cancel).await.is_err());
+        // the stages are finished in the reverse order
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            vec![
+                Event::StageStarted { stage: Stage::Load },
+                Event::StageStarted {
+                    stage: Stage::ReadTrack
+                },
+                Event::StageFinished {
+                    stage: Stage::ReadTrack
+                },
+                Event::StageFinished { stage: Stage::Load },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_load() {
+        use crate::cancel::CancelToken;
+        use crate::error::Error;
+        let _ = env_logger::try_init();
+        let mut backend = Backend::make();
+        let content = std::fs::read("data/blackforest.gpx").unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let result = backend.load_content(&content, &cancel).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(!backend.loaded());
+        backend
+            .load_content(&content, &CancelToken::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            backend.generatePdf(&cancel).await,
+            Err(Error::Cancelled)
+        ));
+    }
 }
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+
+use crate::mercator::DateTime;
+
+/*
+ * Control opening and closing times of brevets.
+ * https://www.audax-club-parisien.com (ACP rules for brevets)
+ * https://rusa.org/pages/acp-brevet-control-times-calculator
+ *
+ * The times depend on the distance of the control (km) with a maximum
+ * speed (opening) and a minimum speed (closing) per distance band.
+ * Within the first 60 km, the closing time is 1 hour + 20 km/h.
+ * Controls at or beyond the nominal distance close at the official
+ * time limit of the brevet.
+ * RUSA uses the ACP table. LRM (Les Randonneurs Mondiaux) extends it
+ * beyond 1200 km.
+ */
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum BrevetRules {
+    ACP,
+    RUSA,
+    LRM,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlTimes {
+    pub open: DateTime,
+    pub close: DateTime,
+}
+
+// (band end in km, max speed, min speed)
+const BANDS: [(f64, f64, f64); 5] = [
+    (200.0, 34.0, 15.0),
+    (400.0, 32.0, 15.0),
+    (600.0, 30.0, 15.0),
+    (1000.0, 28.0, 11.428),
+    (1300.0, 26.0, 13.333),
+];
+
+// beyond the last band
+const LRM_MAX_SPEED: f64 = 25.0;
+const LRM_MIN_SPEED: f64 = 12.0;
+
+// (nominal distance in km, time limit in hours)
+const ACP_LIMITS: [(f64, f64); 6] = [
+    (200.0, 13.5),
+    (300.0, 20.0),
+    (400.0, 27.0),
+    (600.0, 40.0),
+    (1000.0, 75.0),
+    (1200.0, 90.0),
+];
+
+const LRM_LIMITS: [(f64, f64); 5] = [
+    (1200.0, 90.0),
+    (1400.0, 116.0 + 40.0 / 60.0),
+    (1600.0, 133.0 + 20.0 / 60.0),
+    (1800.0, 150.0),
+    (2000.0, 166.0 + 40.0 / 60.0),
+];
+
+// distances up to 1% shorter than the nominal one are accepted (gps noise).
+const TOLERANCE: f64 = 0.01;
+
+impl BrevetRules {
+    fn limits(&self) -> Vec<(f64, f64)> {
+        match self {
+            BrevetRules::ACP | BrevetRules::RUSA => ACP_LIMITS.to_vec(),
+            BrevetRules::LRM => {
+                let mut ret = ACP_LIMITS[..ACP_LIMITS.len() - 1].to_vec();
+                ret.extend_from_slice(&LRM_LIMITS);
+                ret
+            }
+        }
+    }
+
+    fn limit(&self, nominal: f64) -> Option<f64> {
+        self.limits()
+            .iter()
+            .find(|(d, _)| (d - nominal).abs() < 1e-3)
+            .map(|(_, hours)| *hours)
+    }
+
+    // hours after the start
+    fn open_hours(&self, km: f64) -> f64 {
+        let mut ret = 0f64;
+        let mut begin = 0f64;
+        for (end, max_speed, _) in BANDS {
+            if km <= begin {
+                return ret;
+            }
+            ret += (km.min(end) - begin) / max_speed;
+            begin = end;
+        }
+        let last_speed = match self {
+            BrevetRules::LRM => LRM_MAX_SPEED,
+            _ => BANDS[BANDS.len() - 1].1,
+        };
+        ret + (km - begin).max(0f64) / last_speed
+    }
+
+    fn close_hours(&self, km: f64) -> f64 {
+        if km <= 60f64 {
+            return 1f64 + km / 20f64;
+        }
+        if *self == BrevetRules::LRM && km > 1200f64 {
+            let limits = LRM_LIMITS;
+            for k in 1..limits.len() {
+                let ((d0, h0), (d1, h1)) = (limits[k - 1], limits[k]);
+                if km <= d1 {
+                    return h0 + (km - d0) * (h1 - h0) / (d1 - d0);
+                }
+            }
+            let (d, h) = limits[limits.len() - 1];
+            return h + (km - d) / LRM_MIN_SPEED;
+        }
+        let mut ret = 0f64;
+        let mut begin = 0f64;
+        for (end, _, min_speed) in BANDS {
+            if km <= begin {
+                return ret;
+            }
+            ret += (km.min(end) - begin) / min_speed;
+            begin = end;
+        }
+        ret + (km - begin).max(0f64) / BANDS[BANDS.len() - 1].2
+    }
+
+    /*
+     * The largest standard distance not longer than the track (meters).
+     * Tracks shorter than the shortest brevet are their own nominal distance.
+     */
+    pub fn nominal_distance(&self, total_distance: f64) -> f64 {
+        let km = total_distance / 1000f64 * (1f64 + TOLERANCE);
+        let mut ret = None;
+        for (d, _) in self.limits() {
+            if d <= km {
+                ret = Some(d);
+            }
+        }
+        match ret {
+            Some(d) => 1000f64 * d,
+            None => total_distance,
+        }
+    }
+
+    // distance in meters
+    pub fn control_times(&self, distance: f64, nominal: f64, start: &DateTime) -> ControlTimes {
+        let km = distance.max(0f64).min(nominal) / 1000f64;
+        let nominal_km = nominal / 1000f64;
+        let open = self.open_hours(km);
+        let close = match (km >= nominal_km - 1e-3, self.limit(nominal_km)) {
+            (true, Some(limit)) => limit,
+            _ => self.close_hours(km),
+        };
+        ControlTimes {
+            open: *start + minutes(open),
+            close: *start + minutes(close),
+        }
+    }
+}
+
+// rounded to the nearest minute
+fn minutes(hours: f64) -> chrono::TimeDelta {
+    chrono::TimeDelta::minutes((60f64 * hours).round() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hm(start: &DateTime, t: &DateTime) -> String {
+        let minutes = t.signed_duration_since(start).num_minutes();
+        format!("{}:{:02}", minutes / 60, minutes % 60)
+    }
+
+    fn check(rules: &BrevetRules, km: f64, nominal: f64, open: &str, close: &str) {
+        let start: DateTime = "2024-04-06T06:00:00Z".parse().unwrap();
+        let times = rules.control_times(1000f64 * km, 1000f64 * nominal, &start);
+        assert_eq!(hm(&start, &times.open), open, "open at {} km", km);
+        assert_eq!(hm(&start, &times.close), close, "close at {} km", km);
+    }
+
+    #[test]
+    fn acp() {
+        let acp = BrevetRules::ACP;
+        check(&acp, 0.0, 200.0, "0:00", "1:00");
+        check(&acp, 50.0, 200.0, "1:28", "3:30");
+        check(&acp, 60.0, 200.0, "1:46", "4:00");
+        check(&acp, 120.0, 200.0, "3:32", "8:00");
+        check(&acp, 175.0, 200.0, "5:09", "11:40");
+        check(&acp, 205.0, 200.0, "5:53", "13:30");
+        check(&acp, 550.0, 600.0, "17:08", "36:40");
+        check(&acp, 890.0, 1000.0, "29:09", "65:23");
+    }
+
+    #[test]
+    fn nominal() {
+        let acp = BrevetRules::ACP;
+        assert_eq!(acp.nominal_distance(208_000.0), 200_000.0);
+        assert_eq!(acp.nominal_distance(399_000.0), 400_000.0);
+        assert_eq!(acp.nominal_distance(150_000.0), 150_000.0);
+        assert_eq!(acp.nominal_distance(1_450_000.0), 1_200_000.0);
+        let lrm = BrevetRules::LRM;
+        assert_eq!(lrm.nominal_distance(1_450_000.0), 1_400_000.0);
+        check(&lrm, 1400.0, 1400.0, "48:37", "116:40");
+        check(&lrm, 1300.0, 1400.0, "44:37", "103:20");
+    }
+}
@@ -0,0 +1,102 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::Error;
+
+/*
+ * A cancellation token is shared (cloned) between the caller and the
+ * running operation. The operation checks it between its steps and
+ * races its long waits (downloads, pdf compilation) against it.
+ */
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    // wakes the running waits
+    notify: tokio::sync::Notify,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    // completes when the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // registered before the check, such that a cancel() in between
+            // is not missed.
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    // runs the future until it completes or the token is cancelled.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, Error> {
+        use futures::future::{select, Either};
+        self.check()?;
+        let future = std::pin::pin!(future);
+        let cancelled = std::pin::pin!(self.cancelled());
+        match select(future, cancelled).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(Error::Cancelled),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub async fn sleep(duration: std::time::Duration) {
+    wasmtimer::tokio::sleep(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel() {
+        let token = CancelToken::new();
+        assert_eq!(token.run(async { 1 }).await.ok(), Some(1));
+        let copy = token.clone();
+        let never = futures::future::pending::<()>();
+        let cancel = async move {
+            sleep(std::time::Duration::from_millis(10)).await;
+            copy.cancel();
+        };
+        let (result, _) = futures::join!(token.run(never), cancel);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(matches!(token.check(), Err(Error::Cancelled)));
+        // already cancelled
+        token.cancelled().await;
+    }
+}
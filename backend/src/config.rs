@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use crate::{error::Error, parameters::Parameters};
+
+/*
+ * The parameters as JSON or TOML. The field names are the ones of the
+ * structs in parameters.rs, missing fields take their default value such
+ * that a file only needs the values that differ. The user configuration
+ * (WPX/parameters.toml in the configuration directory) holds the defaults
+ * for new tracks, both for the cli and the app.
+ */
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    JSON,
+    TOML,
+}
+
+impl Format {
+    // toml, unless the extension is .json
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Format::JSON,
+            _ => Format::TOML,
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::ConfigInvalid { message }
+}
+
+pub fn parse(content: &str, format: Format) -> Result<Parameters, Error> {
+    let parameters: Parameters = match format {
+        Format::JSON => serde_json::from_str(content).map_err(|e| invalid(e.to_string()))?,
+        Format::TOML => toml::from_str(content).map_err(|e| invalid(e.to_string()))?,
+    };
+    parameters.validate()?;
+    Ok(parameters)
+}
+
+pub fn write(parameters: &Parameters, format: Format) -> Result<String, Error> {
+    match format {
+        Format::JSON => {
+            serde_json::to_string_pretty(parameters).map_err(|e| invalid(e.to_string()))
+        }
+        Format::TOML => toml::to_string_pretty(parameters).map_err(|e| invalid(e.to_string())),
+    }
+}
+
+pub fn read(path: &Path) -> Result<Parameters, Error> {
+    let content = std::fs::read_to_string(path).map_err(|e| Error::IOError {
+        message: format!("could not read {}: {}", path.display(), e),
+    })?;
+    parse(&content, Format::from_path(path)).map_err(|e| match e {
+        Error::ConfigInvalid { message } => invalid(format!("{}: {}", path.display(), message)),
+        _ => e,
+    })
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("WPX").join("parameters.toml"))
+}
+
+// see osm::category
+pub fn user_categories_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("WPX").join("categories.toml"))
+}
+
+// None if there is no user configuration.
+pub fn read_user_config() -> Result<Option<Parameters>, Error> {
+    match user_config_path() {
+        Some(path) if path.exists() => {
+            log::info!("read parameters from {}", path.display());
+            Ok(Some(read(&path)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut parameters = Parameters::default();
+        parameters.speed = 5f64;
+        parameters.brevet_distance = Some(300_000f64);
+        parameters.user_steps_options.step_elevation_gain = Some(500f64);
+        for format in [Format::JSON, Format::TOML] {
+            let text = write(&parameters, format).unwrap();
+            let copy = parse(&text, format).unwrap();
+            assert_eq!(
+                serde_json::to_value(&copy).unwrap(),
+                serde_json::to_value(&parameters).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn partial() {
+        let text = "speed = 6.0\n[user_steps_options]\nstep_distance = 20000.0\n";
+        let parameters = parse(text, Format::TOML).unwrap();
+        assert_eq!(parameters.speed, 6f64);
+        assert_eq!(parameters.user_steps_options.step_distance, Some(20000f64));
+        assert_eq!(
+            parameters.segment_length,
+            Parameters::default().segment_length
+        );
+
+        let text = r#"{"speed": -1.0, "segment_overlap": 1e9, "start_time": "noon",
+            "control_gpx_name_format": "FOO[1]"}"#;
+        match parse(text, Format::JSON) {
+            Err(Error::InvalidParameters { problems }) => assert_eq!(problems.len(), 4),
+            _ => assert!(false),
+        }
+        assert!(matches!(
+            parse("speed = \"fast\"", Format::TOML),
+            Err(Error::ConfigInvalid { .. })
+        ));
+    }
+}
@@ -2,13 +2,15 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use crate::{
     backend::Segment,
+    error::Error,
     inputpoint::{InputPoint, InputType, OSMType, SharedPointMaps},
     locate, math,
-    mercator::MercatorPoint,
+    mercator::{MercatorPoint, WebMercatorProjection},
     parameters::Parameters,
     segment::SegmentData,
     track::Track,
     track_projection::is_close_to_track,
+    wgs84point::WGS84Point,
     wheel::shorten::shorten_name,
 };
 use rstar::{RTree, AABB};
@@ -130,19 +132,74 @@ pub fn make_controls_with_waypoints(track: &Track, gpxpoints: &Vec<InputPoint>)
     ret
 }
 
+/*
+ * Controls edited by the user. They are placed on the track, like the
+ * inferred ones, and identified by their rank along the track.
+ */
+
+// max distance (m) between a point given by the user and the track.
+const MAX_USER_CONTROL_DISTANCE: f64 = 300f64;
+
+pub fn sort_controls(controls: &mut [InputPoint]) {
+    controls.sort_by(|a, b| {
+        let da = a
+            .track_projections
+            .first()
+            .map(|p| p.distance_on_track_to_projection);
+        let db = b
+            .track_projections
+            .first()
+            .map(|p| p.distance_on_track_to_projection);
+        da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+    });
+}
+
+pub fn control_at_distance(
+    track: &Track,
+    distance: f64,
+    name: &str,
+    description: &str,
+) -> Result<InputPoint, Error> {
+    if !(0f64..=track.total_distance()).contains(&distance) {
+        return Err(Error::DistanceOutOfTrack { distance });
+    }
+    let index = track.index_before(distance);
+    Ok(InputPoint::create_control_on_track(
+        track,
+        index,
+        name,
+        description,
+    ))
+}
+
+pub fn control_near(track: &Track, wgs84: &WGS84Point, name: &str) -> Result<InputPoint, Error> {
+    let euclidean = WebMercatorProjection::make().project(wgs84);
+    let point = InputPoint::from_wgs84(wgs84, &euclidean, InputType::Control);
+    let projection = locate::compute_track_projection(track, &track.tree, &point);
+    if projection.track_distance > MAX_USER_CONTROL_DISTANCE {
+        return Err(Error::ControlNotOnTrack);
+    }
+    Ok(InputPoint::create_control_on_track(
+        track,
+        projection.track_index,
+        name,
+        "",
+    ))
+}
+
 fn control_point_goodness(point: &InputPoint) -> i32 {
     match point.kind() {
-        InputType::UserStep => {
+        InputType::UserStep | InputType::Sun => {
             return i32::MIN;
         }
         InputType::GPX | InputType::Control => {
             return i32::MAX;
         }
         InputType::OSM => {
-            let min_population = match point.osmkind().unwrap() {
-                OSMType::City => 10000,
-                OSMType::Village => 1000,
-                OSMType::Hamlet => 100,
+            let min_population = match point.osmkind() {
+                Some(OSMType::City) => 10000,
+                Some(OSMType::Village) => 1000,
+                Some(OSMType::Hamlet) => 100,
                 _ => 0,
             };
             let population = point.population().unwrap_or(min_population);
@@ -343,7 +400,15 @@ mod tests {
         let b: event::SenderHandler = Box::new(event::ConsoleEventSender {});
         let logger = std::sync::RwLock::new(Some(b));
         let mut inputpoints = BTreeMap::new();
-        let mut osmpoints = osm::download_for_track(&track, &logger).await.unwrap();
+        let mut osmpoints = osm::download_for_track(
+            &track,
+            &osm::OverpassConfig::default(),
+            &osm::CacheConfig::default(),
+            &crate::cancel::CancelToken::new(),
+            &logger,
+        )
+        .await
+        .unwrap();
         let trees = ProjectionTrees::make(&track);
         trees.iter_on(&mut osmpoints, &track);
         inputpoints.insert(InputType::OSM, osmpoints);
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/*
+ * Elevation from a digital elevation model (DEM).
+ * Used to fill the elevation of track points before smoothing,
+ * for GPX files exported without <ele> (Komoot, Brouter, ...).
+ */
+
+pub trait ElevationSource {
+    // None if the source has no data for this position.
+    fn elevation(&self, lon: f64, lat: f64) -> Option<f64>;
+}
+
+pub type SharedElevationSource = Arc<dyn ElevationSource + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElevationFill {
+    // only the points without <ele>
+    Missing,
+    // replace all elevations (GPS elevation is often bad)
+    All,
+}
+
+// SRTM voids
+const VOID: i16 = -32768;
+
+struct HgtTile {
+    // 1201 (SRTM3) or 3601 (SRTM1)
+    size: usize,
+    data: Vec<i16>,
+}
+
+impl HgtTile {
+    fn from_bytes(bytes: &[u8]) -> Option<HgtTile> {
+        let n = bytes.len() / 2;
+        let size = (n as f64).sqrt().round() as usize;
+        if size < 2 || bytes.len() != 2 * size * size {
+            return None;
+        }
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        Some(HgtTile { size, data })
+    }
+
+    fn get(&self, row: usize, col: usize) -> Option<f64> {
+        let v = self.data[row * self.size + col];
+        if v == VOID {
+            return None;
+        }
+        Some(v as f64)
+    }
+
+    // (x,y) in [0,1]x[0,1] relative to the south-west corner of the tile.
+    fn interpolate(&self, x: f64, y: f64) -> Option<f64> {
+        let last = (self.size - 1) as f64;
+        // row 0 is the northern edge.
+        let fcol = (x * last).clamp(0.0, last);
+        let frow = ((1.0 - y) * last).clamp(0.0, last);
+        let c0 = fcol.floor() as usize;
+        let r0 = frow.floor() as usize;
+        let c1 = (c0 + 1).min(self.size - 1);
+        let r1 = (r0 + 1).min(self.size - 1);
+        let dx = fcol - c0 as f64;
+        let dy = frow - r0 as f64;
+        let corners = [
+            (self.get(r0, c0), (1.0 - dx) * (1.0 - dy)),
+            (self.get(r0, c1), dx * (1.0 - dy)),
+            (self.get(r1, c0), (1.0 - dx) * dy),
+            (self.get(r1, c1), dx * dy),
+        ];
+        // ignore voids, weighting the remaining corners.
+        let mut acc = 0f64;
+        let mut weights = 0f64;
+        for (value, weight) in corners {
+            if let Some(v) = value {
+                acc += v * weight;
+                weights += weight;
+            }
+        }
+        if weights <= 0f64 {
+            return None;
+        }
+        Some(acc / weights)
+    }
+}
+
+fn hgt_basename(ilon: i32, ilat: i32) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if ilat >= 0 { "N" } else { "S" },
+        ilat.abs(),
+        if ilon >= 0 { "E" } else { "W" },
+        ilon.abs()
+    )
+}
+
+// None is stored for tiles that could not be read (not tried again).
+type TileCache = BTreeMap<(i32, i32), Option<Arc<HgtTile>>>;
+
+// A directory of SRTM .hgt files (N48E008.hgt, ...).
+// Tiles are read on first use and kept in memory.
+pub struct HgtDirectory {
+    directory: std::path::PathBuf,
+    tiles: Mutex<TileCache>,
+}
+
+impl HgtDirectory {
+    pub fn new(directory: &std::path::Path) -> HgtDirectory {
+        HgtDirectory {
+            directory: directory.to_path_buf(),
+            tiles: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn load(&self, ilon: i32, ilat: i32) -> Option<Arc<HgtTile>> {
+        let path = self.directory.join(hgt_basename(ilon, ilat));
+        match std::fs::read(&path) {
+            Ok(bytes) => match HgtTile::from_bytes(&bytes) {
+                Some(tile) => {
+                    log::info!("loaded DEM tile {:?}", path);
+                    Some(Arc::new(tile))
+                }
+                None => {
+                    log::error!("invalid DEM tile {:?} ({} bytes)", path, bytes.len());
+                    None
+                }
+            },
+            Err(e) => {
+                log::info!("no DEM tile {:?} because {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    fn tile(&self, ilon: i32, ilat: i32) -> Option<Arc<HgtTile>> {
+        let mut tiles = self.tiles.lock().unwrap();
+        tiles
+            .entry((ilon, ilat))
+            .or_insert_with(|| self.load(ilon, ilat))
+            .clone()
+    }
+}
+
+impl ElevationSource for HgtDirectory {
+    fn elevation(&self, lon: f64, lat: f64) -> Option<f64> {
+        let ilon = lon.floor() as i32;
+        let ilat = lat.floor() as i32;
+        let tile = self.tile(ilon, ilat)?;
+        tile.interpolate(lon - ilon as f64, lat - ilat as f64)
+    }
+}
+
+// next to the OSM cache.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_directory() -> Option<std::path::PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("WPX").join("hgt"))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn default_directory() -> Option<std::path::PathBuf> {
+    None
+}
+
+pub fn default_source() -> Option<SharedElevationSource> {
+    let directory = default_directory()?;
+    Some(Arc::new(HgtDirectory::new(&directory)))
+}
+
+// returns the number of points that got their elevation from the source.
+pub fn fill(
+    gpxtracks: &mut [gpx::Track],
+    source: &dyn ElevationSource,
+    mode: &ElevationFill,
+) -> usize {
+    let mut count = 0;
+    for track in gpxtracks {
+        for segment in &mut track.segments {
+            for point in &mut segment.points {
+                if *mode == ElevationFill::Missing && point.elevation.is_some() {
+                    continue;
+                }
+                let (lon, lat) = point.point().x_y();
+                if let Some(e) = source.elevation(lon, lat) {
+                    point.elevation = Some(e);
+                    count += 1;
+                }
+            }
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SRTM3 tile with elevation = row + col
+    fn write_tile(dir: &std::path::Path, ilon: i32, ilat: i32) {
+        let size = 1201usize;
+        let mut bytes = Vec::with_capacity(2 * size * size);
+        for row in 0..size {
+            for col in 0..size {
+                let v = (row + col) as i16;
+                bytes.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        std::fs::write(dir.join(hgt_basename(ilon, ilat)), bytes).unwrap();
+    }
+
+    #[test]
+    fn basename() {
+        assert_eq!(hgt_basename(8, 48), "N48E008.hgt");
+        assert_eq!(hgt_basename(-1, -12), "S12W001.hgt");
+    }
+
+    #[test]
+    fn hgt_interpolation() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tile(dir.path(), 8, 48);
+        let source = HgtDirectory::new(dir.path());
+        // north-west corner
+        assert_eq!(source.elevation(8.0, 48.999999).unwrap().round(), 0.0);
+        // south-west corner
+        assert_eq!(source.elevation(8.0, 48.0).unwrap(), 1200.0);
+        // halfway between two samples
+        let step = 1.0 / 1200.0;
+        let e = source.elevation(8.0 + 0.5 * step, 48.0).unwrap();
+        assert!((e - 1200.5).abs() < 1e-6);
+        assert!(source.elevation(9.5, 48.5).is_none());
+    }
+
+    #[test]
+    fn fill_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tile(dir.path(), 8, 48);
+        let source = HgtDirectory::new(dir.path());
+        let mut segment = gpx::TrackSegment::new();
+        let mut p1 = gpx::Waypoint::new(geo::Point::new(8.5, 48.5));
+        p1.elevation = Some(10.0);
+        segment.points.push(p1);
+        segment
+            .points
+            .push(gpx::Waypoint::new(geo::Point::new(8.5, 48.5)));
+        let mut track = gpx::Track::new();
+        track.segments.push(segment);
+        let mut tracks = vec![track];
+
+        assert_eq!(fill(&mut tracks, &source, &ElevationFill::Missing), 1);
+        let points = &tracks[0].segments[0].points;
+        assert_eq!(points[0].elevation, Some(10.0));
+        assert_eq!(points[1].elevation, Some(1200.0));
+
+        assert_eq!(fill(&mut tracks, &source, &ElevationFill::All), 2);
+        assert_eq!(tracks[0].segments[0].points[0].elevation, Some(1200.0));
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(non_snake_case)]
 
+use serde::{Deserialize, Serialize};
+
 /*
  * converted to rust from gpxstudio:
  * https://github.com/gpxstudio/gpx.studio/blob/main/gpx/src/gpx.ts#L1945
@@ -33,16 +35,189 @@ pub fn smooth(
     ret
 }
 
-pub fn elevation_gain(smooth: &Vec<f64>, from: usize, to: usize) -> f64 {
-    debug_assert!(from <= to);
-    let mut ret = 0f64;
-    for k in from..to {
-        if k == 0 {
-            continue;
+// the maximal distance between two resampled points, in meters.
+const RESAMPLE_STEP: f64 = 10f64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Smoothing {
+    // average of the points within smooth_width (see smooth())
+    MovingAverage,
+    // quadratic least squares fit of the points within smooth_width
+    SavitzkyGolay,
+    // moving average of the elevation resampled at regular distances, so
+    // that dense parts of the track (e.g. pauses) have no extra weight.
+    Resampled,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Gain {
+    // the sum of the positive differences
+    Sum,
+    // climbs are counted once they exceed the threshold (meters)
+    Hysteresis { threshold: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ElevationModel {
+    pub smoothing: Smoothing,
+    // meters, on each side of the point
+    pub width: f64,
+    pub gain: Gain,
+}
+
+impl Default for ElevationModel {
+    fn default() -> ElevationModel {
+        ElevationModel {
+            smoothing: Smoothing::MovingAverage,
+            width: 200f64,
+            gain: Gain::Sum,
         }
-        let d = smooth[k] - smooth[k - 1];
-        if d > 0f64 {
-            ret += d;
+    }
+}
+
+// the points within W of each point, as index ranges.
+fn windows(W: f64, distance: &[f64]) -> Vec<std::ops::Range<usize>> {
+    let L = distance.len();
+    let mut ret = Vec::with_capacity(L);
+    let mut start = 0usize;
+    let mut end = 0usize;
+    for i in 0..L {
+        while distance[i] - distance[start] > W {
+            start += 1;
+        }
+        while end < L && distance[end] - distance[i] <= W {
+            end += 1;
+        }
+        ret.push(start..end);
+    }
+    ret
+}
+
+pub fn savitzky_golay(W: f64, distance: &[f64], elevation: &[f64]) -> Vec<f64> {
+    if W <= 0f64 {
+        return elevation.to_vec();
+    }
+    windows(W, distance)
+        .into_iter()
+        .enumerate()
+        .map(|(i, window)| {
+            // y = a + b*x + c*x^2 with x = (distance - distance[i]) / W, the
+            // value at the point is a.
+            let mut S = [0f64; 5];
+            let mut T = [0f64; 3];
+            for j in window.clone() {
+                let x = (distance[j] - distance[i]) / W;
+                let y = elevation[j];
+                let mut p = 1f64;
+                for k in 0..5 {
+                    if k < 3 {
+                        T[k] += p * y;
+                    }
+                    S[k] += p;
+                    p *= x;
+                }
+            }
+            let M = [[S[0], S[1], S[2]], [S[1], S[2], S[3]], [S[2], S[3], S[4]]];
+            let det = |m: &[[f64; 3]; 3]| {
+                m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+                    - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+                    + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+            };
+            let d = det(&M);
+            // too few distinct points for a parabola: the average.
+            if window.len() < 3 || d.abs() < 1e-9 * S[0].powi(3) {
+                return T[0] / S[0];
+            }
+            // Cramer's rule for a.
+            let mut A = M;
+            for r in 0..3 {
+                A[r][0] = T[r];
+            }
+            det(&A) / d
+        })
+        .collect()
+}
+
+// linear interpolation of (x, y) at t, x sorted.
+fn interpolate(x: &[f64], y: &[f64], t: f64) -> f64 {
+    let k = x.partition_point(|v| *v < t);
+    if k == 0 {
+        return y[0];
+    }
+    if k == x.len() {
+        return y[x.len() - 1];
+    }
+    let (x0, x1) = (x[k - 1], x[k]);
+    match x1 - x0 {
+        0f64 => y[k],
+        dx => y[k - 1] + (y[k] - y[k - 1]) * (t - x0) / dx,
+    }
+}
+
+pub fn resampled(W: f64, distance: &[f64], elevation: &[f64]) -> Vec<f64> {
+    let total = distance.last().copied().unwrap_or(0f64);
+    let n = (total / RESAMPLE_STEP).ceil() as usize + 1;
+    if n < 2 {
+        return elevation.to_vec();
+    }
+    let step = total / (n - 1) as f64;
+    let grid: Vec<f64> = (0..n).map(|k| k as f64 * step).collect();
+    let values: Vec<f64> = grid
+        .iter()
+        .map(|t| interpolate(distance, elevation, *t))
+        .collect();
+    let smooth_values = smooth(W, n, |k| grid[k], |k| values[k]);
+    distance
+        .iter()
+        .map(|t| interpolate(&grid, &smooth_values, *t))
+        .collect()
+}
+
+pub fn smooth_with(model: &ElevationModel, distance: &[f64], elevation: &[f64]) -> Vec<f64> {
+    match model.smoothing {
+        Smoothing::MovingAverage => smooth(
+            model.width,
+            distance.len(),
+            |k| distance[k],
+            |k| elevation[k],
+        ),
+        Smoothing::SavitzkyGolay => savitzky_golay(model.width, distance, elevation),
+        Smoothing::Resampled => resampled(model.width, distance, elevation),
+    }
+}
+
+/*
+ * The elevation gain from the start to each point.
+ * With a threshold, a climb is counted once it rises `threshold` above the
+ * last low, and ends after a descent of `threshold` from the last high:
+ * smaller dips and bumps are noise.
+ */
+pub fn cumulative_gain(smooth: &[f64], gain: &Gain) -> Vec<f64> {
+    let threshold = match gain {
+        Gain::Sum => 0f64,
+        Gain::Hysteresis { threshold } => *threshold,
+    };
+    let mut ret = vec![0f64; smooth.len()];
+    // the last low, or the last high while climbing.
+    let mut reference = smooth.first().copied().unwrap_or(0f64);
+    let mut climbing = false;
+    for k in 1..smooth.len() {
+        let e = smooth[k];
+        ret[k] = ret[k - 1];
+        if climbing {
+            if e > reference {
+                ret[k] += e - reference;
+                reference = e;
+            } else if reference - e >= threshold {
+                climbing = false;
+                reference = e;
+            }
+        } else if e < reference {
+            reference = e;
+        } else if e - reference >= threshold {
+            ret[k] += e - reference;
+            reference = e;
+            climbing = true;
         }
     }
     ret
@@ -59,10 +234,10 @@ mod tests {
             .load_filename("data/blackforest.gpx")
             .await
             .expect("fail");
-        let S = backend.segments();
+        let S = backend.segments().unwrap();
         let km = 1000f64;
         for s in &S {
-            let stat = backend.segment_statistics(s);
+            let stat = backend.segment_statistics(s).unwrap();
             log::info!(
                 "{0} {1:8.1} -> {2:8.1}:  {3:8.1}",
                 s.id,
@@ -73,4 +248,63 @@ mod tests {
         }
         assert_eq!(S.len(), 3);
     }
+
+    #[test]
+    fn gain() {
+        use super::*;
+        let elevation = [100f64, 103f64, 101f64, 104f64, 110f64, 108f64, 112f64];
+        let sum = cumulative_gain(&elevation, &Gain::Sum);
+        assert_eq!(sum, vec![0f64, 3f64, 3f64, 6f64, 12f64, 12f64, 16f64]);
+        let hysteresis = cumulative_gain(&elevation, &Gain::Hysteresis { threshold: 5f64 });
+        assert_eq!(
+            hysteresis,
+            vec![0f64, 0f64, 0f64, 0f64, 10f64, 10f64, 12f64]
+        );
+        let noisy = [100f64, 105f64, 104f64, 109f64];
+        let hysteresis = cumulative_gain(&noisy, &Gain::Hysteresis { threshold: 5f64 });
+        assert_eq!(hysteresis, vec![0f64, 5f64, 5f64, 9f64]);
+        // a descent of the threshold ends the climb
+        let hills = [100f64, 110f64, 104f64, 108f64, 98f64, 104f64];
+        let hysteresis = cumulative_gain(&hills, &Gain::Hysteresis { threshold: 5f64 });
+        assert_eq!(hysteresis, vec![0f64, 10f64, 10f64, 10f64, 10f64, 16f64]);
+    }
+
+    #[tokio::test]
+    async fn models() {
+        use super::*;
+        let mut backend = backend::Backend::make();
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let default = backend.statistics().unwrap().elevation_gain;
+        let elevation: Vec<_> = backend.d().track.wgs84.iter().map(|w| w.z()).collect();
+        let raw = *cumulative_gain(&elevation, &Gain::Sum).last().unwrap();
+        let mut gain = |smoothing: &Smoothing, width: f64, gain: Gain| {
+            let mut parameters = backend.get_parameters().unwrap();
+            parameters.elevation_smoothing = smoothing.clone();
+            parameters.smooth_width = width;
+            parameters.elevation_gain = gain;
+            backend.set_parameters(&parameters).unwrap();
+            backend.statistics().unwrap().elevation_gain
+        };
+        assert_eq!(gain(&Smoothing::MovingAverage, 200f64, Gain::Sum), default);
+        for smoothing in [
+            Smoothing::MovingAverage,
+            Smoothing::SavitzkyGolay,
+            Smoothing::Resampled,
+        ] {
+            let mut previous = raw;
+            for width in [50f64, 200f64, 500f64] {
+                let sum = gain(&smoothing, width, Gain::Sum);
+                let hysteresis = gain(&smoothing, width, Gain::Hysteresis { threshold: 5f64 });
+                log::info!("{:?} {} m: {:.1} {:.1}", smoothing, width, sum, hysteresis);
+                assert!(sum <= previous);
+                assert!(hysteresis < sum);
+                // the total climb is about 3.5 km
+                assert!(3000f64 < hysteresis && sum < 7000f64);
+                previous = sum;
+            }
+        }
+    }
 }
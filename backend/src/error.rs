@@ -6,6 +6,30 @@ pub enum Error {
     GPXInvalid,
     GPXHasNoSegment,
     MissingElevation { index: usize },
+    InvalidPartOrder { parts: usize },
+    UnknownFormat,
+    KMLInvalid,
+    GeoJSONInvalid,
+    TCXInvalid,
+    FITInvalid,
+    NoTrack { format: String },
+    ProjectInvalid,
+    ProjectVersion { version: u32 },
+    ControlNotFound { index: usize },
+    ControlNotOnTrack,
+    DistanceOutOfTrack { distance: f64 },
+    PBFInvalid,
+    OSMDownload,
+    Cancelled,
+    NotLoaded,
+    InvalidParameter { name: String, reason: String },
+    InvalidParameters { problems: Vec<String> },
+    InvalidArgument { argument: String, reason: String },
+    ConfigInvalid { message: String },
+    UnknownRenderTarget { what: String },
+    IOError { message: String },
+    PdfCompile { message: String },
+    TemplateInvalid { problems: Vec<String> },
 }
 
 impl fmt::Display for Error {
@@ -17,6 +41,48 @@ impl fmt::Display for Error {
                 write!(f, "{}", format!("missing elevation at index {}", index))
             }
             Error::GPXHasNoSegment => write!(f, "GPX file has no segment"),
+            Error::InvalidPartOrder { parts } => {
+                write!(f, "part order is not a permutation of the {} parts", parts)
+            }
+            Error::UnknownFormat => write!(f, "unknown file format"),
+            Error::KMLInvalid => write!(f, "KML file is invalid"),
+            Error::GeoJSONInvalid => write!(f, "GeoJSON file is invalid"),
+            Error::TCXInvalid => write!(f, "TCX file is invalid"),
+            Error::FITInvalid => write!(f, "FIT file is invalid"),
+            Error::NoTrack { format } => write!(f, "{} file has no track", format),
+            Error::ProjectInvalid => write!(f, "project file is invalid"),
+            Error::ProjectVersion { version } => {
+                write!(f, "project file version {} is not supported", version)
+            }
+            Error::ControlNotFound { index } => write!(f, "there is no control {}", index),
+            Error::ControlNotOnTrack => write!(f, "the control is too far from the track"),
+            Error::DistanceOutOfTrack { distance } => {
+                write!(
+                    f,
+                    "distance {:.1} km is not on the track",
+                    distance / 1000f64
+                )
+            }
+            Error::PBFInvalid => write!(f, "OSM PBF file is invalid"),
+            Error::OSMDownload => write!(f, "could not download the OSM data"),
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::NotLoaded => write!(f, "no track is loaded"),
+            Error::InvalidParameter { name, reason } => {
+                write!(f, "invalid parameter {}: {}", name, reason)
+            }
+            Error::InvalidParameters { problems } => {
+                write!(f, "invalid parameters: {}", problems.join(", "))
+            }
+            Error::InvalidArgument { argument, reason } => {
+                write!(f, "invalid argument '{}': {}", argument, reason)
+            }
+            Error::ConfigInvalid { message } => write!(f, "invalid configuration: {}", message),
+            Error::UnknownRenderTarget { what } => write!(f, "cannot render '{}'", what),
+            Error::IOError { message } => write!(f, "{}", message),
+            Error::PdfCompile { message } => write!(f, "could not compile the pdf: {}", message),
+            Error::TemplateInvalid { problems } => {
+                write!(f, "invalid templates: {}", problems.join(", "))
+            }
         }
     }
 }
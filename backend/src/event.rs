@@ -1,30 +1,78 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/*
+ * The events sent to the app while loading a track and generating the
+ * documents. Each stage sends StageStarted and StageFinished (also when
+ * it fails), with progress and download events in between. Warnings do
+ * not stop the stage (errors are returned as results).
+ */
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Stage {
+    // the whole load, the other load stages are in between.
+    Load,
+    ReadTrack,
+    OSM,
+    Controls,
+    Pdf,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Event {
+    StageStarted { stage: Stage },
+    StageFinished { stage: Stage },
+    // fraction of the stage done, between 0 and 1
+    Progress { stage: Stage, fraction: f64 },
+    BytesDownloaded { bytes: u64 },
+    TilesFetched { done: usize, total: usize },
+    Warning { message: String },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::StageStarted { stage } => write!(f, "start {:?}", stage),
+            Event::StageFinished { stage } => write!(f, "finished {:?}", stage),
+            Event::Progress { stage, fraction } => {
+                write!(f, "{:?} {:.0}%", stage, 100f64 * fraction)
+            }
+            Event::BytesDownloaded { bytes } => write!(f, "downloaded {} bytes", bytes),
+            Event::TilesFetched { done, total } => write!(f, "tiles {}/{}", done, total),
+            Event::Warning { message } => write!(f, "warning: {}", message),
+        }
+    }
+}
+
 pub trait Sender {
-    fn send(&mut self, data: &String);
+    fn send(&mut self, event: &Event);
 }
 
 pub type SenderHandler = Box<dyn Sender + Send + Sync>;
 pub type SenderHandlerLock = std::sync::RwLock<Option<SenderHandler>>;
 
 #[cfg(not(target_arch = "wasm32"))]
-pub async fn send_worker(handler: &SenderHandlerLock, data: &String) {
+pub async fn send_worker(handler: &SenderHandlerLock, event: &Event) {
     match handler.write() {
         Ok(mut lock) => match lock.as_mut() {
             Some(sender) => {
-                sender.send(&data);
+                sender.send(event);
             }
             None => {
-                log::info!("no sender for message: {}", data);
+                log::info!("no sender for event: {}", event);
             }
         },
         Err(_) => {
-            log::info!("write lock error for message: {}", data);
+            log::info!("write lock error for event: {}", event);
         }
     }
 }
 
 #[cfg(target_arch = "wasm32")]
-pub async fn send_worker(handler: &SenderHandlerLock, data: &String) {
-    let _ = handler.write().unwrap().as_mut().unwrap().send(&data);
+pub async fn send_worker(handler: &SenderHandlerLock, event: &Event) {
+    let _ = handler.write().unwrap().as_mut().unwrap().send(event);
     let tick = std::time::Duration::from_millis(0);
     let _ = wasmtimer::tokio::sleep(tick).await;
 }
@@ -34,7 +82,7 @@ pub async fn send_worker(handler: &SenderHandlerLock, data: &String) {
 pub struct ConsoleEventSender {}
 
 impl Sender for ConsoleEventSender {
-    fn send(&mut self, data: &String) {
-        println!("EVENT: {}", data);
+    fn send(&mut self, event: &Event) {
+        println!("EVENT: {}", event);
     }
 }
@@ -1,5 +1,4 @@
-use crate::speed;
-use crate::{inputpoint::InputType, parameters::Parameters};
+use crate::{inputpoint::InputType, mercator::DateTime, parameters::Parameters};
 
 // a bit messy, mostly AI generated
 #[allow(dead_code)]
@@ -9,12 +8,24 @@ pub struct WaypointInfoData {
     pub inter_distance: f64,
     pub inter_elevation_gain: f64,
     pub inter_slope: f64,
+    // from the start, and to the end of the track (meters)
+    pub elevation_gain: f64,
+    pub remaining_distance: f64,
+    pub remaining_elevation_gain: f64,
+    // meters, None without a control after this point
+    pub next_control: Option<f64>,
     pub name: String,
     pub description: String,
     pub origin: InputType,
+    pub time: DateTime,
+    // seconds since the start
+    pub elapsed: f64,
+    pub open: Option<DateTime>,
+    pub close: Option<DateTime>,
 }
 
-fn format_slope(slope_ratio: f64, specifier: &str) -> String {
+// W.P[%], e.g. "4.1" or "4.1%": width, precision (default 1), percent sign.
+fn format_number(value: f64, specifier: &str) -> String {
     let has_percent = specifier.ends_with('%');
     let specifier_cleaned = specifier.trim_end_matches('%');
 
@@ -27,14 +38,11 @@ fn format_slope(slope_ratio: f64, specifier: &str) -> String {
     let width = width_str.parse::<usize>().unwrap_or(0);
     let precision = precision_str.parse::<usize>().unwrap_or(1);
 
-    // Slope is a ratio (e.g., 0.101). Convert to percent grade (e.g., 10.1)
-    let slope_grade = slope_ratio * 100.0;
-
     // Create the formatting string using the standard `format!` syntax:
     // {:>width.precision f} ensures padding and decimal places are applied.
     let formatted_value = format!(
         "{:>width$.precision$}",
-        slope_grade,
+        value,
         width = width,
         precision = precision
     );
@@ -47,10 +55,165 @@ fn format_slope(slope_ratio: f64, specifier: &str) -> String {
     }
 }
 
-pub fn make_gpx_name(data: &WaypointInfoData, parameters: &Parameters) -> String {
+fn format_slope(slope_ratio: f64, specifier: &str) -> String {
+    // Slope is a ratio (e.g., 0.101). Convert to percent grade (e.g., 10.1)
+    format_number(slope_ratio * 100.0, specifier)
+}
+
+// %H (hours, may exceed 24), %M, %S and %%; negative durations get a "-".
+fn format_duration(seconds: f64, specifier: &str) -> String {
+    let total = seconds.abs() as u64;
+    let mut ret = String::new();
+    if seconds < 0f64 && total > 0 {
+        ret.push('-');
+    }
+    let mut chars = specifier.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('H') => ret.push_str(&format!("{:02}", total / 3600)),
+            Some('M') => ret.push_str(&format!("{:02}", (total % 3600) / 60)),
+            Some('S') => ret.push_str(&format!("{:02}", total % 60)),
+            Some('%') => ret.push('%'),
+            _ => {}
+        }
+    }
+    ret
+}
+
+fn valid_duration(specifier: &str) -> bool {
+    let mut chars = specifier.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && !matches!(chars.next(), Some('H' | 'M' | 'S' | '%')) {
+            return false;
+        }
+    }
+    true
+}
+
+const TIMES: [&str; 3] = ["TIME", "OPEN", "CLOSE"];
+const DURATIONS: [&str; 2] = ["ELAPSED", "BUFFER"];
+// W.P[%], like SLOPE; distances in km, elevations in m.
+const NUMBERS: [&str; 9] = [
+    "SLOPE",
+    "DISTANCE",
+    "ELEVATION",
+    "LEG",
+    "LEGGAIN",
+    "GAIN",
+    "REMAINING",
+    "REMAININGGAIN",
+    "NEXTCONTROL",
+];
+const TEXTS: [&str; 2] = ["NAME", "DESCRIPTION"];
+
+fn placeholders() -> Vec<&'static str> {
+    let mut ret: Vec<_> = TIMES
+        .iter()
+        .chain(DURATIONS.iter())
+        .chain(NUMBERS.iter())
+        .chain(TEXTS.iter())
+        .copied()
+        .collect();
+    // the longest first, so that LEGGAIN is not read as LEG.
+    ret.sort_by_key(|p| std::cmp::Reverse(p.len()));
+    ret
+}
+
+// the problems of a format: unknown placeholders and invalid time, duration
+// and number formats. Text outside of the WORD[...] placeholders is kept as is.
+pub fn check_format(format: &str) -> Vec<String> {
+    use chrono::format::{Item, StrftimeItems};
+    use regex::Regex;
+    let placeholder_regex: Regex = Regex::new(r"([A-Z]+)\[([^\]]*)\]").unwrap();
+    let number_regex: Regex = Regex::new(r"^\d*(\.\d*)?%?$").unwrap();
+    let mut ret = Vec::new();
+    for cap in placeholder_regex.captures_iter(format) {
+        let (field_type, specifier) = (&cap[1], &cap[2]);
+        if !placeholders().contains(&field_type) {
+            ret.push(format!("unknown placeholder {}", &cap[0]));
+            continue;
+        }
+        let time = TIMES.contains(&field_type);
+        if time && StrftimeItems::new(specifier).any(|item| item == Item::Error) {
+            ret.push(format!("invalid time format {}", &cap[0]));
+        }
+        if DURATIONS.contains(&field_type) && !valid_duration(specifier) {
+            ret.push(format!("invalid duration format {}", &cap[0]));
+        }
+        if NUMBERS.contains(&field_type) && !number_regex.is_match(specifier) {
+            ret.push(format!("invalid number format {}", &cap[0]));
+        }
+    }
+    ret
+}
+
+fn format_field(field_type: &str, specifier: &str, data: &WaypointInfoData) -> String {
+    let km = |meters: f64| format_number(meters / 1000f64, specifier);
+    match field_type {
+        // The specifier is a Chrono format string (e.g., "%H:%M")
+        "TIME" => data.time.format(specifier).to_string(),
+        // brevet control times, empty if not a control.
+        "OPEN" => match data.open {
+            Some(t) => t.format(specifier).to_string(),
+            None => String::new(),
+        },
+        "CLOSE" => match data.close {
+            Some(t) => t.format(specifier).to_string(),
+            None => String::new(),
+        },
+        "ELAPSED" => format_duration(data.elapsed, specifier),
+        // time left before the control closes (negative when arriving too late).
+        "BUFFER" => match data.close {
+            Some(t) => format_duration((t - data.time).num_seconds() as f64, specifier),
+            None => String::new(),
+        },
+        // The specifier is a custom W.P[%] string (e.g., "4.1" or "4.1%")
+        "SLOPE" => format_slope(data.inter_slope, specifier),
+        "DISTANCE" => km(data.distance),
+        "ELEVATION" => format_number(data.elevation, specifier),
+        "LEG" => km(data.inter_distance),
+        "LEGGAIN" => format_number(data.inter_elevation_gain, specifier),
+        "GAIN" => format_number(data.elevation_gain, specifier),
+        "REMAINING" => km(data.remaining_distance),
+        "REMAININGGAIN" => format_number(data.remaining_elevation_gain, specifier),
+        "NEXTCONTROL" => match data.next_control {
+            Some(d) => km(d),
+            None => String::new(),
+        },
+        // the width is in characters (names are not ascii only).
+        "NAME" => {
+            let width = specifier.parse::<usize>().unwrap_or(usize::MAX);
+            data.name.chars().take(width).collect()
+        }
+        "DESCRIPTION" => {
+            let width = specifier.parse::<usize>().unwrap_or(usize::MAX);
+            data.description.chars().take(width).collect()
+        }
+        _ => {
+            // Should not happen based on the regex
+            String::new()
+        }
+    }
+}
+
+// the format with its placeholders replaced by the values of the point.
+pub fn format_waypoint(format: &str, data: &WaypointInfoData) -> String {
     use regex::Regex;
     let format_regex: Regex =
-        Regex::new(r"(TIME|SLOPE|NAME|DESCRIPTION)(?:\[([^\]]+)\])?").unwrap();
+        Regex::new(&format!(r"({})(?:\[([^\]]*)\])?", placeholders().join("|"))).unwrap();
+    format_regex
+        .replace_all(format, |cap: &regex::Captures| {
+            let specifier = cap.get(2).map_or("", |m| m.as_str());
+            format_field(&cap[1], specifier, data)
+        })
+        .to_string()
+}
+
+pub fn make_gpx_name(data: &WaypointInfoData, parameters: &Parameters) -> String {
     let format = match data.origin {
         InputType::UserStep => parameters.user_steps_options.gpx_name_format.clone(),
         InputType::Control => parameters.control_gpx_name_format.clone(),
@@ -59,58 +222,13 @@ pub fn make_gpx_name(data: &WaypointInfoData, parameters: &Parameters) -> String
     if format.is_empty() {
         return data.name.clone();
     }
-    let mut result = format.to_string();
-    let original_format = format.to_string(); // Keep original for iterating
-    let time = speed::time_at_distance(&data.distance, parameters);
-
-    //log::trace!("format={}", format);
-
-    // Iterate over all matched placeholders in the format string
-    for cap in format_regex.captures_iter(&original_format) {
-        let field_type = &cap[1];
-        let placeholder = &cap[0];
-        let specifier = &cap[2];
-
-        // This variable will hold the formatted string for substitution
-        let formatted_value = match field_type {
-            "TIME" => {
-                // The specifier is a Chrono format string (e.g., "%H:%M")
-                // NOTE: Using "%H:%M" corresponds to the example TIME[HH:MM]
-                time.format(specifier).to_string()
-            }
-            "SLOPE" => {
-                // The specifier is a custom W.P[%] string (e.g., "4.1" or "4.1%")
-                format_slope(data.inter_slope, specifier)
-            }
-            "NAME" => {
-                let mut copy = data.name.clone();
-                let width = specifier.parse::<usize>().unwrap_or(usize::MAX);
-                copy.truncate(width);
-                copy
-            }
-            "DESCRIPTION" => {
-                let mut copy = data.description.clone();
-                let width = specifier.parse::<usize>().unwrap_or(usize::MAX);
-                copy.truncate(width);
-                copy
-            }
-            _ => {
-                // Should not happen based on the regex
-                String::new()
-            }
-        };
-
-        // Replace the placeholder. We use replacen(..., 1) to replace only the current match,
-        // avoiding issues if the same field appears multiple times.
-        result = result.replacen(placeholder, &formatted_value, 1);
-    }
-
-    result
+    format_waypoint(&format, data)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::speed;
 
     // Helper function to create a test WaypointInfoData
     fn setup_test_data(slope: f64) -> WaypointInfoData {
@@ -120,9 +238,17 @@ mod tests {
             inter_distance: 50.0,
             inter_slope: slope,
             inter_elevation_gain: 50.0,
+            elevation_gain: 800.0,
+            remaining_distance: 185000.0,
+            remaining_elevation_gain: 1200.0,
+            next_control: None,
             name: "P2".to_string(),
             description: "description".to_string(),
             origin: InputType::UserStep,
+            time: "1985-04-12T09:00:00Z".parse().unwrap(),
+            elapsed: 3600.0,
+            open: None,
+            close: None,
         }
     }
 
@@ -147,6 +273,17 @@ mod tests {
         assert_eq!(make_gpx_name(&data, &parameters(&format2)), "09:00:00");
     }
 
+    #[test]
+    fn test_control_times_formatting() {
+        let mut data = setup_test_data(0.0);
+        let format = "NAME[2] OPEN[%H:%M]-CLOSE[%H:%M]";
+        // not a control: no control times
+        assert_eq!(make_gpx_name(&data, &parameters(&format)), "P2 -");
+        data.open = Some("1985-04-12T08:26:00Z".parse().unwrap());
+        data.close = Some("1985-04-12T09:00:00Z".parse().unwrap());
+        assert_eq!(make_gpx_name(&data, &parameters(&format)), "P2 08:26-09:00");
+    }
+
     #[test]
     fn test_name_formatting() {
         // Time is 12:32:30 UTC
@@ -155,6 +292,13 @@ mod tests {
         // Example 1: TIME[HH:MM] -> "12:32" (Using chrono's "%H:%M")
         let format1 = "NAME[2]-TIME[%H:%M]";
         assert_eq!(make_gpx_name(&data, &parameters(&format1)), "P2-09:00");
+
+        // a width within a multibyte character
+        let mut data = setup_test_data(0.0);
+        data.name = "Höllental".to_string();
+        data.description = "Café".to_string();
+        let format2 = "NAME[2] DESCRIPTION[4]";
+        assert_eq!(make_gpx_name(&data, &parameters(&format2)), "Hö Café");
     }
 
     #[test]
@@ -227,4 +371,33 @@ mod tests {
             "T:09-S:  5.0%"
         );
     }
+
+    #[test]
+    fn test_table_fields() {
+        let mut data = setup_test_data(0.0);
+        let format = "DISTANCE[.0] LEG[.1] GAIN[.0] LEGGAIN[.0] REMAINING[.0] REMAININGGAIN[.0]";
+        assert_eq!(format_waypoint(format, &data), "15 0.1 800 50 185 1200");
+        assert_eq!(format_waypoint("ELAPSED[%H:%M]", &data), "01:00");
+        assert_eq!(format_waypoint("NEXTCONTROL[.1]|BUFFER[%H:%M]", &data), "|");
+        data.next_control = Some(12345.0);
+        data.close = Some("1985-04-12T08:30:00Z".parse().unwrap());
+        assert_eq!(
+            format_waypoint("NEXTCONTROL[.1]|BUFFER[%H:%M]", &data),
+            "12.3|-00:30"
+        );
+        assert_eq!(format_waypoint("ELEVATION[5.0] m", &data), "   10 m");
+    }
+
+    #[test]
+    fn test_check_format() {
+        assert!(check_format("NAME[*]-TIME[%H:%M]-SLOPE[4.1%]").is_empty());
+        assert!(check_format("OPEN[%H:%M]/CLOSE[%H:%M]").is_empty());
+        assert!(check_format("REMAINING[4.0] km ELAPSED[%H:%M] BUFFER[]").is_empty());
+        let problems = check_format("NAME[3]-SPEED[2]-TIME[%Q]");
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].contains("SPEED[2]"));
+        assert!(problems[1].contains("TIME[%Q]"));
+        let problems = check_format("GAIN[x] ELAPSED[%d]");
+        assert_eq!(problems.len(), 2);
+    }
 }
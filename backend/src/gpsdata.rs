@@ -1,7 +1,9 @@
 use crate::bbox::BoundingBox;
 use crate::error::Error;
+use crate::import;
 use crate::inputpoint::{InputPoint, InputPointMap};
 use crate::math::Point2D;
+use crate::part_order::{self, PartOrder, PartOrdering};
 use crate::wgs84point::WGS84Point;
 use crate::{mercator, track};
 use geo::Distance;
@@ -12,14 +14,6 @@ pub fn distance_wgs84(p1: &WGS84Point, p2: &WGS84Point) -> f64 {
     geo::Haversine::distance(p1, p2)
 }
 
-fn read_gpx_content(bytes: &Vec<u8>) -> Result<gpx::Gpx, Error> {
-    let reader_mem = std::io::Cursor::new(bytes);
-    match gpx::read(reader_mem) {
-        Ok(d) => Ok(d),
-        Err(_e) => Err(Error::GPXInvalid),
-    }
-}
-
 fn make_track_from_segment(segment: &gpx::TrackSegment, name: String) -> gpx::Track {
     let mut ret = gpx::Track::new();
     ret.segments.push(segment.clone());
@@ -39,29 +33,13 @@ fn make_track_from_route(route: &gpx::Route, name: String) -> gpx::Track {
     return ret;
 }
 
-fn read_routes(gpx: &mut gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
-    let routes = &mut gpx.routes;
-    routes.sort_by_key(|route| {
-        let zero = "A".to_string();
-        let infinity = "ziel".to_string();
-        if route.name.is_none() {
-            return zero;
-        }
-        let name = route.name.as_ref().unwrap().to_lowercase();
-        if name.contains("end") {
-            return infinity;
-        }
-        if name.contains("ziel") {
-            return infinity;
-        }
-        if name.contains("start") {
-            return zero;
-        }
-        return name;
-    });
+fn read_routes(gpx: &gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
     let mut ret: Vec<gpx::Track> = Vec::new();
-    for route in routes {
-        ret.push(make_track_from_route(route, "foo".to_string()));
+    for route in &gpx.routes {
+        ret.push(make_track_from_route(
+            route,
+            route.name.clone().unwrap_or("foo".to_string()),
+        ));
     }
     if ret.is_empty() {
         return Err(Error::GPXHasNoSegment);
@@ -69,28 +47,9 @@ fn read_routes(gpx: &mut gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
     Ok(ret)
 }
 
-fn read_tracks(gpx: &mut gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
-    let tracks = &mut gpx.tracks;
-    tracks.sort_by_key(|track| {
-        let zero = "A".to_string();
-        let infinity = "ziel".to_string();
-        if track.name.is_none() {
-            return zero;
-        }
-        let name = track.name.as_ref().unwrap().to_lowercase();
-        if name.contains("end") {
-            return infinity;
-        }
-        if name.contains("ziel") {
-            return infinity;
-        }
-        if name.contains("start") {
-            return zero;
-        }
-        return name;
-    });
+fn read_tracks(gpx: &gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
     let mut ret: Vec<gpx::Track> = Vec::new();
-    for track in tracks {
+    for track in &gpx.tracks {
         for segment in &track.segments {
             ret.push(make_track_from_segment(
                 segment,
@@ -107,28 +66,29 @@ fn read_tracks(gpx: &mut gpx::Gpx) -> Result<Vec<gpx::Track>, Error> {
 pub struct GpxData {
     pub waypoints: InputPointMap,
     pub tracks: Vec<gpx::Track>,
+    pub part_order: PartOrder,
 }
 
 pub fn read_content(content: &Vec<u8>) -> Result<GpxData, Error> {
-    let mut gpx = read_gpx_content(content)?;
+    read_content_with_order(content, &PartOrdering::Geometry)
+}
+
+// the parts (tracks/segments, or routes) are returned in the order given by `ordering`.
+pub fn read_content_with_order(content: &[u8], ordering: &PartOrdering) -> Result<GpxData, Error> {
+    let gpx = import::read(content)?;
     let tracks = if gpx.tracks.is_empty() {
-        match read_routes(&mut gpx) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(e);
-            }
-        }
+        read_routes(&gpx)?
     } else {
-        match read_tracks(&mut gpx) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(e);
-            }
-        }
+        read_tracks(&gpx)?
+    };
+    let part_order = match ordering {
+        PartOrdering::Geometry => part_order::order_by_geometry(&tracks),
+        PartOrdering::Explicit(order) => part_order::order_explicit(&tracks, order)?,
     };
     Ok(GpxData {
-        tracks,
+        tracks: part_order::apply(tracks, &part_order),
         waypoints: read_waypoints(&gpx),
+        part_order,
     })
 }
 
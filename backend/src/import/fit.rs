@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+
+use super::{empty_gpx, make_track, make_waypoint};
+use crate::error::Error;
+
+/*
+ * Minimal reader for Garmin FIT course (and activity) files.
+ * Only the messages we need are decoded:
+ *  - record (20): the track,
+ *  - course_point (32): the waypoints,
+ *  - course (31): the name.
+ * https://developer.garmin.com/fit/protocol/
+ */
+
+const RECORD: u16 = 20;
+const COURSE: u16 = 31;
+const COURSE_POINT: u16 = 32;
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+fn crc(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in bytes {
+        let mut tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+        tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize];
+    }
+    crc
+}
+
+pub fn is_fit(content: &[u8]) -> bool {
+    content.len() >= 12 && (content[0] == 12 || content[0] == 14) && &content[8..12] == b".FIT"
+}
+
+struct Definition {
+    big_endian: bool,
+    global: u16,
+    // (field number, size)
+    fields: Vec<(u8, usize)>,
+    developer_size: usize,
+}
+
+struct Message {
+    big_endian: bool,
+    fields: BTreeMap<u8, Vec<u8>>,
+}
+
+impl Message {
+    fn uint(&self, field: u8) -> Option<u64> {
+        let bytes = self.fields.get(&field)?;
+        if bytes.is_empty() || bytes.len() > 8 {
+            return None;
+        }
+        let mut ret = 0u64;
+        for k in 0..bytes.len() {
+            let b = match self.big_endian {
+                true => bytes[k],
+                false => bytes[bytes.len() - 1 - k],
+            };
+            ret = (ret << 8) | b as u64;
+        }
+        // all bits set means invalid
+        let invalid = u64::MAX >> (64 - 8 * bytes.len());
+        if ret == invalid {
+            return None;
+        }
+        Some(ret)
+    }
+
+    fn sint32(&self, field: u8) -> Option<i32> {
+        let bytes = self.fields.get(&field)?;
+        if bytes.len() != 4 {
+            return None;
+        }
+        let array = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        let ret = match self.big_endian {
+            true => i32::from_be_bytes(array),
+            false => i32::from_le_bytes(array),
+        };
+        if ret == i32::MAX {
+            return None;
+        }
+        Some(ret)
+    }
+
+    fn string(&self, field: u8) -> Option<String> {
+        let bytes = self.fields.get(&field)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        let ret = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+        if ret.is_empty() {
+            return None;
+        }
+        Some(ret)
+    }
+
+    // (lon, lat) in degrees
+    fn position(&self, lat_field: u8, lon_field: u8) -> Option<(f64, f64)> {
+        let semicircles = 180f64 / 2f64.powi(31);
+        let lat = self.sint32(lat_field)? as f64 * semicircles;
+        let lon = self.sint32(lon_field)? as f64 * semicircles;
+        Some((lon, lat))
+    }
+
+    fn altitude(&self) -> Option<f64> {
+        // enhanced_altitude, then altitude (both scale 5, offset 500)
+        let raw = self.uint(78).or_else(|| self.uint(2))?;
+        Some(raw as f64 / 5f64 - 500f64)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        if self.pos + n > self.data.len() {
+            return Err(Error::FITInvalid);
+        }
+        let ret = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(ret)
+    }
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+    fn done(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+fn read_definition(reader: &mut Reader, developer: bool) -> Result<Definition, Error> {
+    let _reserved = reader.u8()?;
+    let big_endian = reader.u8()? == 1;
+    let g = reader.take(2)?;
+    let global = match big_endian {
+        true => u16::from_be_bytes([g[0], g[1]]),
+        false => u16::from_le_bytes([g[0], g[1]]),
+    };
+    let n = reader.u8()? as usize;
+    let mut fields = Vec::new();
+    for _ in 0..n {
+        let f = reader.take(3)?;
+        fields.push((f[0], f[1] as usize));
+    }
+    let mut developer_size = 0;
+    if developer {
+        let n = reader.u8()? as usize;
+        for _ in 0..n {
+            developer_size += reader.take(3)?[1] as usize;
+        }
+    }
+    Ok(Definition {
+        big_endian,
+        global,
+        fields,
+        developer_size,
+    })
+}
+
+fn read_messages(data: &[u8]) -> Result<Vec<(u16, Message)>, Error> {
+    let mut definitions: BTreeMap<u8, Definition> = BTreeMap::new();
+    let mut reader = Reader { data, pos: 0 };
+    let mut ret = Vec::new();
+    while !reader.done() {
+        let header = reader.u8()?;
+        let local = if header & 0x80 != 0 {
+            // compressed timestamp header
+            (header >> 5) & 0x03
+        } else if header & 0x40 != 0 {
+            let definition = read_definition(&mut reader, header & 0x20 != 0)?;
+            definitions.insert(header & 0x0F, definition);
+            continue;
+        } else {
+            header & 0x0F
+        };
+        let definition = match definitions.get(&local) {
+            Some(d) => d,
+            None => {
+                log::error!("FIT: no definition for local message {}", local);
+                return Err(Error::FITInvalid);
+            }
+        };
+        let mut fields = BTreeMap::new();
+        for (number, size) in &definition.fields {
+            fields.insert(*number, reader.take(*size)?.to_vec());
+        }
+        reader.take(definition.developer_size)?;
+        let message = Message {
+            big_endian: definition.big_endian,
+            fields,
+        };
+        ret.push((definition.global, message));
+    }
+    Ok(ret)
+}
+
+pub fn read(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    if !is_fit(content) {
+        return Err(Error::FITInvalid);
+    }
+    let header_size = content[0] as usize;
+    let data_size = u32::from_le_bytes([content[4], content[5], content[6], content[7]]) as usize;
+    let end = header_size + data_size;
+    if content.len() < end + 2 {
+        return Err(Error::FITInvalid);
+    }
+    let expected = u16::from_le_bytes([content[end], content[end + 1]]);
+    if crc(&content[..end]) != expected {
+        log::error!("FIT: bad CRC");
+        return Err(Error::FITInvalid);
+    }
+
+    let mut ret = empty_gpx();
+    let mut name = None;
+    let mut points = Vec::new();
+    for (global, message) in read_messages(&content[header_size..end])? {
+        match global {
+            RECORD => {
+                if let Some((lon, lat)) = message.position(0, 1) {
+                    points.push(make_waypoint(lon, lat, message.altitude()));
+                }
+            }
+            COURSE => {
+                name = message.string(5);
+            }
+            COURSE_POINT => {
+                if let Some((lon, lat)) = message.position(2, 3) {
+                    let mut w = make_waypoint(lon, lat, None);
+                    w.name = message.string(6);
+                    ret.waypoints.push(w);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !points.is_empty() {
+        ret.tracks.push(make_track(name, vec![points]));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semicircles(degrees: f64) -> [u8; 4] {
+        ((degrees * 2f64.powi(31) / 180f64).round() as i32).to_le_bytes()
+    }
+
+    fn make_fit() -> Vec<u8> {
+        let mut data = Vec::new();
+        // definition: local 0 = record (lat, lon, altitude)
+        data.extend_from_slice(&[0x40, 0, 0, 20, 0, 3, 0, 4, 0x85, 1, 4, 0x85, 2, 2, 0x84]);
+        for (lon, lat, ele) in [(8.0, 48.0, 100.0), (8.1, 48.1, 110.0)] {
+            data.push(0x00);
+            data.extend_from_slice(&semicircles(lat));
+            data.extend_from_slice(&semicircles(lon));
+            data.extend_from_slice(&(((ele + 500.0) * 5.0) as u16).to_le_bytes());
+        }
+        // record without position (invalid lat/lon)
+        data.push(0x00);
+        data.extend_from_slice(&i32::MAX.to_le_bytes());
+        data.extend_from_slice(&i32::MAX.to_le_bytes());
+        data.extend_from_slice(&0xFFFFu16.to_le_bytes());
+        // definition: local 1 = course (name)
+        data.extend_from_slice(&[0x41, 0, 0, 31, 0, 1, 5, 8, 0x07]);
+        data.push(0x01);
+        data.extend_from_slice(b"BRM 200\0");
+        // definition: local 2 = course_point (lat, lon, name)
+        data.extend_from_slice(&[0x42, 0, 0, 32, 0, 3, 2, 4, 0x85, 3, 4, 0x85, 6, 4, 0x07]);
+        data.push(0x02);
+        data.extend_from_slice(&semicircles(48.1));
+        data.extend_from_slice(&semicircles(8.1));
+        data.extend_from_slice(b"K1\0\0");
+
+        let mut ret = vec![14u8, 0x20, 0, 0];
+        ret.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        ret.extend_from_slice(b".FIT");
+        let header_crc = crc(&ret);
+        ret.extend_from_slice(&header_crc.to_le_bytes());
+        ret.extend_from_slice(&data);
+        let file_crc = crc(&ret);
+        ret.extend_from_slice(&file_crc.to_le_bytes());
+        ret
+    }
+
+    #[test]
+    fn fit() {
+        let content = make_fit();
+        assert!(is_fit(&content));
+        let gpx = read(&content).unwrap();
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_ref().unwrap(), "BRM 200");
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 2);
+        let (lon, lat) = points[1].point().x_y();
+        assert!((lon - 8.1).abs() < 1e-6 && (lat - 48.1).abs() < 1e-6);
+        assert!((points[1].elevation.unwrap() - 110.0).abs() < 1e-6);
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_ref().unwrap(), "K1");
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut content = make_fit();
+        let n = content.len();
+        content[n - 1] ^= 0xFF;
+        assert!(matches!(read(&content), Err(Error::FITInvalid)));
+    }
+}
@@ -0,0 +1,158 @@
+use serde_json::Value;
+
+use super::{empty_gpx, make_track, make_waypoint};
+use crate::error::Error;
+
+/*
+ * GeoJSON: LineString and MultiLineString geometries are tracks,
+ * Point geometries are waypoints (name and description from the properties).
+ */
+
+fn read_position(value: &Value) -> Result<gpx::Waypoint, Error> {
+    let array = value.as_array().ok_or(Error::GeoJSONInvalid)?;
+    let values: Vec<f64> = array.iter().filter_map(|v| v.as_f64()).collect();
+    if values.len() < 2 || values.len() != array.len() {
+        return Err(Error::GeoJSONInvalid);
+    }
+    Ok(make_waypoint(values[0], values[1], values.get(2).copied()))
+}
+
+fn read_line(value: &Value) -> Result<Vec<gpx::Waypoint>, Error> {
+    let array = value.as_array().ok_or(Error::GeoJSONInvalid)?;
+    array.iter().map(read_position).collect()
+}
+
+fn property(properties: Option<&Value>, names: &[&str]) -> Option<String> {
+    let map = properties?.as_object()?;
+    for name in names {
+        if let Some(text) = map.get(*name).and_then(|v| v.as_str()) {
+            return Some(text.to_string());
+        }
+    }
+    None
+}
+
+fn read_geometry(
+    geometry: &Value,
+    properties: Option<&Value>,
+    gpx: &mut gpx::Gpx,
+) -> Result<(), Error> {
+    let kind = geometry
+        .get("type")
+        .and_then(|t| t.as_str())
+        .ok_or(Error::GeoJSONInvalid)?;
+    let name = property(properties, &["name", "title"]);
+    match kind {
+        "LineString" => {
+            let coordinates = geometry.get("coordinates").ok_or(Error::GeoJSONInvalid)?;
+            gpx.tracks
+                .push(make_track(name, vec![read_line(coordinates)?]));
+        }
+        "MultiLineString" => {
+            let coordinates = geometry
+                .get("coordinates")
+                .and_then(|c| c.as_array())
+                .ok_or(Error::GeoJSONInvalid)?;
+            let lines: Result<Vec<_>, _> = coordinates.iter().map(read_line).collect();
+            gpx.tracks.push(make_track(name, lines?));
+        }
+        "Point" => {
+            let coordinates = geometry.get("coordinates").ok_or(Error::GeoJSONInvalid)?;
+            let mut w = read_position(coordinates)?;
+            w.elevation = None;
+            w.name = name;
+            w.description = property(properties, &["description", "desc"]);
+            gpx.waypoints.push(w);
+        }
+        "GeometryCollection" => {
+            let geometries = geometry
+                .get("geometries")
+                .and_then(|g| g.as_array())
+                .ok_or(Error::GeoJSONInvalid)?;
+            for g in geometries {
+                read_geometry(g, properties, gpx)?;
+            }
+        }
+        _ => {
+            log::info!("ignore GeoJSON geometry {}", kind);
+        }
+    }
+    Ok(())
+}
+
+fn read_object(object: &Value, gpx: &mut gpx::Gpx) -> Result<(), Error> {
+    let kind = object
+        .get("type")
+        .and_then(|t| t.as_str())
+        .ok_or(Error::GeoJSONInvalid)?;
+    match kind {
+        "FeatureCollection" => {
+            let features = object
+                .get("features")
+                .and_then(|f| f.as_array())
+                .ok_or(Error::GeoJSONInvalid)?;
+            for feature in features {
+                read_object(feature, gpx)?;
+            }
+        }
+        "Feature" => {
+            match object.get("geometry") {
+                Some(Value::Null) | None => {}
+                Some(geometry) => {
+                    read_geometry(geometry, object.get("properties"), gpx)?;
+                }
+            };
+        }
+        _ => {
+            read_geometry(object, None, gpx)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    let json: Value = serde_json::from_slice(content).map_err(|e| {
+        log::error!("cannot parse GeoJSON: {}", e);
+        Error::GeoJSONInvalid
+    })?;
+    let mut ret = empty_gpx();
+    read_object(&json, &mut ret)?;
+    ret.tracks.retain(|t| !t.segments.is_empty());
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geojson() {
+        let data = r#"{
+          "type": "FeatureCollection",
+          "features": [
+            {"type": "Feature", "properties": {"name": "Day 1"},
+             "geometry": {"type": "LineString", "coordinates": [[8.0, 48.0, 100.0], [8.1, 48.1]]}},
+            {"type": "Feature", "properties": {"name": "Day 2"},
+             "geometry": {"type": "MultiLineString", "coordinates": [[[8.1, 48.1], [8.2, 48.2]], [[8.2, 48.2], [8.3, 48.3]]]}},
+            {"type": "Feature", "properties": {"name": "K1", "description": "Bakery"},
+             "geometry": {"type": "Point", "coordinates": [8.1, 48.1]}},
+            {"type": "Feature", "properties": {}, "geometry": null}
+          ]
+        }"#;
+        let gpx = read(data.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 2);
+        assert_eq!(gpx.tracks[0].name.as_ref().unwrap(), "Day 1");
+        assert_eq!(gpx.tracks[0].segments[0].points[0].elevation, Some(100.0));
+        assert_eq!(gpx.tracks[0].segments[0].points[1].elevation, None);
+        assert_eq!(gpx.tracks[1].segments.len(), 2);
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].description.as_ref().unwrap(), "Bakery");
+    }
+
+    #[test]
+    fn invalid() {
+        let data = r#"{"type": "LineString", "coordinates": [[8.0, "48.0"]]}"#;
+        assert!(matches!(read(data.as_bytes()), Err(Error::GeoJSONInvalid)));
+        assert!(matches!(read(b"{"), Err(Error::GeoJSONInvalid)));
+    }
+}
@@ -0,0 +1,161 @@
+use roxmltree::Node;
+
+use super::{child_text, empty_gpx, make_track, make_waypoint};
+use crate::error::Error;
+
+/*
+ * KML: Placemarks with a LineString (or a gx:Track, or several of them
+ * in a MultiGeometry) are tracks, Placemarks with a Point are waypoints.
+ */
+
+// "lon,lat[,ele] lon,lat[,ele] ..."
+fn read_coordinates(text: &str) -> Result<Vec<gpx::Waypoint>, Error> {
+    let mut ret = Vec::new();
+    for tuple in text.split_whitespace() {
+        let values: Vec<f64> = match tuple.split(',').map(|v| v.parse::<f64>()).collect() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(Error::KMLInvalid);
+            }
+        };
+        if values.len() < 2 {
+            return Err(Error::KMLInvalid);
+        }
+        ret.push(make_waypoint(values[0], values[1], values.get(2).copied()));
+    }
+    Ok(ret)
+}
+
+// <gx:coord>lon lat ele</gx:coord>
+fn read_gx_track(node: &Node) -> Result<Vec<gpx::Waypoint>, Error> {
+    let mut ret = Vec::new();
+    for coord in node.children().filter(|n| n.tag_name().name() == "coord") {
+        let text = coord.text().unwrap_or("");
+        let values: Vec<f64> = match text.split_whitespace().map(|v| v.parse::<f64>()).collect() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(Error::KMLInvalid);
+            }
+        };
+        if values.len() < 2 {
+            return Err(Error::KMLInvalid);
+        }
+        ret.push(make_waypoint(values[0], values[1], values.get(2).copied()));
+    }
+    Ok(ret)
+}
+
+fn read_lines(placemark: &Node) -> Result<Vec<Vec<gpx::Waypoint>>, Error> {
+    let mut ret = Vec::new();
+    for node in placemark.descendants() {
+        match node.tag_name().name() {
+            "LineString" => {
+                let text = child_text(&node, "coordinates").unwrap_or_default();
+                ret.push(read_coordinates(&text)?);
+            }
+            "Track" => {
+                ret.push(read_gx_track(&node)?);
+            }
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+fn read_point(placemark: &Node) -> Result<Option<gpx::Waypoint>, Error> {
+    let point = match placemark
+        .descendants()
+        .find(|n| n.tag_name().name() == "Point")
+    {
+        Some(p) => p,
+        None => {
+            return Ok(None);
+        }
+    };
+    let text = child_text(&point, "coordinates").unwrap_or_default();
+    let mut points = read_coordinates(&text)?;
+    if points.is_empty() {
+        return Ok(None);
+    }
+    let mut ret = points.remove(0);
+    // GPX waypoints elevation is ignored anyway.
+    ret.elevation = None;
+    ret.name = child_text(placemark, "name");
+    ret.description = child_text(placemark, "description");
+    Ok(Some(ret))
+}
+
+pub fn read(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    let text = std::str::from_utf8(content).map_err(|_| Error::KMLInvalid)?;
+    let document = roxmltree::Document::parse(text).map_err(|e| {
+        log::error!("cannot parse KML: {}", e);
+        Error::KMLInvalid
+    })?;
+    let mut ret = empty_gpx();
+    for placemark in document
+        .descendants()
+        .filter(|n| n.tag_name().name() == "Placemark")
+    {
+        let lines = read_lines(&placemark)?;
+        if !lines.is_empty() {
+            let track = make_track(child_text(&placemark, "name"), lines);
+            if !track.segments.is_empty() {
+                ret.tracks.push(track);
+            }
+            continue;
+        }
+        if let Some(w) = read_point(&placemark)? {
+            ret.waypoints.push(w);
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kml() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <Placemark>
+      <name>Brevet</name>
+      <LineString>
+        <coordinates>
+          8.0,48.0,100 8.1,48.1,110
+          8.2,48.2
+        </coordinates>
+      </LineString>
+    </Placemark>
+    <Placemark>
+      <name>K1</name>
+      <description>Bakery</description>
+      <Point><coordinates>8.1,48.1,0</coordinates></Point>
+    </Placemark>
+    <Placemark>
+      <gx:Track><gx:coord>9.0 47.0 300</gx:coord><gx:coord>9.1 47.1 310</gx:coord></gx:Track>
+    </Placemark>
+  </Document>
+</kml>"#;
+        let gpx = read(data.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 2);
+        assert_eq!(gpx.tracks[0].name.as_ref().unwrap(), "Brevet");
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[1].point().x_y(), (8.1, 48.1));
+        assert_eq!(points[1].elevation, Some(110.0));
+        assert_eq!(points[2].elevation, None);
+        assert_eq!(gpx.tracks[1].segments[0].points[1].elevation, Some(310.0));
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_ref().unwrap(), "K1");
+        assert_eq!(gpx.waypoints[0].description.as_ref().unwrap(), "Bakery");
+    }
+
+    #[test]
+    fn invalid() {
+        let data = "<kml><Placemark><LineString><coordinates>8.0;48.0</coordinates></LineString></Placemark></kml>";
+        assert!(matches!(read(data.as_bytes()), Err(Error::KMLInvalid)));
+    }
+}
@@ -0,0 +1,159 @@
+mod fit;
+mod geojson;
+mod kml;
+mod tcx;
+
+use roxmltree::Node;
+
+use crate::error::Error;
+
+/*
+ * Input formats other than GPX are converted to gpx::Gpx
+ * (tracks and waypoints), so that the rest of the pipeline
+ * only deals with GPX data.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum InputFormat {
+    GPX,
+    KML,
+    GeoJSON,
+    TCX,
+    FIT,
+}
+
+// name of the root element of an XML document.
+fn xml_root(text: &str) -> Option<&str> {
+    let mut rest = text;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        if rest.starts_with('?') || rest.starts_with('!') {
+            continue;
+        }
+        let end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+        let name = &rest[..end];
+        // drop the namespace prefix
+        return Some(name.rsplit(':').next().unwrap_or(name));
+    }
+}
+
+pub fn detect(content: &[u8]) -> Option<InputFormat> {
+    if fit::is_fit(content) {
+        return Some(InputFormat::FIT);
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with('{') {
+        return Some(InputFormat::GeoJSON);
+    }
+    match xml_root(text)? {
+        "gpx" => Some(InputFormat::GPX),
+        "kml" => Some(InputFormat::KML),
+        "TrainingCenterDatabase" => Some(InputFormat::TCX),
+        _ => None,
+    }
+}
+
+fn read_gpx(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    let reader_mem = std::io::Cursor::new(content);
+    match gpx::read(reader_mem) {
+        Ok(d) => Ok(d),
+        Err(_e) => Err(Error::GPXInvalid),
+    }
+}
+
+pub fn read(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    let format = match detect(content) {
+        Some(f) => f,
+        None => {
+            return Err(Error::UnknownFormat);
+        }
+    };
+    log::info!("input format: {:?}", format);
+    let ret = match format {
+        InputFormat::GPX => read_gpx(content)?,
+        InputFormat::KML => kml::read(content)?,
+        InputFormat::GeoJSON => geojson::read(content)?,
+        InputFormat::TCX => tcx::read(content)?,
+        InputFormat::FIT => fit::read(content)?,
+    };
+    if format != InputFormat::GPX && ret.tracks.is_empty() {
+        return Err(Error::NoTrack {
+            format: format!("{:?}", format),
+        });
+    }
+    Ok(ret)
+}
+
+fn make_waypoint(lon: f64, lat: f64, ele: Option<f64>) -> gpx::Waypoint {
+    let mut ret = gpx::Waypoint::new(geo::Point::new(lon, lat));
+    ret.elevation = ele;
+    ret
+}
+
+fn make_track(name: Option<String>, segments: Vec<Vec<gpx::Waypoint>>) -> gpx::Track {
+    let mut ret = gpx::Track::new();
+    ret.name = name;
+    for points in segments {
+        if points.is_empty() {
+            continue;
+        }
+        let mut segment = gpx::TrackSegment::new();
+        segment.points = points;
+        ret.segments.push(segment);
+    }
+    ret
+}
+
+// XML helpers for KML and TCX (namespaces are ignored)
+fn child<'a, 'input>(node: &Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn child_text(node: &Node, name: &str) -> Option<String> {
+    let text = child(node, name)?.text()?.trim().to_string();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text)
+}
+
+fn empty_gpx() -> gpx::Gpx {
+    gpx::Gpx {
+        version: gpx::GpxVersion::Gpx11,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        let gpx = "<?xml version=\"1.0\"?>\n<gpx version=\"1.1\"></gpx>";
+        assert_eq!(detect(gpx.as_bytes()), Some(InputFormat::GPX));
+        let kml = "<?xml version=\"1.0\"?><!-- x --><kml xmlns=\"a\"></kml>";
+        assert_eq!(detect(kml.as_bytes()), Some(InputFormat::KML));
+        let tcx = "<TrainingCenterDatabase xmlns=\"b\"/>";
+        assert_eq!(detect(tcx.as_bytes()), Some(InputFormat::TCX));
+        let json = "\u{feff} {\"type\":\"Feature\"}";
+        assert_eq!(detect(json.as_bytes()), Some(InputFormat::GeoJSON));
+        assert_eq!(detect("hello".as_bytes()), None);
+    }
+
+    #[test]
+    fn unknown() {
+        assert!(matches!(
+            read("hello".as_bytes()),
+            Err(Error::UnknownFormat)
+        ));
+        let kml = "<kml><Document></Document></kml>";
+        assert!(matches!(
+            read(kml.as_bytes()),
+            Err(Error::NoTrack { format: _ })
+        ));
+    }
+}
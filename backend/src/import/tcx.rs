@@ -0,0 +1,141 @@
+use roxmltree::Node;
+
+use super::{child, child_text, empty_gpx, make_track, make_waypoint};
+use crate::error::Error;
+
+/*
+ * TCX: Courses (or Activities) with Trackpoints are tracks,
+ * CoursePoints are waypoints.
+ */
+
+fn child_f64(node: &Node, name: &str) -> Result<Option<f64>, Error> {
+    match child_text(node, name) {
+        Some(text) => match text.parse::<f64>() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(Error::TCXInvalid),
+        },
+        None => Ok(None),
+    }
+}
+
+fn read_position(node: &Node) -> Result<Option<gpx::Waypoint>, Error> {
+    let position = match child(node, "Position") {
+        Some(p) => p,
+        // trackpoints without position (pauses) are skipped
+        None => {
+            return Ok(None);
+        }
+    };
+    let lat = child_f64(&position, "LatitudeDegrees")?.ok_or(Error::TCXInvalid)?;
+    let lon = child_f64(&position, "LongitudeDegrees")?.ok_or(Error::TCXInvalid)?;
+    let ele = child_f64(node, "AltitudeMeters")?;
+    Ok(Some(make_waypoint(lon, lat, ele)))
+}
+
+// a Course, or a Lap of an Activity
+fn read_tracks(node: &Node) -> Result<Vec<Vec<gpx::Waypoint>>, Error> {
+    let mut ret = Vec::new();
+    for track in node.children().filter(|n| n.tag_name().name() == "Track") {
+        let mut points = Vec::new();
+        for trackpoint in track
+            .children()
+            .filter(|n| n.tag_name().name() == "Trackpoint")
+        {
+            if let Some(w) = read_position(&trackpoint)? {
+                points.push(w);
+            }
+        }
+        ret.push(points);
+    }
+    Ok(ret)
+}
+
+pub fn read(content: &[u8]) -> Result<gpx::Gpx, Error> {
+    let text = std::str::from_utf8(content).map_err(|_| Error::TCXInvalid)?;
+    let document = roxmltree::Document::parse(text).map_err(|e| {
+        log::error!("cannot parse TCX: {}", e);
+        Error::TCXInvalid
+    })?;
+    let mut ret = empty_gpx();
+    for node in document.descendants() {
+        match node.tag_name().name() {
+            "Course" => {
+                let track = make_track(child_text(&node, "Name"), read_tracks(&node)?);
+                ret.tracks.push(track);
+                for point in node
+                    .children()
+                    .filter(|n| n.tag_name().name() == "CoursePoint")
+                {
+                    if let Some(mut w) = read_position(&point)? {
+                        w.elevation = None;
+                        w.name = child_text(&point, "Name");
+                        w.description = child_text(&point, "Notes");
+                        ret.waypoints.push(w);
+                    }
+                }
+            }
+            "Activity" => {
+                let mut segments = Vec::new();
+                for lap in node.children().filter(|n| n.tag_name().name() == "Lap") {
+                    segments.extend(read_tracks(&lap)?);
+                }
+                // all laps make one track
+                let points: Vec<_> = segments.into_iter().flatten().collect();
+                ret.tracks
+                    .push(make_track(child_text(&node, "Id"), vec![points]));
+            }
+            _ => {}
+        }
+    }
+    ret.tracks.retain(|t| !t.segments.is_empty());
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tcx() {
+        let data = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Courses>
+    <Course>
+      <Name>BRM 300</Name>
+      <Track>
+        <Trackpoint>
+          <Position><LatitudeDegrees>48.0</LatitudeDegrees><LongitudeDegrees>8.0</LongitudeDegrees></Position>
+          <AltitudeMeters>250.5</AltitudeMeters>
+        </Trackpoint>
+        <Trackpoint><Time>2024-01-01T00:00:00Z</Time></Trackpoint>
+        <Trackpoint>
+          <Position><LatitudeDegrees>48.1</LatitudeDegrees><LongitudeDegrees>8.1</LongitudeDegrees></Position>
+        </Trackpoint>
+      </Track>
+      <CoursePoint>
+        <Name>K1</Name>
+        <Position><LatitudeDegrees>48.1</LatitudeDegrees><LongitudeDegrees>8.1</LongitudeDegrees></Position>
+        <PointType>Food</PointType>
+        <Notes>Bakery</Notes>
+      </CoursePoint>
+    </Course>
+  </Courses>
+</TrainingCenterDatabase>"#;
+        let gpx = read(data.as_bytes()).unwrap();
+        assert_eq!(gpx.tracks.len(), 1);
+        assert_eq!(gpx.tracks[0].name.as_ref().unwrap(), "BRM 300");
+        let points = &gpx.tracks[0].segments[0].points;
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].elevation, Some(250.5));
+        assert_eq!(points[1].point().x_y(), (8.1, 48.1));
+        assert_eq!(gpx.waypoints.len(), 1);
+        assert_eq!(gpx.waypoints[0].name.as_ref().unwrap(), "K1");
+        assert_eq!(gpx.waypoints[0].description.as_ref().unwrap(), "Bakery");
+    }
+
+    #[test]
+    fn invalid() {
+        let data = "<TrainingCenterDatabase><Courses><Course><Track><Trackpoint><Position><LatitudeDegrees>x</LatitudeDegrees></Position></Trackpoint></Track></Course></Courses></TrainingCenterDatabase>";
+        assert!(matches!(read(data.as_bytes()), Err(Error::TCXInvalid)));
+    }
+}
@@ -9,6 +9,7 @@ use serde_json::json;
 
 use crate::{
     mercator::{EuclideanBoundingBox, MercatorPoint},
+    osm::category,
     tile::{self, Tile},
     track::Track,
     track_projection::{TrackProjection, TrackProjections},
@@ -17,14 +18,24 @@ use crate::{
 };
 
 pub type Tags = std::collections::BTreeMap<String, String>;
+pub use crate::osm::category::{OSMCategory, OSMTagFilter};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+// see osm::category for the tags of each type.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Hash)]
 pub enum OSMType {
     City,
     MountainPass,
     Peak,
     Village,
     Hamlet,
+    DrinkingWater,
+    Fuel,
+    Supermarket,
+    Bakery,
+    BicycleShop,
+    Toilets,
+    Shelter,
+    Campsite,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Hash)]
@@ -33,6 +44,8 @@ pub enum InputType {
     OSM,
     UserStep,
     Control,
+    // sunrise and sunset, computed per segment (not in the point maps).
+    Sun,
 }
 
 pub type Kinds = HashSet<InputType>;
@@ -42,6 +55,7 @@ pub fn allkinds() -> Kinds {
         InputType::GPX,
         InputType::OSM,
         InputType::Control,
+        InputType::Sun,
     ])
 }
 
@@ -95,6 +109,12 @@ impl InputPoint {
         p
     }
 
+    pub fn create_sun_on_track(track: &Track, index: usize, name: &String) -> InputPoint {
+        let mut p = InputPoint::create_user_step_on_track(track, index, name);
+        p.tags.extend(InputPoint::tags_for_type(InputType::Sun));
+        p
+    }
+
     pub fn create_control_on_track(
         track: &Track,
         index: usize,
@@ -237,46 +257,14 @@ impl InputPoint {
             InputType::OSM => "OSM",
             InputType::UserStep => "UserStep",
             InputType::Control => "Control",
+            InputType::Sun => "Sun",
         };
         tags.insert("wpxtype".to_string(), value.to_string());
         tags
     }
 
     pub fn osmkind(&self) -> Option<OSMType> {
-        match self.tags.get("place") {
-            Some(place) => {
-                if place == "city" {
-                    return Some(OSMType::City);
-                }
-                if place == "town" {
-                    return Some(OSMType::City);
-                }
-                if place == "village" {
-                    return Some(OSMType::Village);
-                }
-                if place == "hamlet" {
-                    return Some(OSMType::Hamlet);
-                }
-            }
-            _ => {}
-        }
-        match self.tags.get("mountain_pass") {
-            Some(pass) => {
-                if pass == "yes" {
-                    return Some(OSMType::MountainPass);
-                }
-            }
-            _ => {}
-        }
-        match self.tags.get("natural") {
-            Some(natural) => {
-                if natural == "peak" {
-                    return Some(OSMType::Peak);
-                }
-            }
-            _ => {}
-        }
-        None
+        category::classify(&self.tags)
     }
 
     pub fn kind(&self) -> InputType {
@@ -292,6 +280,9 @@ impl InputPoint {
                     "Control" => {
                         return InputType::Control;
                     }
+                    "Sun" => {
+                        return InputType::Sun;
+                    }
                     _ => {}
                 };
             }
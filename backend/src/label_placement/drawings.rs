@@ -9,7 +9,11 @@ use crate::{
 pub fn timestr(w: &InputPoint, segment: &SegmentData) -> String {
     let index = w.single_track_index().unwrap();
     let track = &segment.track;
-    let t = speed::time_at_distance(&track.distance(index), &segment.parameters);
+    let t = speed::time_at_distance(
+        &track.distance(index),
+        &segment.timetable,
+        &segment.parameters,
+    );
     format!("{}", t.format("%H:%M"))
 }
 
@@ -25,7 +29,7 @@ pub fn make_label_text(w: &InputPoint, segment: &SegmentData) -> String {
             return format!("{}", timestr(w, segment));
         }
 
-        InputType::Control => {
+        InputType::Control | InputType::Sun => {
             return format!("{} ({})", w.name(), timestr(w, segment));
         }
     }
@@ -52,15 +56,18 @@ fn make_circle(
 
 pub fn draw_for_profile(center: &Point2D, id: &str, w: &InputPoint) -> PointFeatureDrawing {
     let (r, fill) = match w.kind() {
-        InputType::OSM => match w.osmkind().unwrap() {
-            OSMType::City => (5f64, "Black"),
-            OSMType::Village => (4f64, "Black"),
-            OSMType::Hamlet => (2f64, "Gray"),
-            OSMType::MountainPass => (3f64, "Green"),
-            OSMType::Peak => (3f64, "Green"),
+        InputType::OSM => match w.osmkind() {
+            Some(OSMType::City) => (5f64, "Black"),
+            Some(OSMType::Village) => (4f64, "Black"),
+            Some(OSMType::Hamlet) => (2f64, "Gray"),
+            Some(OSMType::MountainPass) => (3f64, "Green"),
+            Some(OSMType::Peak) => (3f64, "Green"),
+            // services (water, food, shelter...)
+            _ => (3f64, "DarkCyan"),
         },
         InputType::GPX => (5f64, "Blue"),
         InputType::UserStep => (3f64, "Black"),
+        InputType::Sun => (3f64, "Orange"),
         InputType::Control => (5f64, "Blue"),
     };
 
@@ -70,24 +77,19 @@ pub fn draw_for_profile(center: &Point2D, id: &str, w: &InputPoint) -> PointFeat
     let mut group = svg::node::element::Group::new();
     group = group.add(circle);
 
-    match w.kind() {
-        InputType::OSM => {
-            let osm = w.osmkind().unwrap();
-            if osm == OSMType::City || osm == OSMType::Village || osm == OSMType::Hamlet {
-                let mut white =
-                    make_circle(center, &format!("{}-little-white", id), "white", &0.0, "");
-                white = white.set("r", format!("{}", (r - 1.5).max(0.0)));
-                group = group.add(white);
+    if let (InputType::OSM, Some(osm)) = (w.kind(), w.osmkind()) {
+        if osm == OSMType::City || osm == OSMType::Village || osm == OSMType::Hamlet {
+            let mut white = make_circle(center, &format!("{}-little-white", id), "white", &0.0, "");
+            white = white.set("r", format!("{}", (r - 1.5).max(0.0)));
+            group = group.add(white);
 
-                if osm == OSMType::City {
-                    let mut black =
-                        make_circle(center, &format!("{}-little-white", id), "black", &0.0, "");
-                    black = black.set("r", format!("{}", (r - 2.5).max(0.0)));
-                    group = group.add(black);
-                }
+            if osm == OSMType::City {
+                let mut black =
+                    make_circle(center, &format!("{}-little-white", id), "black", &0.0, "");
+                black = black.set("r", format!("{}", (r - 2.5).max(0.0)));
+                group = group.add(black);
             }
         }
-        _ => {}
     }
 
     PointFeatureDrawing {
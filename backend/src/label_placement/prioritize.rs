@@ -1,6 +1,7 @@
 use crate::{
     inputpoint::{InputPoint, InputType, OSMType},
     locate,
+    osm::category,
     segment::SegmentData,
     track_projection::is_close_to_track,
 };
@@ -67,6 +68,7 @@ impl ProfilePoints {
         } else {
             controls.clone()
         };
+        self.controls.extend(segment.points(&InputType::Sun));
         let trackrange = segment.range();
         {
             let points = segment.points(&InputType::UserStep);
@@ -92,14 +94,14 @@ impl ProfilePoints {
             if !is_close_to_track(&wi) {
                 continue;
             }
-            match wi.osmkind().unwrap() {
-                OSMType::City => {
+            match wi.osmkind() {
+                Some(OSMType::City) => {
                     self.cities.push(wi);
                 }
-                OSMType::MountainPass | OSMType::Peak => {
+                Some(OSMType::MountainPass) | Some(OSMType::Peak) => {
                     self.mountains.push(wi);
                 }
-                OSMType::Village => {
+                Some(OSMType::Village) => {
                     self.villages.push(wi);
                 }
                 _ => {
@@ -107,6 +109,10 @@ impl ProfilePoints {
                 }
             }
         }
+        self.osmrest.sort_by_key(|w| match w.osmkind() {
+            Some(kind) => category::get(&kind).priority,
+            None => i32::MAX,
+        });
         sort_by_elevation(&mut self.mountains);
         sort_by_population(&mut self.cities);
         sort_by_population(&mut self.villages);
@@ -143,11 +149,8 @@ pub fn map(segment: &SegmentData) -> Vec<Vec<InputPoint>> {
         if is_close_to_track(&w) {
             continue;
         }
-        match w.osmkind().unwrap() {
-            OSMType::City => {
-                offtrack_cities.push(w);
-            }
-            _ => {}
+        if w.osmkind() == Some(OSMType::City) {
+            offtrack_cities.push(w);
         }
     }
     for point in &mut offtrack_cities {
@@ -1,12 +1,17 @@
 pub mod backend;
 mod bbox;
+pub mod brevet;
+pub mod cancel;
+pub mod config;
 mod controls;
-mod elevation;
+pub mod dem;
+pub mod elevation;
 pub mod error;
 mod event;
 mod format;
 pub mod gpsdata;
 mod gpxexport;
+mod import;
 pub mod inputpoint;
 mod label_placement;
 mod locate;
@@ -15,8 +20,10 @@ pub mod math;
 pub mod mercator;
 mod osm;
 pub mod parameters;
-mod pdf;
+pub mod part_order;
+pub mod pdf;
 mod profile;
+mod projectfile;
 mod tile;
 mod track_projection;
 pub mod wheel;
@@ -25,6 +32,7 @@ mod zipexport;
 mod render;
 mod segment;
 pub mod speed;
+mod sun;
 mod svgmap;
 mod track;
 pub mod waypoint;
@@ -1,14 +1,21 @@
 #![allow(non_snake_case)]
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tracks::backend::Backend;
+use tracks::cancel::CancelToken;
 use tracks::math::IntegerSize2D;
 use tracks::speed;
-use tracks::{error, inputpoint};
+use tracks::wgs84point::WGS84Point;
+use tracks::{backend, brevet, config, dem, elevation, error, inputpoint, parameters, pdf};
 
 /// Search for a pattern in a file and display the lines that contain it.
 #[derive(Parser)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    /// parameters file (.toml or .json), instead of the user configuration
+    #[arg(long, value_name = "config")]
+    config: Option<std::path::PathBuf>,
     #[arg(long, value_name = "debug")]
     debug: Option<bool>,
     #[arg(long, value_name = "outdir")]
@@ -21,6 +28,75 @@ struct Cli {
     start_time: Option<String>,
     #[arg(long, value_name = "speed")]
     speed: Option<f64>,
+    /// slope dependent speed, e.g. "-5:35,0:25,5:12" (slope in %, speed in km/h)
+    #[arg(long, value_name = "speed_table", value_delimiter = ',')]
+    speed_table: Option<Vec<String>>,
+    /// rider power in W, enables the physics speed model
+    #[arg(long, value_name = "power")]
+    power: Option<f64>,
+    /// rider and bike mass in kg (physics model)
+    #[arg(long, value_name = "mass")]
+    mass: Option<f64>,
+    #[arg(long, value_name = "cda")]
+    cda: Option<f64>,
+    #[arg(long, value_name = "crr")]
+    crr: Option<f64>,
+    /// minutes spent at each control
+    #[arg(long, value_name = "control_dwell")]
+    control_dwell: Option<f64>,
+    /// minutes spent at each user step
+    #[arg(long, value_name = "user_step_dwell")]
+    user_step_dwell: Option<f64>,
+    /// sleep breaks, e.g. "380:3,780:2" (km, hours)
+    #[arg(long, value_name = "sleep", value_delimiter = ',')]
+    sleep: Option<Vec<String>>,
+    /// acp, rusa, lrm or none (default: none, no control times)
+    #[arg(long, value_name = "brevet_rules")]
+    brevet_rules: Option<String>,
+    /// nominal brevet distance in km (default: inferred from the track)
+    #[arg(long, value_name = "brevet_distance")]
+    brevet_distance: Option<f64>,
+    /// meters on each side of a point for the elevation smoothing
+    #[arg(long, value_name = "smooth_width")]
+    smooth_width: Option<f64>,
+    /// average, savitzky-golay or resampled
+    #[arg(long, value_name = "elevation_smoothing")]
+    elevation_smoothing: Option<String>,
+    /// meters, climbs below are not counted in the elevation gain
+    #[arg(long, value_name = "gain_threshold")]
+    gain_threshold: Option<f64>,
+    /// auto, embedded, binary or remote
+    #[arg(long, value_name = "pdf_backend")]
+    pdf_backend: Option<String>,
+    /// the typst binary (default: found in PATH)
+    #[arg(long, value_name = "typst_binary")]
+    typst_binary: Option<String>,
+    /// the server compiling the pdf
+    #[arg(long, value_name = "pdf_remote_url")]
+    pdf_remote_url: Option<String>,
+    /// pem file with the certificates of the pdf server (required for
+    /// a self signed certificate)
+    #[arg(long, value_name = "pdf_ca_certificate")]
+    pdf_ca_certificate: Option<String>,
+    /// trust only pdf_ca_certificate for the pdf server
+    #[arg(long, value_name = "pdf_pin_certificate")]
+    pdf_pin_certificate: Option<bool>,
+    /// accept any certificate from the pdf server
+    #[arg(long, value_name = "pdf_insecure")]
+    pdf_insecure: Option<bool>,
+    /// seconds
+    #[arg(long, value_name = "pdf_timeout")]
+    pdf_timeout: Option<u64>,
+    /// columns of the points table of the pdf, e.g.
+    /// "KM=DISTANCE[.0];TIME=TIME[%H:%M];TO GO=REMAINING[.0]"
+    #[arg(long, value_name = "points_table", value_delimiter = ';')]
+    points_table: Option<Vec<String>>,
+    /// rows of each points table of the pdf, the others continue on the next pages
+    #[arg(long, value_name = "max_table_rows")]
+    max_table_rows: Option<usize>,
+    /// directory with the typst templates of the pdf
+    #[arg(long, value_name = "templates")]
+    templates: Option<String>,
     #[arg(long, value_name = "step_distance")]
     step_distance: Option<usize>,
     #[arg(long, value_name = "step_elevation_gain")]
@@ -31,21 +107,227 @@ struct Cli {
     map_max_area_ratio: Option<f64>,
     #[arg(long, value_name = "render_wheel")]
     render_wheel: Option<bool>,
+    #[arg(long, value_name = "dem_directory")]
+    dem_directory: Option<std::path::PathBuf>,
+    #[arg(long, value_name = "dem_all")]
+    dem_all: Option<bool>,
+    /// read the osm points from a local .osm.pbf file (no download).
+    /// only nodes: places mapped as areas only are missing.
+    #[arg(long, value_name = "osm_pbf")]
+    osm_pbf: Option<std::path::PathBuf>,
+    /// overpass servers, tried in order
+    #[arg(long, value_name = "overpass_url", value_delimiter = ',')]
+    overpass_url: Option<Vec<String>>,
+    /// store the overpass responses in this directory
+    #[arg(long, value_name = "osm_record")]
+    osm_record: Option<std::path::PathBuf>,
+    /// read the overpass responses from this directory (no download)
+    #[arg(long, value_name = "osm_replay")]
+    osm_replay: Option<std::path::PathBuf>,
+    /// days, older osm data is downloaded again
+    #[arg(long, value_name = "cache_max_age")]
+    cache_max_age: Option<u64>,
+    /// MB, the least recently used osm data is removed beyond
+    #[arg(long, value_name = "cache_max_size")]
+    cache_max_size: Option<u64>,
+    #[arg(long, value_name = "part_order", value_delimiter = ',')]
+    part_order: Option<Vec<usize>>,
+    /// a .wpx file to save the project to (input, parameters and points).
+    #[arg(long, value_name = "save_project")]
+    save_project: Option<std::path::PathBuf>,
     #[arg(long, value_name = "main-test")]
     main_test: Option<bool>,
     #[arg(value_name = "gpx")]
-    filename: std::path::PathBuf,
+    filename: Option<std::path::PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Manage the cache of the OSM points
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Show the default parameters
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check or export the typst templates of the pdf
+    Templates {
+        #[command(subcommand)]
+        action: TemplatesAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum TemplatesAction {
+    /// Report the problems of the templates of a directory
+    Check { directory: String },
+    /// Write the built-in templates to a directory
+    Export { directory: String },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the default parameters (user configuration or --config) as TOML
+    Show,
+    /// Print the path of the user configuration file
+    Path,
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Print the number of chunks, the size and the age of the cache
+    Stats,
+    /// Remove all OSM points from the cache
+    Clear,
+    /// Download the OSM points along a track (gpx) or in a bounding box
+    /// ("minlon,minlat,maxlon,maxlat")
+    Prefetch {
+        #[arg(value_name = "gpx|bbox")]
+        target: String,
+    },
+}
+
+fn parse_speed_table(entries: &Vec<String>) -> Result<Vec<speed::SlopeSpeed>, error::Error> {
+    let mut ret = Vec::new();
+    for entry in entries {
+        let values: Vec<_> = entry.split(':').map(|v| v.trim().parse::<f64>()).collect();
+        match values.as_slice() {
+            [Ok(slope), Ok(kmh)] => ret.push(speed::SlopeSpeed {
+                slope: slope / 100f64,
+                speed: speed::mps(*kmh),
+            }),
+            _ => {
+                return Err(error::Error::InvalidArgument {
+                    argument: entry.clone(),
+                    reason: "speed table entries are slope:km/h".to_string(),
+                });
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_sleeps(entries: &Vec<String>) -> Result<Vec<speed::Stop>, error::Error> {
+    let mut ret = Vec::new();
+    for entry in entries {
+        let values: Vec<_> = entry.split(':').map(|v| v.trim().parse::<f64>()).collect();
+        match values.as_slice() {
+            [Ok(km), Ok(hours)] => ret.push(speed::Stop {
+                distance: 1000f64 * km,
+                duration: 3600f64 * hours,
+            }),
+            _ => {
+                return Err(error::Error::InvalidArgument {
+                    argument: entry.clone(),
+                    reason: "sleep entries are km:hours".to_string(),
+                });
+            }
+        }
+    }
+    Ok(ret)
+}
+
+// "TITLE=FORMAT" entries, the title is the format without "=".
+fn parse_table_columns(entries: &Vec<String>) -> Vec<parameters::TableColumn> {
+    let mut ret = Vec::new();
+    for entry in entries {
+        let (title, format) = entry.split_once('=').unwrap_or((entry, entry));
+        ret.push(parameters::TableColumn {
+            title: title.trim().to_string(),
+            format: format.to_string(),
+        });
+    }
+    ret
+}
+
+fn parse_bbox(text: &str) -> Option<(WGS84Point, WGS84Point)> {
+    let values: Vec<_> = text.split(',').map(|v| v.trim().parse::<f64>()).collect();
+    match values.as_slice() {
+        [Ok(minlon), Ok(minlat), Ok(maxlon), Ok(maxlat)] => Some((
+            WGS84Point::new(minlon, minlat, &0f64),
+            WGS84Point::new(maxlon, maxlat, &0f64),
+        )),
+        _ => None,
+    }
+}
+
+fn format_timestamp(timestamp: Option<i64>) -> String {
+    match timestamp.and_then(|t| chrono::DateTime::from_timestamp(t, 0)) {
+        Some(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+async fn cache_command(backend: &mut Backend, action: &CacheAction) -> Result<(), error::Error> {
+    match action {
+        CacheAction::Stats => {
+            let stats = backend.osm_cache_stats().await;
+            println!("chunks:  {}", stats.chunks);
+            println!("size:    {:.1} MB", stats.size as f64 / (1024f64 * 1024f64));
+            println!("expired: {}", stats.expired);
+            println!("oldest:  {}", format_timestamp(stats.oldest));
+            println!("newest:  {}", format_timestamp(stats.newest));
+        }
+        CacheAction::Clear => {
+            backend.clear_osm_cache().await;
+        }
+        CacheAction::Prefetch { target } => {
+            if std::path::Path::new(target).exists() {
+                // loading the track fills the cache along it.
+                backend.load_filename(target).await?;
+                return Ok(());
+            }
+            match parse_bbox(target) {
+                Some((min, max)) => backend.prefetch_osm(&min, &max).await?,
+                None => {
+                    return Err(error::Error::InvalidArgument {
+                        argument: target.clone(),
+                        reason: "neither a file nor a bounding box".to_string(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn config_command(backend: &Backend, action: &ConfigAction) -> Result<(), error::Error> {
+    match action {
+        ConfigAction::Show => {
+            let parameters = backend.get_default_parameters();
+            print!("{}", config::write(&parameters, config::Format::TOML)?);
+        }
+        ConfigAction::Path => match config::user_config_path() {
+            Some(path) => println!("{}", path.display()),
+            None => log::error!("there is no configuration directory"),
+        },
+    }
+    Ok(())
+}
+
+fn templates_command(backend: &Backend, action: &TemplatesAction) -> Result<(), error::Error> {
+    match action {
+        TemplatesAction::Check { directory } => {
+            backend.check_templates(directory)?;
+            println!("{}: ok", directory);
+        }
+        TemplatesAction::Export { directory } => backend.export_templates(directory)?,
+    }
+    Ok(())
 }
 
 fn main_test(backend: &mut Backend) -> Result<(), error::Error> {
     let start = std::time::Instant::now();
-    let segment = backend.trackSegment();
+    let segment = backend.trackSegment()?;
     backend.render_segment_what(
         &segment,
         &"map".to_string(),
         &IntegerSize2D::new(1000, 1000),
         inputpoint::allkinds(),
-    );
+    )?;
     let duration = start.elapsed();
     log::info!("main_test took: {:.3?}", duration);
     Ok(())
@@ -69,12 +351,70 @@ async fn main() -> Result<(), error::Error> {
 
     let args = Cli::parse();
 
+    let mut backend = Backend::make();
+    let mut cache = backend.get_cache_config();
+    match args.cache_max_age {
+        Some(days) => {
+            cache.max_age = Some(days * 24 * 3600);
+        }
+        _ => {}
+    }
+    match args.cache_max_size {
+        Some(mb) => {
+            cache.max_size = Some(mb * 1024 * 1024);
+        }
+        _ => {}
+    }
+    backend.set_cache_config(&cache);
+    let mut overpass = backend::OverpassConfig::default();
+    match &args.overpass_url {
+        Some(urls) => {
+            overpass.endpoints = urls.clone();
+        }
+        _ => {}
+    }
+    match (&args.osm_record, &args.osm_replay) {
+        (Some(path), _) => {
+            overpass.recording = backend::Recording::Record(path.to_str().unwrap().to_string());
+        }
+        (_, Some(path)) => {
+            overpass.recording = backend::Recording::Replay(path.to_str().unwrap().to_string());
+        }
+        _ => {}
+    }
+    backend.set_overpass_config(&overpass);
+
+    match &args.config {
+        Some(path) => {
+            backend.set_default_parameters(&config::read(path)?)?;
+        }
+        _ => {
+            backend.load_user_config()?;
+        }
+    }
+
+    match &args.command {
+        Some(Commands::Cache { action }) => {
+            return cache_command(&mut backend, action).await;
+        }
+        Some(Commands::Config { action }) => {
+            return config_command(&backend, action);
+        }
+        Some(Commands::Templates { action }) => {
+            return templates_command(&backend, action);
+        }
+        _ => {}
+    }
+
     let gpxinput;
-    if args.filename.exists() {
-        gpxinput = args.filename.as_os_str().to_str().unwrap();
-    } else {
-        let e = error::Error::GPXNotFound;
-        return Err(e);
+    match &args.filename {
+        Some(filename) if filename.exists() => {
+            gpxinput = filename.as_os_str().to_str().unwrap();
+        }
+        _ => {
+            let e = error::Error::GPXNotFound;
+            return Err(e);
+        }
     }
 
     let gpxpath = std::path::Path::new(gpxinput);
@@ -86,10 +426,40 @@ async fn main() -> Result<(), error::Error> {
 
     log::info!("read gpx {}", gpxinput);
     log::info!("outdir   {}", outdir);
-    let mut backend = Backend::make();
-    backend.load_filename(gpxinput).await?;
+    match &args.dem_directory {
+        Some(path) => {
+            let fill = match args.dem_all {
+                Some(true) => dem::ElevationFill::All,
+                _ => dem::ElevationFill::Missing,
+            };
+            backend.set_dem_directory(path.to_str().unwrap(), fill);
+        }
+        _ => {}
+    }
+    match &args.osm_pbf {
+        Some(path) => {
+            backend.set_osm_pbf_file(Some(path.to_str().unwrap().to_string()));
+        }
+        _ => {}
+    }
+    backend.set_part_order(args.part_order.clone());
+    // .wpx files are projects saved with --save-project
+    match gpxpath.extension().and_then(|e| e.to_str()) {
+        Some("wpx") => {
+            let content = std::fs::read(gpxpath).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => error::Error::GPXNotFound,
+                _ => error::Error::IOError {
+                    message: format!("could not read {}: {}", gpxpath.display(), e),
+                },
+            })?;
+            backend.load_project(&content).await?;
+        }
+        _ => {
+            backend.load_filename(gpxinput).await?;
+        }
+    }
 
-    let mut parameters = backend.get_parameters();
+    let mut parameters = backend.get_parameters()?;
     match args.segment_length {
         Some(length) => {
             parameters.segment_length = 1000f64 * (length as f64);
@@ -118,6 +488,184 @@ async fn main() -> Result<(), error::Error> {
         _ => {}
     }
 
+    match &args.speed_table {
+        Some(entries) => {
+            parameters.speed_model = speed::SpeedModel::SlopeTable(parse_speed_table(entries)?);
+        }
+        _ => {}
+    }
+
+    match args.power {
+        Some(power) => {
+            let mut model = speed::PhysicsModel::default();
+            model.power = power;
+            model.mass = args.mass.unwrap_or(model.mass);
+            model.cda = args.cda.unwrap_or(model.cda);
+            model.crr = args.crr.unwrap_or(model.crr);
+            parameters.speed_model = speed::SpeedModel::Physics(model);
+        }
+        _ => {}
+    }
+
+    match args.control_dwell {
+        Some(minutes) => {
+            parameters.control_dwell = 60f64 * minutes;
+        }
+        _ => {}
+    }
+
+    match args.user_step_dwell {
+        Some(minutes) => {
+            parameters.user_steps_options.dwell = 60f64 * minutes;
+        }
+        _ => {}
+    }
+
+    match &args.sleep {
+        Some(entries) => {
+            parameters.sleeps = parse_sleeps(entries)?;
+        }
+        _ => {}
+    }
+
+    match &args.brevet_rules {
+        Some(rules) => {
+            parameters.brevet_rules = match rules.to_lowercase().as_str() {
+                "acp" => Some(brevet::BrevetRules::ACP),
+                "rusa" => Some(brevet::BrevetRules::RUSA),
+                "lrm" => Some(brevet::BrevetRules::LRM),
+                "none" => None,
+                _ => {
+                    return Err(error::Error::InvalidArgument {
+                        argument: rules.clone(),
+                        reason: "brevet rules are acp, rusa, lrm or none".to_string(),
+                    });
+                }
+            };
+        }
+        _ => {}
+    }
+
+    match args.brevet_distance {
+        Some(km) => {
+            parameters.brevet_distance = Some(1000f64 * km);
+        }
+        _ => {}
+    }
+
+    match args.smooth_width {
+        Some(width) => {
+            parameters.smooth_width = width;
+        }
+        _ => {}
+    }
+
+    match &args.elevation_smoothing {
+        Some(smoothing) => {
+            parameters.elevation_smoothing = match smoothing.to_lowercase().as_str() {
+                "average" => elevation::Smoothing::MovingAverage,
+                "savitzky-golay" => elevation::Smoothing::SavitzkyGolay,
+                "resampled" => elevation::Smoothing::Resampled,
+                _ => {
+                    return Err(error::Error::InvalidArgument {
+                        argument: smoothing.clone(),
+                        reason: "elevation smoothing is average, savitzky-golay or resampled"
+                            .to_string(),
+                    });
+                }
+            };
+        }
+        _ => {}
+    }
+
+    match args.gain_threshold {
+        Some(threshold) => {
+            parameters.elevation_gain = elevation::Gain::Hysteresis { threshold };
+        }
+        _ => {}
+    }
+
+    match &args.pdf_backend {
+        Some(backend) => {
+            parameters.pdf.backend = match backend.to_lowercase().as_str() {
+                "auto" => pdf::PdfBackend::Auto,
+                "embedded" => pdf::PdfBackend::Embedded,
+                "binary" => pdf::PdfBackend::Binary,
+                "remote" => pdf::PdfBackend::Remote,
+                _ => {
+                    return Err(error::Error::InvalidArgument {
+                        argument: backend.clone(),
+                        reason: "pdf backend is auto, embedded, binary or remote".to_string(),
+                    });
+                }
+            };
+        }
+        _ => {}
+    }
+
+    match &args.typst_binary {
+        Some(binary) => {
+            parameters.pdf.typst_binary = Some(binary.clone());
+        }
+        _ => {}
+    }
+
+    match &args.pdf_remote_url {
+        Some(url) => {
+            parameters.pdf.remote_url = Some(url.clone());
+        }
+        _ => {}
+    }
+
+    match &args.pdf_ca_certificate {
+        Some(path) => {
+            parameters.pdf.ca_certificate = Some(path.clone());
+        }
+        _ => {}
+    }
+
+    match args.pdf_pin_certificate {
+        Some(pin) => {
+            parameters.pdf.pin_certificate = pin;
+        }
+        _ => {}
+    }
+
+    match args.pdf_insecure {
+        Some(insecure) => {
+            parameters.pdf.accept_invalid_certificates = insecure;
+        }
+        _ => {}
+    }
+
+    match args.pdf_timeout {
+        Some(seconds) => {
+            parameters.pdf.timeout = seconds;
+        }
+        _ => {}
+    }
+
+    match &args.points_table {
+        Some(columns) => {
+            parameters.points_table = parse_table_columns(columns);
+        }
+        _ => {}
+    }
+
+    match args.max_table_rows {
+        Some(rows) => {
+            parameters.max_table_rows = rows;
+        }
+        _ => {}
+    }
+
+    match &args.templates {
+        Some(directory) => {
+            parameters.templates = Some(directory.clone());
+        }
+        _ => {}
+    }
+
     match args.step_distance {
         Some(km) => {
             parameters.user_steps_options.step_distance = Some((1000 * km) as f64);
@@ -153,7 +701,17 @@ async fn main() -> Result<(), error::Error> {
         _ => {}
     }
 
-    backend.set_parameters(&parameters);
+    backend.set_parameters(&parameters)?;
+
+    match &args.save_project {
+        Some(path) => {
+            log::info!("make: {}", path.display());
+            std::fs::write(path, backend.save_project()?).map_err(|e| error::Error::IOError {
+                message: format!("could not write {}: {}", path.display(), e),
+            })?;
+        }
+        _ => {}
+    }
 
     match args.main_test {
         Some(enabled) => {
@@ -167,14 +725,14 @@ async fn main() -> Result<(), error::Error> {
     match args.render_wheel {
         Some(enabled) => {
             if enabled {
-                let track_segment = backend.trackSegment();
+                let track_segment = backend.trackSegment()?;
                 let size = IntegerSize2D::new(250, 250);
                 let svg = backend.render_segment_what(
                     &track_segment,
                     &"wheel".to_string(),
                     &size,
                     inputpoint::allkinds(),
-                );
+                )?;
                 let filename = std::format!("/tmp/wheel.svg");
                 std::fs::write(&filename, svg.clone()).unwrap();
                 return Ok(());
@@ -183,11 +741,11 @@ async fn main() -> Result<(), error::Error> {
         _ => {}
     }
 
-    let stats = backend.statistics();
+    let stats = backend.statistics()?;
     log::info!("length = {:.1} km", stats.length / 1000f64);
     log::info!("elevation gain = {:.1} km", stats.elevation_gain);
 
-    let pdfbytes = backend.generatePdf().await;
+    let pdfbytes = backend.generatePdf(&CancelToken::new()).await?;
     let pdfname = format!(
         "{}/{}.pdf",
         outdir,
@@ -196,7 +754,7 @@ async fn main() -> Result<(), error::Error> {
     log::info!("make: {}", pdfname);
     std::fs::write(pdfname, &pdfbytes).expect("Could not write pdf.");
 
-    let gpxbytes = backend.generateGpx();
+    let gpxbytes = backend.generateGpx()?;
     let gpxname = format!(
         "{}/{}-waypoints.gpx",
         outdir,
@@ -1,11 +1,142 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
 
 use crate::error::GenericResult;
-use crate::event::{self, SenderHandlerLock};
 use crate::inputpoint::InputPointMap;
 use crate::mercator::EuclideanBoundingBox;
 use crate::tile::{self, Chunk, Tile};
 
+use super::chunkformat::{decode, encode};
+
+/*
+ * The cache has one entry per chunk, with the format version, the time it
+ * was written and the points of its tiles (see chunkformat.rs). Older
+ * formats are still read and are rewritten in the current format the
+ * next time the chunk is written (version 1 chunks have no timestamp, with
+ * a max age they are expired). Chunks with an unknown version are ignored
+ * and downloaded again.
+ *
+ * An index entry keeps the size and the last use of each chunk, for the
+ * stats and for the size limit: when the cache is too large, the least
+ * recently used chunks are removed. The index is created with the first
+ * write; a cache without index is indexed again from its chunks.
+ */
+
+const INDEX: &str = "index";
+// seconds, the last use of a chunk is not updated more often (the index is
+// written only when a last use changes).
+const LAST_USED_RESOLUTION: i64 = 3600;
+
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    // seconds, older chunks are downloaded again
+    pub max_age: Option<u64>,
+    // bytes
+    pub max_size: Option<u64>,
+}
+
+// the test cache (see cache_dir) is made of version 1 chunks that must not
+// expire.
+#[cfg(test)]
+fn default_max_age() -> Option<u64> {
+    None
+}
+
+#[cfg(not(test))]
+fn default_max_age() -> Option<u64> {
+    Some(90 * 24 * 3600)
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            max_age: default_max_age(),
+            max_size: Some(500 * 1024 * 1024),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CacheStats {
+    pub chunks: usize,
+    pub size: u64,
+    pub expired: usize,
+    // unix timestamps of the oldest and newest chunks
+    pub oldest: Option<i64>,
+    pub newest: Option<i64>,
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+// version 1 chunks have no timestamp, they are too old for any max_age.
+fn expired(timestamp: Option<i64>, config: &CacheConfig, now: i64) -> bool {
+    match (timestamp, config.max_age) {
+        (Some(t), Some(max_age)) => now - t > max_age as i64,
+        (None, Some(_)) => true,
+        (_, None) => false,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct IndexEntry {
+    size: u64,
+    // None for version 1 chunks
+    written: Option<i64>,
+    last_used: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    chunks: BTreeMap<String, IndexEntry>,
+}
+
+impl Index {
+    fn size(&self) -> u64 {
+        self.chunks.values().map(|e| e.size).sum()
+    }
+
+    // removes the least recently used chunks (but not the ones in keep)
+    // until the size is below max_size and returns their keys.
+    fn evict(&mut self, max_size: u64, keep: &BTreeSet<String>) -> Vec<String> {
+        let mut candidates: Vec<_> = self
+            .chunks
+            .iter()
+            .filter(|(key, _)| !keep.contains(*key))
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        candidates.sort();
+        let mut size = self.size();
+        let mut ret = Vec::new();
+        for (_, key) in candidates {
+            if size <= max_size {
+                break;
+            }
+            size -= self.chunks.remove(&key).unwrap().size;
+            ret.push(key);
+        }
+        ret
+    }
+
+    fn stats(&self, config: &CacheConfig) -> CacheStats {
+        let now = now();
+        let written: Vec<i64> = self.chunks.values().filter_map(|e| e.written).collect();
+        CacheStats {
+            chunks: self.chunks.len(),
+            size: self.size(),
+            expired: self
+                .chunks
+                .values()
+                .filter(|e| expired(e.written, config, now))
+                .count(),
+            oldest: written.iter().min().copied(),
+            newest: written.iter().max().copied(),
+        }
+    }
+}
+
 #[cfg(test)]
 fn cache_dir() -> String {
     "data/ref/cache".to_string()
@@ -27,22 +158,42 @@ fn cache_path(filename: &str) -> String {
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-async fn write_worker(filename: &str, data: String) {
+async fn write_worker(filename: &str, data: Vec<u8>) {
     super::filesystem::write(&cache_path(filename), data)
 }
 
 #[cfg(not(target_arch = "wasm32"))]
-async fn read_worker(filename: &str) -> GenericResult<String> {
+async fn read_worker(filename: &str) -> GenericResult<Vec<u8>> {
     super::filesystem::read(&cache_path(filename))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+async fn remove_worker(filename: &str) {
+    let _ = std::fs::remove_file(cache_path(filename));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn list_worker() -> Vec<String> {
+    super::filesystem::list(&cache_dir())
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn remove_worker(path: &str) {
+    super::indexdb::remove(path).await
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn list_worker() -> Vec<String> {
+    super::indexdb::list().await
+}
+
 #[cfg(target_arch = "wasm32")]
-async fn write_worker(path: &str, data: String) {
+async fn write_worker(path: &str, data: Vec<u8>) {
     super::indexdb::write(&path, data).await
 }
 
 #[cfg(target_arch = "wasm32")]
-async fn read_worker(path: &str) -> GenericResult<String> {
+async fn read_worker(path: &str) -> GenericResult<Vec<u8>> {
     super::indexdb::read(path).await
 }
 
@@ -58,8 +209,8 @@ async fn _hit_cache_worker(path: &String) -> bool {
 
 async fn _valid_cache(key: &str) -> bool {
     match read_worker(key).await {
-        Ok(data) => match InputPointMap::from_string(&data) {
-            Ok(_map) => {
+        Ok(data) => match decode(&data) {
+            Ok(_) => {
                 return true;
             }
             _ => {
@@ -88,19 +239,86 @@ pub fn tiles_from_bbox(bbox: &EuclideanBoundingBox) -> MissingTiles {
     ret
 }
 
-pub async fn read(bbox: &EuclideanBoundingBox) -> GenericResult<(InputPointMap, MissingTiles)> {
-    let chunks = tile::split_chunks(&bbox);
+async fn read_index() -> Option<Index> {
+    let data = read_worker(INDEX).await.ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            log::info!("invalid cache index: {:?}", e);
+            None
+        }
+    }
+}
+
+async fn write_index(index: &Index) {
+    write_worker(INDEX, serde_json::to_vec(index).unwrap()).await;
+}
+
+// reads the index, or makes it from the chunks.
+async fn load_index() -> Index {
+    if let Some(index) = read_index().await {
+        return index;
+    }
+    let mut index = Index::default();
+    let now = now();
+    for key in list_worker().await {
+        if key == INDEX {
+            continue;
+        }
+        let data = match read_worker(&key).await {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let written = match decode(&data) {
+            Ok((_, timestamp)) => timestamp,
+            Err(e) => {
+                log::info!("ignore cache entry {}: {:?}", key, e);
+                continue;
+            }
+        };
+        let entry = IndexEntry {
+            size: data.len() as u64,
+            written,
+            last_used: now,
+        };
+        index.chunks.insert(key, entry);
+    }
+    index
+}
+
+pub async fn stats(config: &CacheConfig) -> CacheStats {
+    load_index().await.stats(config)
+}
+
+pub async fn clear() {
+    for key in list_worker().await {
+        remove_worker(&key).await;
+    }
+}
+
+pub async fn read(
+    bbox: &EuclideanBoundingBox,
+    config: &CacheConfig,
+) -> GenericResult<(InputPointMap, MissingTiles)> {
+    let chunks = tile::split_chunks(bbox);
     let mut missing: MissingTiles = tiles_from_bbox(bbox);
     let mut good = InputPointMap::new();
-    for mut chunk in chunks {
+    let now = now();
+    let mut used = Vec::new();
+    for chunk in chunks {
         let key = chunk.basename();
         match read_worker(&key).await {
-            Ok(bytes) => match chunk.load_map(&bytes) {
-                Ok(()) => {
-                    for (tile, points) in &chunk.data.map {
+            Ok(bytes) => match decode(&bytes) {
+                Ok((map, timestamp)) => {
+                    if expired(timestamp, config, now) {
+                        log::info!("chunk {} has expired", key);
+                        continue;
+                    }
+                    used.push(key.clone());
+                    for (tile, points) in &map.map {
                         if bbox.overlap(&tile.bbox()) {
-                            missing.remove(&tile);
-                            good.insert_points(tile, &points);
+                            missing.remove(tile);
+                            good.insert_points(tile, points);
                         }
                     }
                     for tile in &missing {
@@ -108,17 +326,13 @@ pub async fn read(bbox: &EuclideanBoundingBox) -> GenericResult<(InputPointMap,
                             log::warn!(
                                 "could not find data for tile {} in chunk {}",
                                 tile.basename(),
-                                chunk.basename()
+                                key
                             );
                         }
                     }
                 }
                 Err(e) => {
-                    log::info!(
-                        "could not load map for chunk: {} because {:?}",
-                        chunk.basename(),
-                        e
-                    );
+                    log::info!("could not load map for chunk: {} because {:?}", key, e);
                     log::info!("this is probably because the format has changed");
                 }
             },
@@ -128,28 +342,47 @@ pub async fn read(bbox: &EuclideanBoundingBox) -> GenericResult<(InputPointMap,
             }
         }
     }
+    // caches without index (not written yet) are not touched.
+    if !used.is_empty() {
+        if let Some(mut index) = read_index().await {
+            let mut changed = false;
+            for key in used {
+                if let Some(entry) = index.chunks.get_mut(&key) {
+                    if now - entry.last_used >= LAST_USED_RESOLUTION {
+                        entry.last_used = now;
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                write_index(&index).await;
+            }
+        }
+    }
     Ok((good, missing))
 }
 
-pub async fn write(points: &InputPointMap, logger: &SenderHandlerLock) -> GenericResult<()> {
-    use tile::Chunk;
+pub async fn write(points: &InputPointMap, config: &CacheConfig) -> GenericResult<()> {
     let mut chunks = BTreeSet::new();
     for tile in points.map.keys() {
         chunks.insert(Chunk::from_coord(&tile::chunk_coord(tile)));
     }
-    let mut index = 0;
-    let total = chunks.len();
+    let mut index = load_index().await;
+    let now = now();
+    let mut written = BTreeSet::new();
     for mut chunk in chunks {
         let key = chunk.basename();
         match read_worker(&key).await {
-            Ok(bytes) => match chunk.load_map(&bytes) {
-                Ok(()) => {}
+            Ok(bytes) => match decode(&bytes) {
+                // the tiles of an expired chunk are not kept.
+                Ok((map, timestamp)) if !expired(timestamp, config, now) => {
+                    chunk.data = map;
+                }
+                Ok(_) => {
+                    log::info!("drop expired chunk {}", key);
+                }
                 Err(e) => {
-                    log::warn!(
-                        "cannot load map for {} because {:?} => ignore",
-                        chunk.basename(),
-                        e
-                    );
+                    log::warn!("cannot load map for {} because {:?} => ignore", key, e);
                 }
             },
             Err(_) => {}
@@ -158,23 +391,168 @@ pub async fn write(points: &InputPointMap, logger: &SenderHandlerLock) -> Generi
         let ltiles: Vec<_> = points
             .map
             .keys()
-            .filter(|tile| chunk.contains(&tile))
+            .filter(|tile| chunk.contains(tile))
             .collect();
         for tile in ltiles {
-            let tpoints = points.get(&tile).unwrap();
+            let tpoints = points.get(tile).unwrap();
             log::trace!(
                 "tile {} chunk {} npoints {}",
                 tile.basename(),
-                chunk.basename(),
+                key,
                 tpoints.len()
             );
             chunk.data.map.insert(tile.clone(), tpoints.clone());
         }
-        event::send_worker(logger, &format!("write cache {}/{}", index + 1, total)).await;
-        let data = chunk.map_as_string();
-        log::debug!("rewrite chunk {}", chunk.basename());
+        let data = encode(&chunk.data, now);
+        log::debug!("rewrite chunk {}", key);
+        index.chunks.insert(
+            key.clone(),
+            IndexEntry {
+                size: data.len() as u64,
+                written: Some(now),
+                last_used: now,
+            },
+        );
         write_worker(&key, data).await;
-        index += 1;
+        written.insert(key);
     }
+    if let Some(max_size) = config.max_size {
+        for key in index.evict(max_size, &written) {
+            log::info!("cache is full: remove chunk {}", key);
+            remove_worker(&key).await;
+        }
+    }
+    write_index(&index).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry() {
+        let config = CacheConfig {
+            max_age: Some(10),
+            max_size: None,
+        };
+        assert!(expired(Some(1000), &config, 1011));
+        assert!(!expired(Some(1000), &config, 1010));
+        assert!(expired(None, &config, 1011));
+        let forever = CacheConfig {
+            max_age: None,
+            max_size: None,
+        };
+        assert!(!expired(None, &forever, 1011));
+        assert!(!expired(Some(0), &forever, 1011));
+    }
+
+    #[test]
+    fn expiry_version1() {
+        // a version 1 chunk is the json of the map, without timestamp.
+        let bytes = InputPointMap::new().as_string().unwrap().into_bytes();
+        let (_, timestamp) = decode(&bytes).unwrap();
+        let config = CacheConfig {
+            max_age: Some(90 * 24 * 3600),
+            max_size: None,
+        };
+        assert!(expired(timestamp, &config, now()));
+    }
+
+    #[test]
+    fn evict() {
+        let entry = |size, last_used| IndexEntry {
+            size,
+            written: Some(0),
+            last_used,
+        };
+        let mut index = Index::default();
+        index.chunks.insert("a".to_string(), entry(100, 3));
+        index.chunks.insert("b".to_string(), entry(100, 1));
+        index.chunks.insert("c".to_string(), entry(100, 2));
+        index.chunks.insert("d".to_string(), entry(100, 0));
+        let keep = BTreeSet::from(["d".to_string()]);
+        let removed = index.evict(250, &keep);
+        assert_eq!(removed, vec!["b".to_string(), "c".to_string()]);
+        assert_eq!(index.size(), 200);
+        assert!(index.chunks.contains_key("d"));
+        let config = CacheConfig {
+            max_age: Some(10),
+            max_size: None,
+        };
+        let stats = index.stats(&config);
+        assert_eq!(stats.chunks, 2);
+        assert_eq!(stats.expired, 2);
+    }
+
+    /*
+     * Compares the time spent in decoding chunks (the bulk of read()) in
+     * the json and in the binary format. The chunks are made in a temporary
+     * directory, with a point for each point of data/blackforest.gpx:
+     *   cargo test --release bench_read -- --ignored --nocapture
+     */
+    #[test]
+    #[ignore]
+    fn bench_read() {
+        const RUNS: u32 = 20;
+        let gpx = std::fs::read("data/blackforest.gpx").unwrap();
+        let data = crate::gpsdata::read_content(&gpx).unwrap();
+        let track = crate::track::Track::from_tracks(&data.tracks).unwrap();
+        let mut points = InputPointMap::new();
+        for k in 0..track.len() {
+            let mut point = crate::inputpoint::InputPoint::from_wgs84(
+                &track.wgs84[k],
+                &track.euclidean[k],
+                crate::inputpoint::InputType::OSM,
+            );
+            point
+                .tags
+                .insert("name".to_string(), format!("point {}", k));
+            point
+                .tags
+                .insert("place".to_string(), "village".to_string());
+            points.insert_point(&point);
+        }
+        let mut chunks: BTreeMap<(isize, isize), Chunk> = BTreeMap::new();
+        for (tile, tpoints) in &points.map {
+            let coord = tile::chunk_coord(tile);
+            let chunk = chunks
+                .entry(coord)
+                .or_insert_with(|| Chunk::from_coord(&coord));
+            chunk.data.insert_points(tile, tpoints);
+        }
+        let directory = tempfile::tempdir().unwrap();
+        let path = |name: String| directory.path().join(name).to_str().unwrap().to_string();
+        for chunk in chunks.values() {
+            let key = chunk.basename();
+            let json = chunk.data.as_string().unwrap().into_bytes();
+            super::super::filesystem::write(&path(format!("json-{}", key)), json);
+            super::super::filesystem::write(&path(format!("bin-{}", key)), encode(&chunk.data, 0));
+        }
+        let mut json = Vec::new();
+        let mut binary = Vec::new();
+        for key in super::super::filesystem::list(directory.path().to_str().unwrap()) {
+            let bytes = super::super::filesystem::read(&path(key.clone())).unwrap();
+            if key.starts_with("json-") {
+                json.push(bytes);
+            } else {
+                binary.push(bytes);
+            }
+        }
+        assert_eq!(json.len(), chunks.len());
+        let measure = |chunks: &Vec<Vec<u8>>| {
+            let start = std::time::Instant::now();
+            for _ in 0..RUNS {
+                for bytes in chunks {
+                    decode(bytes).unwrap();
+                }
+            }
+            start.elapsed() / RUNS
+        };
+        let size = |chunks: &Vec<Vec<u8>>| chunks.iter().map(|c| c.len()).sum::<usize>();
+        let (tjson, tbinary) = (measure(&json), measure(&binary));
+        println!("json:   {:8} bytes {:.3?}", size(&json), tjson);
+        println!("binary: {:8} bytes {:.3?}", size(&binary), tbinary);
+        assert!(size(&binary) < size(&json));
+    }
+}
@@ -0,0 +1,377 @@
+use serde::Deserialize;
+
+use crate::error::Error;
+use crate::inputpoint::{OSMType, Tags};
+
+/*
+ * The registry of the OSM point categories. Each category has the tag
+ * filters used to build the overpass query and to classify the
+ * downloaded points (the first matching category wins), the name and
+ * icon shown in the app, the max distance to the track and a priority
+ * for the label placement (lower is more important).
+ *
+ * The kinds are fixed (OSMType), their definitions are built in and can
+ * be changed in WPX/categories.toml in the configuration directory:
+ *
+ *   [[category]]
+ *   kind = "DrinkingWater"
+ *   max_distance = 300.0
+ *   filters = [{ element = "node", key = "amenity", value = "drinking_water" }]
+ *
+ * Missing fields keep their built-in value.
+ */
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OSMTagFilter {
+    // overpass element type: "node" or "nwr"
+    pub element: String,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct OSMCategory {
+    pub kind: OSMType,
+    pub name: String,
+    pub icon: String,
+    pub filters: Vec<OSMTagFilter>,
+    pub max_distance: f64,
+    pub priority: i32,
+}
+
+fn filter(element: &str, key: &str, value: &str) -> OSMTagFilter {
+    OSMTagFilter {
+        element: element.to_string(),
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn category(
+    kind: OSMType,
+    name: &str,
+    icon: &str,
+    filters: Vec<OSMTagFilter>,
+    max_distance: f64,
+    priority: i32,
+) -> OSMCategory {
+    OSMCategory {
+        kind,
+        name: name.to_string(),
+        icon: icon.to_string(),
+        filters,
+        max_distance,
+        priority,
+    }
+}
+
+impl OSMCategory {
+    pub fn matches(&self, tags: &Tags) -> bool {
+        self.filters
+            .iter()
+            .any(|f| tags.get(&f.key).is_some_and(|v| *v == f.value))
+    }
+
+    pub fn is_place(&self) -> bool {
+        self.filters.iter().all(|f| f.key == "place")
+    }
+
+    // overpass statements, without the bounding box
+    pub fn queries(&self) -> Vec<String> {
+        self.filters
+            .iter()
+            .map(|f| format!("{}[\"{}\"=\"{}\"]", f.element, f.key, f.value))
+            .collect()
+    }
+}
+
+static REGISTRY: std::sync::LazyLock<Vec<OSMCategory>> = std::sync::LazyLock::new(|| {
+    let mut ret = make_registry();
+    match read_user_config(&mut ret) {
+        Ok(()) => {}
+        Err(e) => {
+            log::error!("ignoring the category configuration: {}", e);
+            ret = make_registry();
+        }
+    }
+    ret
+});
+
+pub fn registry() -> &'static [OSMCategory] {
+    &REGISTRY
+}
+
+fn make_registry() -> Vec<OSMCategory> {
+    use OSMType::*;
+    vec![
+        category(
+            City,
+            "City",
+            "city",
+            vec![
+                filter("nwr", "place", "city"),
+                filter("nwr", "place", "town"),
+            ],
+            2000f64,
+            0,
+        ),
+        category(
+            Village,
+            "Village",
+            "village",
+            vec![filter("nwr", "place", "village")],
+            300f64,
+            2,
+        ),
+        category(
+            Hamlet,
+            "Hamlet",
+            "hamlet",
+            vec![filter("nwr", "place", "hamlet")],
+            300f64,
+            5,
+        ),
+        category(
+            MountainPass,
+            "Mountain pass",
+            "pass",
+            vec![filter("node", "mountain_pass", "yes")],
+            300f64,
+            1,
+        ),
+        category(
+            Peak,
+            "Peak",
+            "peak",
+            vec![filter("node", "natural", "peak")],
+            300f64,
+            1,
+        ),
+        category(
+            DrinkingWater,
+            "Drinking water",
+            "water",
+            vec![
+                filter("node", "amenity", "drinking_water"),
+                filter("node", "amenity", "water_point"),
+            ],
+            100f64,
+            3,
+        ),
+        category(
+            Fuel,
+            "Fuel station",
+            "fuel",
+            vec![filter("node", "amenity", "fuel")],
+            200f64,
+            4,
+        ),
+        category(
+            Supermarket,
+            "Supermarket",
+            "shop",
+            vec![
+                filter("node", "shop", "supermarket"),
+                filter("node", "shop", "convenience"),
+            ],
+            200f64,
+            3,
+        ),
+        category(
+            Bakery,
+            "Bakery",
+            "bakery",
+            vec![filter("node", "shop", "bakery")],
+            200f64,
+            3,
+        ),
+        category(
+            BicycleShop,
+            "Bike shop",
+            "bicycle",
+            vec![filter("node", "shop", "bicycle")],
+            300f64,
+            4,
+        ),
+        category(
+            Toilets,
+            "Toilets",
+            "toilets",
+            vec![filter("node", "amenity", "toilets")],
+            100f64,
+            6,
+        ),
+        category(
+            Shelter,
+            "Shelter",
+            "shelter",
+            vec![filter("node", "amenity", "shelter")],
+            200f64,
+            6,
+        ),
+        category(
+            Campsite,
+            "Campsite",
+            "camping",
+            vec![filter("node", "tourism", "camp_site")],
+            500f64,
+            5,
+        ),
+    ]
+}
+
+#[derive(Deserialize)]
+struct CategoryConfig {
+    kind: OSMType,
+    name: Option<String>,
+    icon: Option<String>,
+    filters: Option<Vec<OSMTagFilter>>,
+    max_distance: Option<f64>,
+    priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct CategoriesConfig {
+    #[serde(default)]
+    category: Vec<CategoryConfig>,
+}
+
+fn invalid(message: String) -> Error {
+    Error::ConfigInvalid { message }
+}
+
+// changes the categories of the registry with the ones of the toml content.
+fn configure(registry: &mut [OSMCategory], content: &str) -> Result<(), Error> {
+    let config: CategoriesConfig = toml::from_str(content).map_err(|e| invalid(e.to_string()))?;
+    for c in config.category {
+        let category = registry
+            .iter_mut()
+            .find(|r| r.kind == c.kind)
+            .ok_or_else(|| invalid(format!("{:?}: unknown kind", c.kind)))?;
+        if let Some(name) = c.name {
+            category.name = name;
+        }
+        if let Some(icon) = c.icon {
+            category.icon = icon;
+        }
+        if let Some(filters) = c.filters {
+            if filters.is_empty() {
+                return Err(invalid(format!("{:?}: no filters", c.kind)));
+            }
+            if let Some(f) = filters
+                .iter()
+                .find(|f| f.element != "node" && f.element != "nwr")
+            {
+                return Err(invalid(format!(
+                    "{:?}: element must be node or nwr, not {}",
+                    c.kind, f.element
+                )));
+            }
+            category.filters = filters;
+        }
+        if let Some(max_distance) = c.max_distance {
+            if max_distance.is_nan() || max_distance <= 0f64 {
+                return Err(invalid(format!(
+                    "{:?}: max_distance must be positive",
+                    c.kind
+                )));
+            }
+            category.max_distance = max_distance;
+        }
+        if let Some(priority) = c.priority {
+            category.priority = priority;
+        }
+    }
+    Ok(())
+}
+
+fn read_user_config(registry: &mut [OSMCategory]) -> Result<(), Error> {
+    let path = match crate::config::user_categories_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(()),
+    };
+    log::info!("read categories from {}", path.display());
+    let content = std::fs::read_to_string(&path).map_err(|e| Error::IOError {
+        message: format!("could not read {}: {}", path.display(), e),
+    })?;
+    configure(registry, &content).map_err(|e| match e {
+        Error::ConfigInvalid { message } => invalid(format!("{}: {}", path.display(), message)),
+        _ => e,
+    })
+}
+
+pub fn get(kind: &OSMType) -> &'static OSMCategory {
+    registry().iter().find(|c| c.kind == *kind).unwrap()
+}
+
+pub fn classify(tags: &Tags) -> Option<OSMType> {
+    registry()
+        .iter()
+        .find(|c| c.matches(tags))
+        .map(|c| c.kind.clone())
+}
+
+// the categories shown by default (the ones of the first versions).
+pub fn default_selection() -> std::collections::HashSet<OSMType> {
+    use OSMType::*;
+    std::collections::HashSet::from([City, Village, Hamlet, MountainPass, Peak])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_tags() {
+        let tags = |k: &str, v: &str| Tags::from([(k.to_string(), v.to_string())]);
+        assert_eq!(classify(&tags("place", "town")), Some(OSMType::City));
+        assert_eq!(classify(&tags("natural", "peak")), Some(OSMType::Peak));
+        assert_eq!(
+            classify(&tags("amenity", "water_point")),
+            Some(OSMType::DrinkingWater)
+        );
+        assert_eq!(classify(&tags("amenity", "bench")), None);
+        // every kind has exactly one category
+        for c in registry() {
+            assert_eq!(get(&c.kind).name, c.name);
+            assert!(!c.filters.is_empty());
+        }
+        assert!(get(&OSMType::Hamlet).is_place());
+        assert!(!get(&OSMType::Bakery).is_place());
+    }
+
+    #[test]
+    fn configuration() {
+        let mut registry = make_registry();
+        let content = r#"
+            [[category]]
+            kind = "DrinkingWater"
+            name = "Water"
+            max_distance = 300.0
+            filters = [{ element = "node", key = "amenity", value = "drinking_water" }]
+        "#;
+        configure(&mut registry, content).unwrap();
+        let water = registry
+            .iter()
+            .find(|c| c.kind == OSMType::DrinkingWater)
+            .unwrap();
+        assert_eq!(water.name, "Water");
+        assert_eq!(water.max_distance, 300f64);
+        assert_eq!(water.filters.len(), 1);
+        // unchanged
+        assert_eq!(water.icon, "water");
+        assert_eq!(water.priority, 3);
+
+        for content in [
+            "[[category]]\nkind = \"Pub\"",
+            "[[category]]\nkind = \"Fuel\"\nfilters = []",
+            "[[category]]\nkind = \"Fuel\"\nmax_distance = -1.0",
+            "[[category]]\nkind = \"Fuel\"\nfilters = [{ element = \"way\", key = \"a\", value = \"b\" }]",
+        ] {
+            assert!(matches!(
+                configure(&mut registry, content),
+                Err(Error::ConfigInvalid { .. })
+            ));
+        }
+    }
+}
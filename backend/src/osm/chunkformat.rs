@@ -0,0 +1,223 @@
+use std::io::{Read, Write};
+
+use serde::Deserialize;
+
+use crate::error::{GenericError, GenericResult};
+use crate::inputpoint::{InputPoint, InputPointMap, Tags};
+use crate::mercator::MercatorPoint;
+use crate::tile::Tile;
+use crate::track_projection::TrackProjections;
+use crate::wgs84point::WGS84Point;
+
+/*
+ * The formats of the cache chunks:
+ *  - version 1: json list of (tile, points),
+ *  - version 2: json object with the version, the timestamp and the tiles,
+ *  - version 3: "WPXC", the version (u32) and the zlib compressed binary
+ *    data: the timestamp, then for each tile its coordinates and its
+ *    points (wgs84, euclidean and tags).
+ * The track projections are not stored, they depend on the track and are
+ * computed after loading. Only version 3 is written.
+ * Numbers are little endian, strings and lists are prefixed with their
+ * length (u32).
+ */
+
+pub const VERSION: u32 = 3;
+
+const MAGIC: &[u8; 4] = b"WPXC";
+
+fn invalid(what: &str) -> GenericError {
+    GenericError::from(format!("invalid cache chunk: {}", what))
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_i64(out: &mut Vec<u8>, v: i64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_f64(out: &mut Vec<u8>, v: f64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> GenericResult<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("truncated"));
+        }
+        let (ret, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(ret)
+    }
+    fn u32(&mut self) -> GenericResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn i64(&mut self) -> GenericResult<i64> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    fn f64(&mut self) -> GenericResult<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+    fn string(&mut self) -> GenericResult<String> {
+        let n = self.u32()? as usize;
+        let bytes = self.bytes(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("string"))
+    }
+}
+
+fn encode_point(out: &mut Vec<u8>, point: &InputPoint) {
+    put_f64(out, point.wgs84.longitude());
+    put_f64(out, point.wgs84.latitude());
+    put_f64(out, point.wgs84.z());
+    put_f64(out, point.euclidean.x());
+    put_f64(out, point.euclidean.y());
+    put_u32(out, point.tags.len() as u32);
+    for (key, value) in &point.tags {
+        put_string(out, key);
+        put_string(out, value);
+    }
+}
+
+fn decode_point(reader: &mut Reader) -> GenericResult<InputPoint> {
+    let (lon, lat, ele) = (reader.f64()?, reader.f64()?, reader.f64()?);
+    let (x, y) = (reader.f64()?, reader.f64()?);
+    let mut tags = Tags::new();
+    for _ in 0..reader.u32()? {
+        let key = reader.string()?;
+        tags.insert(key, reader.string()?);
+    }
+    Ok(InputPoint {
+        wgs84: WGS84Point::new(&lon, &lat, &ele),
+        euclidean: MercatorPoint(x, y),
+        tags,
+        track_projections: TrackProjections::new(),
+    })
+}
+
+pub fn encode(map: &InputPointMap, timestamp: i64) -> Vec<u8> {
+    let mut data = Vec::new();
+    put_i64(&mut data, timestamp);
+    put_u32(&mut data, map.map.len() as u32);
+    for (tile, points) in &map.map {
+        put_i64(&mut data, tile.coord.0 as i64);
+        put_i64(&mut data, tile.coord.1 as i64);
+        put_u32(&mut data, points.len() as u32);
+        for point in points {
+            encode_point(&mut data, point);
+        }
+    }
+    let mut ret = MAGIC.to_vec();
+    put_u32(&mut ret, VERSION);
+    let mut encoder = flate2::write::ZlibEncoder::new(ret, flate2::Compression::default());
+    encoder.write_all(&data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decode_binary(bytes: &[u8]) -> GenericResult<(InputPointMap, i64)> {
+    let mut data = Vec::new();
+    flate2::read::ZlibDecoder::new(bytes)
+        .read_to_end(&mut data)
+        .map_err(|_| invalid("compression"))?;
+    let mut reader = Reader { data: &data };
+    let timestamp = reader.i64()?;
+    let mut map = InputPointMap::new();
+    for _ in 0..reader.u32()? {
+        let coord = (reader.i64()? as isize, reader.i64()? as isize);
+        let n = reader.u32()? as usize;
+        let mut points = Vec::with_capacity(n.min(reader.data.len()));
+        for _ in 0..n {
+            points.push(decode_point(&mut reader)?);
+        }
+        map.map.insert(Tile { coord }, points);
+    }
+    Ok((map, timestamp))
+}
+
+#[derive(Deserialize)]
+struct JsonChunk {
+    version: u32,
+    timestamp: i64,
+    tiles: Vec<(Tile, Vec<InputPoint>)>,
+}
+
+// the timestamp is None for version 1 chunks.
+pub fn decode(bytes: &[u8]) -> GenericResult<(InputPointMap, Option<i64>)> {
+    if let Some(rest) = bytes.strip_prefix(MAGIC) {
+        let mut reader = Reader { data: rest };
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let (map, timestamp) = decode_binary(reader.data)?;
+        return Ok((map, Some(timestamp)));
+    }
+    let text = std::str::from_utf8(bytes).map_err(|_| invalid("unknown format"))?;
+    if text.trim_start().starts_with('[') {
+        return Ok((InputPointMap::from_string(text)?, None));
+    }
+    let chunk: JsonChunk = serde_json::from_str(text)?;
+    if chunk.version != 2 {
+        return Err(invalid(&format!("unsupported version {}", chunk.version)));
+    }
+    let map = InputPointMap {
+        map: chunk.tiles.into_iter().collect(),
+    };
+    Ok((map, Some(chunk.timestamp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> InputPointMap {
+        let mut tags = Tags::new();
+        tags.insert("name".to_string(), "Feldberg".to_string());
+        tags.insert("natural".to_string(), "peak".to_string());
+        let point = InputPoint {
+            wgs84: WGS84Point::new(&8.004, &47.874, &1493.0),
+            euclidean: MercatorPoint(891000.5, 6082000.25),
+            tags,
+            track_projections: TrackProjections::new(),
+        };
+        let mut map = InputPointMap::new();
+        map.map.insert(Tile { coord: (89, 609) }, vec![point]);
+        map.map.insert(Tile { coord: (-1, 609) }, Vec::new());
+        map
+    }
+
+    #[test]
+    fn formats() {
+        let map = points();
+        let bytes = encode(&map, 1000);
+        let (copy, timestamp) = decode(&bytes).unwrap();
+        assert_eq!(timestamp, Some(1000));
+        assert!(copy.map == map.map);
+        // version 1
+        let (copy, timestamp) = decode(map.as_string().unwrap().as_bytes()).unwrap();
+        assert_eq!(timestamp, None);
+        assert!(copy.map == map.map);
+        // version 2
+        let tiles: Vec<_> = map.map.iter().collect();
+        let v2 = serde_json::json!({"version": 2, "timestamp": 7, "tiles": tiles});
+        let (copy, timestamp) = decode(v2.to_string().as_bytes()).unwrap();
+        assert_eq!(timestamp, Some(7));
+        assert!(copy.map == map.map);
+        // unknown versions and broken data
+        let mut newer = bytes.clone();
+        newer[4] = 99;
+        assert!(decode(&newer).is_err());
+        assert!(decode(&bytes[..bytes.len() - 4]).is_err());
+        assert!(decode(b"{\"version\": 99}").is_err());
+    }
+}
@@ -1,72 +1,156 @@
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde_json::Value;
 
 use crate::{
+    cancel::sleep,
     event::{self, SenderHandlerLock},
     inputpoint::{InputPoint, InputPoints, Tags},
     mercator,
+    osm::category,
     track_projection::TrackProjections,
     wgs84point::WGS84Point,
 };
 
 use log;
 
-#[cfg(target_arch = "wasm32")]
-fn use_disk() -> bool {
-    false
+/*
+ * The overpass servers are tried in order. A server that answers 429 (too
+ * many requests) or 504 (timeout) is retried after an exponential
+ * backoff, other errors go to the next server.
+ * With a recording directory, the responses are stored (Record) or read
+ * instead of downloaded (Replay), one file per request.
+ */
+
+#[derive(Clone, Debug)]
+pub enum Recording {
+    Off,
+    Record(String),
+    Replay(String),
 }
 
-/* For debugging:
- * use_disk = true => write download in /tmp/last-dl.data
- *                 => read download from /tmp/dl.data if exists
- */
+#[derive(Clone, Debug)]
+pub struct OverpassConfig {
+    pub endpoints: Vec<String>,
+    pub user_agent: String,
+    // attempts per endpoint on 429/504
+    pub max_attempts: usize,
+    pub initial_backoff_ms: u64,
+    pub recording: Recording,
+}
 
-#[cfg(not(target_arch = "wasm32"))]
-fn use_disk() -> bool {
-    // true
-    false
+impl Default for OverpassConfig {
+    fn default() -> OverpassConfig {
+        OverpassConfig {
+            endpoints: vec![
+                "https://overpass-api.de/api/interpreter".to_string(),
+                "https://overpass.private.coffee/api/interpreter".to_string(),
+                "https://maps.mail.ru/osm/tools/overpass/api/interpreter".to_string(),
+            ],
+            user_agent: format!(
+                "WPX/{} (https://github.com/Julien5/wpx)",
+                env!("CARGO_PKG_VERSION")
+            ),
+            max_attempts: 3,
+            initial_backoff_ms: 2000,
+            recording: Recording::Off,
+        }
+    }
 }
 
-async fn dl_worker(req: &str) -> std::result::Result<String, reqwest::Error> {
-    log::info!("download:{}", req);
-    let url = "https://overpass-api.de/api/interpreter";
-    // let url = "https://overpass.private.coffee/api/interpreter";
-    let client = Client::new();
+// FNV-1a, stable across runs and platforms (unlike DefaultHasher).
+fn request_key(request: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in request.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}.json", hash)
+}
+
+fn recording_path(directory: &str, request: &str) -> std::path::PathBuf {
+    std::path::Path::new(directory).join(request_key(request))
+}
+
+fn io_error(message: String) -> std::io::Error {
+    std::io::Error::other(message)
+}
+
+async fn post(
+    client: &Client,
+    url: &str,
+    req: &str,
+    config: &OverpassConfig,
+) -> std::result::Result<String, (Option<StatusCode>, std::io::Error)> {
     let request = client
         .post(url)
-        .header("User-Agent", "jbo/WPX")
-        .header("Accept", "*/*")
-        .header("Accept-Language", "en-US,en;q=0.5")
-        .header("Accept-Encoding", "gzip, deflate, br, zstd")
+        .header("User-Agent", &config.user_agent)
         .header(
             "Content-Type",
             "application/x-www-form-urlencoded; charset=UTF-8",
         )
-        .header("Origin", "https://overpass-turbo.eu")
-        .header("Connection", "keep-alive")
-        .header("Referer", "https://overpass-turbo.eu/")
-        .header("Sec-Fetch-Dest", "empty")
-        .header("Sec-Fetch-Mode", "cors")
-        .header("Sec-Fetch-Site", "cross-site")
-        .header("Priority", "u=0")
-        .body(format!("data={}", urlencoding::encode(&req)));
+        .body(format!("data={}", urlencoding::encode(req)));
     log::debug!("request={:?}", request);
-    match request.send().await {
-        Ok(response) => {
-            let text = response.text().await;
-            if use_disk() {
-                let filename = std::format!("/tmp/last-dl.data");
-                let data = text.as_ref().unwrap().clone();
-                // write overwrites.
-                std::fs::write(filename, data).expect("Unable to write file");
-            }
-            match text {
-                Ok(json) => Ok(json),
-                Err(e) => Err(e),
+    let response = request
+        .send()
+        .await
+        .map_err(|e| (None, io_error(e.to_string())))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err((
+            Some(status),
+            io_error(format!("{} answered {}", url, status)),
+        ));
+    }
+    response
+        .text()
+        .await
+        .map_err(|e| (None, io_error(e.to_string())))
+}
+
+async fn dl_worker(req: &str, config: &OverpassConfig) -> std::io::Result<String> {
+    log::info!("download:{}", req);
+    if let Recording::Replay(directory) = &config.recording {
+        let path = recording_path(directory, req);
+        log::info!("replay {}", path.display());
+        // no download, no retry: a missing recording is an error.
+        return std::fs::read_to_string(&path)
+            .map_err(|e| io_error(format!("no recording {}: {}", path.display(), e)));
+    }
+    let client = Client::new();
+    let mut last_error = io_error("no overpass endpoint".to_string());
+    for url in &config.endpoints {
+        let mut backoff = config.initial_backoff_ms;
+        for attempt in 1..=config.max_attempts.max(1) {
+            match post(&client, url, req, config).await {
+                Ok(text) => {
+                    if let Recording::Record(directory) = &config.recording {
+                        let path = recording_path(directory, req);
+                        log::info!("record {}", path.display());
+                        let _ = std::fs::create_dir_all(directory);
+                        // the download is good, even if it cannot be recorded.
+                        if let Err(e) = std::fs::write(&path, &text) {
+                            log::error!("could not record {}: {}", path.display(), e);
+                        }
+                    }
+                    return Ok(text);
+                }
+                Err((status, e)) => {
+                    log::warn!("{} (attempt {})", e, attempt);
+                    last_error = e;
+                    let busy = matches!(
+                        status,
+                        Some(StatusCode::TOO_MANY_REQUESTS) | Some(StatusCode::GATEWAY_TIMEOUT)
+                    );
+                    if !busy || attempt == config.max_attempts {
+                        break;
+                    }
+                    sleep(std::time::Duration::from_millis(backoff)).await;
+                    backoff *= 2;
+                }
             }
         }
-        Err(e) => Err(e),
     }
+    Err(last_error)
 }
 
 /*
@@ -75,34 +159,36 @@ Grabener Höhe is tourism = viewpoint.
 To get it: node["tourism"="viewpoint"]({{bbox}});
 */
 
-pub async fn all(
-    bbox: &str,
-    logger: &SenderHandlerLock,
-) -> std::result::Result<String, reqwest::Error> {
-    if use_disk() {
-        //let data = std::fs::read_to_string("data/ref/overpass/dl.txt").unwrap();
-        //let data = std::fs::read_to_string("/tmp/dl.data").unwrap();
-        match std::fs::read_to_string("/tmp/dl.data") {
-            Ok(bytes) => {
-                return Ok(bytes);
-            }
-            Err(_) => {}
+/*
+ * All the categories of the registry are downloaded, so that the cached
+ * tiles do not depend on the selection in the parameters.
+ */
+fn query_body(bbox: &str) -> String {
+    let mut reqs = Vec::new();
+    for category in category::registry() {
+        for query in category.queries() {
+            reqs.push(format!("{}{}", query, bbox));
         }
     }
+    reqs.join(";")
+}
+
+fn query(bbox: &str) -> String {
     let timeout = 250;
     let header = format!("[out:json][timeout:{}]", timeout);
-    let mut reqs = Vec::new();
-    reqs.push(format!("node[\"mountain_pass\"=\"yes\"]{}", bbox));
-    reqs.push(format!("node[\"natural\"=\"peak\"]{}", bbox));
-    reqs.push(format!("nwr[\"place\"=\"city\"]{}", bbox));
-    reqs.push(format!("nwr[\"place\"=\"town\"]{}", bbox));
-    reqs.push(format!("nwr[\"place\"=\"village\"]{}", bbox));
-    reqs.push(format!("nwr[\"place\"=\"hamlet\"]{}", bbox));
-    let body = reqs.join(";");
     let footer = "out geom".to_string();
-    let request = format!("{};({};);{};", header, body, footer);
-    event::send_worker(logger, &format!("{}", "send request")).await;
-    dl_worker(&request).await
+    format!("{};({};);{};", header, query_body(bbox), footer)
+}
+
+pub async fn all(
+    bbox: &str,
+    config: &OverpassConfig,
+    logger: &SenderHandlerLock,
+) -> std::io::Result<String> {
+    let ret = dl_worker(&query(bbox), config).await?;
+    let bytes = ret.len() as u64;
+    event::send_worker(logger, &event::Event::BytesDownloaded { bytes }).await;
+    Ok(ret)
 }
 
 fn read_f64(map: &serde_json::Map<String, Value>, name: &str) -> f64 {
@@ -183,6 +269,32 @@ mod tests {
     use crate::{math::Point2D, track::WGS84BoundingBox};
 
     use super::*;
+    #[test]
+    fn overpass_query() {
+        let body = query_body("(1,2,3,4)");
+        assert!(body.starts_with("nwr[\"place\"=\"city\"](1,2,3,4);"));
+        assert!(body.contains("node[\"shop\"=\"bakery\"](1,2,3,4)"));
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().to_str().unwrap().to_string();
+        let bbox = "(47.86,9.66,48.17,10.80)";
+        let mut config = OverpassConfig::default();
+        // no network: the endpoint must not be used.
+        config.endpoints = vec!["http://localhost:1/api/interpreter".to_string()];
+        config.recording = Recording::Replay(path.clone());
+        let logger = std::sync::RwLock::new(None);
+        assert!(all(bbox, &config, &logger).await.is_err());
+        let content = "{\"elements\": []}";
+        std::fs::write(recording_path(&path, &query(bbox)), content).unwrap();
+        assert_eq!(all(bbox, &config, &logger).await.unwrap(), content);
+        // the key depends on the request only
+        assert_eq!(request_key("abc"), request_key("abc"));
+        assert_ne!(request_key("abc"), request_key("abd"));
+    }
+
     #[test]
     fn download() {
         //let bbox = "(47.86,9.66,48.17,10.80)";
@@ -2,9 +2,9 @@ use std::path::{Path, PathBuf};
 
 use crate::error::{GenericError, GenericResult};
 
-pub fn read(path: &String) -> GenericResult<String> {
+pub fn read(path: &String) -> GenericResult<Vec<u8>> {
     let path = Path::new(path.as_str());
-    match std::fs::read_to_string(path) {
+    match std::fs::read(path) {
         Ok(data) => Ok(data),
         Err(e) => Err(GenericError::from(e)),
     }
@@ -19,7 +19,20 @@ pub fn hit_cache(path: &String) -> bool {
     true
 }
 
-pub fn write(path: &String, data: String) {
+// the file names in the directory
+pub fn list(path: &str) -> Vec<String> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_string()))
+        .collect()
+}
+
+pub fn write(path: &String, data: Vec<u8>) {
     let pathbuf = PathBuf::from(&path);
     let dirname = pathbuf.parent().unwrap().to_str().unwrap();
     let _ = std::fs::create_dir_all(dirname);
@@ -1,6 +1,9 @@
 use indexed_db_futures::database::Database;
 use indexed_db_futures::prelude::*;
+use indexed_db_futures::primitive::TryFromJs;
 use indexed_db_futures::transaction::TransactionMode;
+use indexed_db_futures::typed_array::Uint8Array;
+use wasm_bindgen::JsValue;
 
 use crate::error::GenericError;
 use crate::error::GenericResult;
@@ -57,7 +60,7 @@ async fn opendb() -> GenericResult<Database> {
     }
 }
 
-async fn awrite(filename: &str, data: String) {
+async fn awrite(filename: &str, data: Vec<u8>) {
     let db = match opendb().await {
         Ok(db) => db,
         Err(e) => {
@@ -74,7 +77,11 @@ async fn awrite(filename: &str, data: String) {
     match transaction {
         Ok(t) => {
             let store = t.object_store(STORE).unwrap();
-            match store.put(data).with_key(identifier(filename)).await {
+            match store
+                .put(Uint8Array::from(data))
+                .with_key(identifier(filename))
+                .await
+            {
                 Ok(s) => {
                     log::info!("write: {}", s);
                 }
@@ -105,11 +112,11 @@ async fn awrite(filename: &str, data: String) {
     }
 }
 
-pub async fn write(filename: &str, data: String) {
+pub async fn write(filename: &str, data: Vec<u8>) {
     let _ = awrite(filename, data).await;
 }
 
-async fn aread(filename: &str) -> GenericResult<String> {
+async fn aread(filename: &str) -> GenericResult<Vec<u8>> {
     let db = match opendb().await {
         Ok(db) => db,
         Err(e) => {
@@ -129,9 +136,17 @@ async fn aread(filename: &str) -> GenericResult<String> {
     };
 
     let store = transaction.object_store(STORE).unwrap();
-    let data = store.get(identifier(&filename)).await.unwrap();
+    let data: Option<JsValue> = store.get(identifier(&filename)).await.unwrap();
     match data {
-        Some(bytes) => Ok(bytes),
+        // the json chunks were stored as strings
+        Some(value) if value.is_string() => Ok(value.as_string().unwrap().into_bytes()),
+        Some(value) => match Uint8Array::from_js(value) {
+            Ok(array) => Ok(array.as_ref().to_vec()),
+            Err(e) => Err(GenericError::from(format!(
+                "could not read {}: {:?}",
+                filename, e
+            ))),
+        },
         None => Err(GenericError::from(format!("could not read {}", filename))),
     }
 }
@@ -160,7 +175,50 @@ async fn ahit_cache(filename: &str) -> bool {
     data.is_some()
 }
 
-pub async fn read(filename: &str) -> GenericResult<String> {
+async fn aremove(filename: &str) -> GenericResult<()> {
+    let db = opendb().await?;
+    let transaction = db
+        .transaction(STORE)
+        .with_mode(TransactionMode::Readwrite)
+        .build()?;
+    let store = transaction.object_store(STORE)?;
+    store.delete(identifier(filename)).await?;
+    Ok(())
+}
+
+// the filenames, assuming they were valid identifiers (see identifier())
+async fn alist() -> GenericResult<Vec<String>> {
+    let db = opendb().await?;
+    let transaction = db
+        .transaction(STORE)
+        .with_mode(TransactionMode::Readonly)
+        .build()?;
+    let store = transaction.object_store(STORE)?;
+    let keys: Vec<String> = store.get_all_keys().await?.collect::<Result<_, _>>()?;
+    Ok(keys
+        .iter()
+        .filter_map(|key| key.strip_prefix("id-"))
+        .map(|key| key.to_string())
+        .collect())
+}
+
+pub async fn remove(filename: &str) {
+    if let Err(e) = aremove(filename).await {
+        log::info!("could not remove {}: {:?}", filename, e);
+    }
+}
+
+pub async fn list() -> Vec<String> {
+    match alist().await {
+        Ok(keys) => keys,
+        Err(e) => {
+            log::info!("could not list the keys: {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn read(filename: &str) -> GenericResult<Vec<u8>> {
     aread(filename).await
 }
 
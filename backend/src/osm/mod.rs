@@ -1,16 +1,26 @@
 mod cache;
+pub mod category;
+mod chunkformat;
 mod download;
 mod filesystem;
 #[cfg(target_arch = "wasm32")]
 mod indexdb;
 pub mod osmpoint;
+mod pbf;
 
-use crate::error::{GenericError, GenericResult};
-use crate::event::SenderHandlerLock;
+pub use cache::{CacheConfig, CacheStats};
+pub use download::{OverpassConfig, Recording};
+
+use std::collections::BTreeMap;
+
+use crate::cancel::CancelToken;
+use crate::error::{Error, GenericError, GenericResult};
+use crate::event::{Event, SenderHandlerLock, Stage};
 use crate::inputpoint::{InputPointMap, InputPoints};
 use crate::mercator::EuclideanBoundingBox;
 use crate::osm::cache::{tiles_from_bbox, MissingTiles};
 use crate::track::*;
+use crate::wgs84point::WGS84Point;
 use crate::{event, tile::*};
 
 // 5 digits are enough for 1-meter precision.
@@ -26,11 +36,12 @@ fn osm3(bbox: &WGS84BoundingBox) -> String {
 
 async fn download_chunk_real(
     bbox: &WGS84BoundingBox,
+    config: &OverpassConfig,
     logger: &SenderHandlerLock,
 ) -> std::result::Result<InputPoints, std::io::Error> {
     use download::*;
     let bboxparam = osm3(&bbox);
-    let dl_result = all(&bboxparam, logger).await;
+    let dl_result = all(&bboxparam, config, logger).await;
     match dl_result {
         Err(e) => {
             log::error!("download failed, error = {:?}", e);
@@ -57,45 +68,113 @@ async fn download_chunk_real(
     }
 }
 
-async fn download_tiles(
+/*
+ * The missing tiles are downloaded in batches of at most
+ * BATCH_WIDTH x BATCH_WIDTH tiles, so that the requests stay small even
+ * for long diagonal tracks. A few batches run at the same time, each one
+ * is written in the cache as soon as it is done. The retries are done by
+ * the download (see download.rs), a batch that fails is not retried.
+ */
+
+// tiles per side of a batch (CHUNKWIDTH is a multiple of it).
+const BATCH_WIDTH: isize = 5;
+const MAX_CONCURRENT_DOWNLOADS: usize = 2;
+
+fn batches(tiles: &MissingTiles) -> Vec<MissingTiles> {
+    let mut ret: BTreeMap<(isize, isize), MissingTiles> = BTreeMap::new();
+    for tile in tiles {
+        let key = (
+            tile.coord.0.div_euclid(BATCH_WIDTH),
+            tile.coord.1.div_euclid(BATCH_WIDTH),
+        );
+        ret.entry(key).or_default().insert(tile.clone());
+    }
+    ret.into_values().collect()
+}
+
+async fn download_batch(
     tiles: &MissingTiles,
+    config: &OverpassConfig,
     logger: &SenderHandlerLock,
-) -> GenericResult<InputPoints> {
-    if tiles.is_empty() {
-        return Ok(InputPoints::new());
+) -> GenericResult<InputPointMap> {
+    let wgsbbox = bounding_box(tiles).unproject();
+    let points = download_chunk_real(&wgsbbox, config, logger).await?;
+    log::info!(
+        "downloaded {:3} points for {} tiles",
+        points.points.len(),
+        tiles.len()
+    );
+    let mut map = InputPointMap::from_vector(&points.points);
+    // tiles where there is no data must also have an entry in the
+    // map
+    let mut empty_tiles = 0;
+    for tile in tiles {
+        if !map.map.contains_key(tile) {
+            log::trace!("insert empty tile {}", tile.basename());
+            empty_tiles += 1;
+            map.map.insert(tile.clone(), Vec::new());
+        }
     }
-    let eucbbox = bounding_box(tiles);
-    let wgsbbox = eucbbox.unproject();
+    log::info!("inserted {} empty tiles", empty_tiles);
+    Ok(map)
+}
 
-    log::info!("downloading for {} tiles", tiles.len());
-    let mut empty_tiles = 0;
-    match download_chunk_real(&wgsbbox, logger).await {
-        Ok(points) => {
-            log::info!("downloaded {:3} points", points.points.len());
-            let mut map = InputPointMap::from_vector(&points.points);
-            // tiles where there is no data must also have an entry in the
-            // map
-            for tile in tiles {
-                if !map.map.contains_key(&tile) {
-                    log::info!("insert empty tile {}", tile.basename());
-                    empty_tiles += 1;
-                    map.map.insert(tile.clone(), Vec::new());
-                }
+// returns the number of tiles that could not be downloaded.
+async fn download_tiles(
+    tiles: &MissingTiles,
+    config: &OverpassConfig,
+    cache_config: &CacheConfig,
+    cancel: &CancelToken,
+    logger: &SenderHandlerLock,
+) -> GenericResult<usize> {
+    use futures::StreamExt;
+    let batches = batches(tiles);
+    let total = batches.len();
+    log::info!("downloading {} tiles in {} batches", tiles.len(), total);
+    let mut results = futures::stream::iter(batches.iter())
+        .map(|batch| async move { (batch, download_batch(batch, config, logger).await) })
+        .buffer_unordered(MAX_CONCURRENT_DOWNLOADS);
+    let mut failed = 0;
+    let mut done = 0;
+    let mut fetched = 0;
+    // the cache is written here, one batch after the other. When
+    // cancelled, the running downloads are dropped but the batches
+    // already written stay in the cache.
+    while let Some((batch, result)) = cancel.run(results.next()).await? {
+        done += 1;
+        match result {
+            Ok(map) => {
+                cache::write(&map, cache_config).await?;
+                fetched += batch.len();
+                let total = tiles.len();
+                event::send_worker(
+                    logger,
+                    &Event::TilesFetched {
+                        done: fetched,
+                        total,
+                    },
+                )
+                .await;
+            }
+            Err(e) => {
+                log::error!("could not download {} tiles: {}", batch.len(), e);
+                failed += batch.len();
             }
-            log::info!("inserted {} empty tiles", empty_tiles);
-            cache::write(&map, logger).await?;
-            Ok(points)
-        }
-        Err(e) => {
-            log::info!("error downloading: {:?}", e);
-            log::info!("assuming there is nothing");
-            return Err(GenericError::from(e));
         }
+        let progress = Event::Progress {
+            stage: Stage::OSM,
+            fraction: done as f64 / total as f64,
+        };
+        event::send_worker(logger, &progress).await;
     }
+    Ok(failed)
 }
 
-async fn read(bbox: &EuclideanBoundingBox) -> GenericResult<(InputPointMap, MissingTiles)> {
-    cache::read(bbox).await
+async fn read(
+    bbox: &EuclideanBoundingBox,
+    cache_config: &CacheConfig,
+) -> GenericResult<(InputPointMap, MissingTiles)> {
+    cache::read(bbox, cache_config).await
 }
 
 #[allow(dead_code)]
@@ -119,11 +198,15 @@ fn print_missing(missing: &MissingTiles) {
 
 async fn process(
     bbox: &EuclideanBoundingBox,
+    corridor: &Tiles,
+    config: &OverpassConfig,
+    cache_config: &CacheConfig,
+    cancel: &CancelToken,
     logger: &SenderHandlerLock,
 ) -> GenericResult<InputPointMap> {
     let mut found = InputPointMap::new();
     let mut missing = tiles_from_bbox(bbox);
-    match read(bbox).await {
+    match read(bbox, cache_config).await {
         Ok((local_found, local_missing)) => {
             found = local_found;
             missing = local_missing;
@@ -132,34 +215,37 @@ async fn process(
                 found.map.len(),
                 missing.len()
             );
-            //print_missing(&missing);
-            if missing.is_empty() {
-                log::info!("the cache is complete.");
-                return Ok(found);
-            }
         }
         Err(e) => {
             log::info!("error: {}", e);
             log::info!("the cache could not be read => update");
         }
     }
+    // only the tiles close to the track are needed.
+    missing.retain(|tile| corridor.contains(tile));
+    if missing.is_empty() {
+        log::info!("the cache is complete.");
+        return Ok(found);
+    }
 
     // download and write in cache
-    download_tiles(&missing, logger).await?;
+    let failed = download_tiles(&missing, config, cache_config, cancel, logger).await?;
+    if failed > 0 {
+        let message = format!("no osm data for {} tiles", failed);
+        event::send_worker(logger, &Event::Warning { message }).await;
+    }
 
-    match read(bbox).await {
-        Ok((map, missing)) => {
+    match read(bbox, cache_config).await {
+        Ok((map, mut missing)) => {
+            missing.retain(|tile| corridor.contains(tile));
             log::info!("found: {} missing: {}", map.map.len(), missing.len());
             if missing.is_empty() {
-                log::info!("the cache is complete with {} tiles", found.map.len());
-                return Ok(map);
+                log::info!("the cache is complete with {} tiles", map.map.len());
             } else {
+                // keep what could be downloaded
                 log::error!("the cache has still {} missing tiles", missing.len());
-                //print_missing(&missing);
-                return Err(GenericError::from(
-                    "still missing data in cache".to_string(),
-                ));
             }
+            Ok(map)
         }
         Err(e) => {
             log::error!("error: {}", e);
@@ -171,11 +257,95 @@ async fn process(
 
 pub async fn download_for_track(
     track: &Track,
+    config: &OverpassConfig,
+    cache_config: &CacheConfig,
+    cancel: &CancelToken,
     logger: &SenderHandlerLock,
 ) -> GenericResult<InputPointMap> {
     let bbox = track.euclidean_bounding_box();
     assert!(!bbox.empty());
-    event::send_worker(logger, &format!("{}", "download")).await;
-    let ret = process(&bbox, logger).await;
+    let ret = process(&bbox, &track.tiles, config, cache_config, cancel, logger).await;
     ret
 }
+
+// fills the cache for all tiles of the bounding box (not only along a track).
+pub async fn download_for_bbox(
+    bbox: &WGS84BoundingBox,
+    config: &OverpassConfig,
+    cache_config: &CacheConfig,
+    cancel: &CancelToken,
+    logger: &SenderHandlerLock,
+) -> GenericResult<InputPointMap> {
+    let proj = crate::mercator::WebMercatorProjection::make();
+    let (min, max) = (bbox.get_min(), bbox.get_max());
+    let min = proj.project(&WGS84Point::from_xy(&(min.x, min.y)));
+    let max = proj.project(&WGS84Point::from_xy(&(max.x, max.y)));
+    let bbox = EuclideanBoundingBox::minmax(min.point2d(), max.point2d());
+    if bbox.empty() {
+        return Err(GenericError::from("empty bounding box".to_string()));
+    }
+    let corridor = tiles_from_bbox(&bbox);
+    process(&bbox, &corridor, config, cache_config, cancel, logger).await
+}
+
+pub async fn cache_stats(config: &CacheConfig) -> CacheStats {
+    cache::stats(config).await
+}
+
+pub async fn clear_cache() {
+    cache::clear().await
+}
+
+/*
+ * Offline alternative to download_for_track: the points are read from a
+ * local .osm.pbf file and written in the cache for the tiles of the
+ * track, so that the next loads find them there. The token is checked
+ * between the blocks of the file.
+ */
+pub async fn extract_for_track(
+    track: &Track,
+    filename: &str,
+    cache_config: &CacheConfig,
+    cancel: &CancelToken,
+) -> GenericResult<InputPointMap> {
+    log::info!("read {}", filename);
+    // reading a country extract takes a while, not on the async threads.
+    let tiles = track.tiles.clone();
+    let filename = filename.to_string();
+    let cancel = cancel.clone();
+    let mut map = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&filename).map_err(|e| Error::IOError {
+            message: format!("could not read {}: {}", filename, e),
+        })?;
+        pbf::extract(std::io::BufReader::new(file), &tiles, &cancel)
+    })
+    .await??;
+    // like for downloads, tiles without points must be in the cache.
+    for tile in &track.tiles {
+        map.map.entry(tile.clone()).or_default();
+    }
+    cache::write(&map, cache_config).await?;
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn download_batches() {
+        // a diagonal line of tiles, crossing the origin
+        let tiles: MissingTiles = (-6..14).map(|k| Tile { coord: (k, k) }).collect();
+        let batches = batches(&tiles);
+        assert_eq!(batches.len(), 5);
+        assert_eq!(batches.iter().map(|b| b.len()).sum::<usize>(), tiles.len());
+        for batch in &batches {
+            let first = batch.first().unwrap();
+            assert!(batch.iter().all(
+                |t| t.coord.0.div_euclid(BATCH_WIDTH) == first.coord.0.div_euclid(BATCH_WIDTH)
+            ));
+        }
+        // a batch never spans two chunks
+        assert_eq!(CHUNKWIDTH as isize % BATCH_WIDTH, 0);
+    }
+}
@@ -0,0 +1,490 @@
+use std::io::Read;
+
+use crate::{
+    cancel::CancelToken,
+    error::Error,
+    inputpoint::{InputPoint, InputPointMap, Tags},
+    mercator::WebMercatorProjection,
+    osm::category,
+    tile::{Tile, Tiles},
+    track_projection::TrackProjections,
+    wgs84point::WGS84Point,
+};
+
+/*
+ * Reads the nodes of an .osm.pbf file (e.g. a Geofabrik extract).
+ * https://wiki.openstreetmap.org/wiki/PBF_Format
+ *
+ * The file is a sequence of blobs: 4 bytes (big endian) for the size of
+ * the BlobHeader, the BlobHeader, and the Blob. OSMData blobs contain a
+ * PrimitiveBlock with a string table and groups of nodes (plain or dense).
+ * Only the nodes with the tags of an OSM category are kept, like for the
+ * overpass download.
+ *
+ * Ways and relations are ignored: their position would need the
+ * coordinates of their nodes, which come earlier in the file. Unlike the
+ * overpass download ("nwr" categories, see category.rs), the places mapped
+ * only as an area are missing. Most places have a node (the usual tagging),
+ * the POI categories are nodes only.
+ */
+
+// the limits of the format, a larger size is a corrupt file.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+// a minimal protobuf reader, enough for the PBF messages.
+struct Message<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Message<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Message { data, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, Error> {
+        let mut ret = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.data.get(self.pos).ok_or(Error::PBFInvalid)?;
+            self.pos += 1;
+            ret |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(ret);
+            }
+        }
+        Err(Error::PBFInvalid)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), Error> {
+        if self.pos + n > self.data.len() {
+            return Err(Error::PBFInvalid);
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>, Error> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                let start = self.pos;
+                self.skip(len)?;
+                Value::Bytes(&self.data[start..start + len])
+            }
+            5 => {
+                self.skip(4)?;
+                Value::Fixed
+            }
+            _ => return Err(Error::PBFInvalid),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+fn zigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn packed(data: &[u8]) -> Result<Vec<u64>, Error> {
+    let mut message = Message::new(data);
+    let mut ret = Vec::new();
+    while message.pos < data.len() {
+        ret.push(message.varint()?);
+    }
+    Ok(ret)
+}
+
+fn packed_sint(data: &[u8]) -> Result<Vec<i64>, Error> {
+    Ok(packed(data)?.into_iter().map(zigzag).collect())
+}
+
+struct Block {
+    strings: Vec<String>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl Block {
+    fn string(&self, index: u64) -> Result<&String, Error> {
+        self.strings.get(index as usize).ok_or(Error::PBFInvalid)
+    }
+
+    fn coordinate(&self, offset: i64, value: i64) -> f64 {
+        1e-9 * (offset + self.granularity * value) as f64
+    }
+
+    fn tags(&self, keys: &[u64], values: &[u64]) -> Result<Tags, Error> {
+        let mut ret = Tags::new();
+        for (k, v) in keys.iter().zip(values.iter()) {
+            ret.insert(self.string(*k)?.clone(), self.string(*v)?.clone());
+        }
+        Ok(ret)
+    }
+}
+
+// the points found in the tiles.
+struct Extraction<'a> {
+    tiles: &'a Tiles,
+    projection: WebMercatorProjection,
+    points: InputPointMap,
+}
+
+impl Extraction<'_> {
+    fn add(&mut self, lon: f64, lat: f64, tags: Tags) {
+        if category::classify(&tags).is_none() {
+            return;
+        }
+        let wgs84 = WGS84Point::new_lonlat(&lon, &lat);
+        let euclidean = self.projection.project(&wgs84);
+        if !self.tiles.contains(&Tile::for_point(&euclidean)) {
+            return;
+        }
+        self.points.insert_point(&InputPoint {
+            wgs84,
+            euclidean,
+            tags,
+            track_projections: TrackProjections::new(),
+        });
+    }
+
+    fn node(&mut self, block: &Block, data: &[u8]) -> Result<(), Error> {
+        let mut message = Message::new(data);
+        let (mut keys, mut values) = (Vec::new(), Vec::new());
+        let (mut lat, mut lon) = (0i64, 0i64);
+        while let Some((field, value)) = message.next_field()? {
+            match (field, value) {
+                (2, Value::Bytes(b)) => keys = packed(b)?,
+                (3, Value::Bytes(b)) => values = packed(b)?,
+                (8, Value::Varint(v)) => lat = zigzag(v),
+                (9, Value::Varint(v)) => lon = zigzag(v),
+                _ => {}
+            }
+        }
+        let tags = block.tags(&keys, &values)?;
+        self.add(
+            block.coordinate(block.lon_offset, lon),
+            block.coordinate(block.lat_offset, lat),
+            tags,
+        );
+        Ok(())
+    }
+
+    fn dense(&mut self, block: &Block, data: &[u8]) -> Result<(), Error> {
+        let mut message = Message::new(data);
+        let (mut lats, mut lons, mut keys_vals) = (Vec::new(), Vec::new(), Vec::new());
+        while let Some((field, value)) = message.next_field()? {
+            match (field, value) {
+                (8, Value::Bytes(b)) => lats = packed_sint(b)?,
+                (9, Value::Bytes(b)) => lons = packed_sint(b)?,
+                (10, Value::Bytes(b)) => keys_vals = packed(b)?,
+                _ => {}
+            }
+        }
+        if lats.len() != lons.len() {
+            return Err(Error::PBFInvalid);
+        }
+        // keys_vals: (key, value)* 0 for each node, empty if no node has tags.
+        let mut kv = keys_vals.iter();
+        let (mut lat, mut lon) = (0i64, 0i64);
+        for k in 0..lats.len() {
+            lat += lats[k];
+            lon += lons[k];
+            let mut tags = Tags::new();
+            while let Some(key) = kv.next() {
+                if *key == 0 {
+                    break;
+                }
+                let value = kv.next().ok_or(Error::PBFInvalid)?;
+                tags.insert(block.string(*key)?.clone(), block.string(*value)?.clone());
+            }
+            if tags.is_empty() {
+                continue;
+            }
+            self.add(
+                block.coordinate(block.lon_offset, lon),
+                block.coordinate(block.lat_offset, lat),
+                tags,
+            );
+        }
+        Ok(())
+    }
+
+    fn primitive_block(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut block = Block {
+            strings: Vec::new(),
+            granularity: 100,
+            lat_offset: 0,
+            lon_offset: 0,
+        };
+        let mut groups = Vec::new();
+        let mut message = Message::new(data);
+        while let Some((field, value)) = message.next_field()? {
+            match (field, value) {
+                (1, Value::Bytes(b)) => {
+                    let mut table = Message::new(b);
+                    while let Some((_, value)) = table.next_field()? {
+                        if let Value::Bytes(s) = value {
+                            block.strings.push(String::from_utf8_lossy(s).to_string());
+                        }
+                    }
+                }
+                (2, Value::Bytes(b)) => groups.push(b),
+                (17, Value::Varint(v)) => block.granularity = v as i64,
+                (19, Value::Varint(v)) => block.lat_offset = v as i64,
+                (20, Value::Varint(v)) => block.lon_offset = v as i64,
+                _ => {}
+            }
+        }
+        // the groups come before the offsets in some writers.
+        for group in groups {
+            let mut message = Message::new(group);
+            while let Some((field, value)) = message.next_field()? {
+                match (field, value) {
+                    (1, Value::Bytes(b)) => self.node(&block, b)?,
+                    (2, Value::Bytes(b)) => self.dense(&block, b)?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn blob_data(blob: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut message = Message::new(blob);
+    while let Some((field, value)) = message.next_field()? {
+        match (field, value) {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3, Value::Bytes(zlib)) => {
+                let mut ret = Vec::new();
+                flate2::read::ZlibDecoder::new(zlib)
+                    .take(MAX_BLOB_SIZE as u64 + 1)
+                    .read_to_end(&mut ret)
+                    .map_err(|_| Error::PBFInvalid)?;
+                if ret.len() > MAX_BLOB_SIZE {
+                    return Err(Error::PBFInvalid);
+                }
+                return Ok(ret);
+            }
+            _ => {}
+        }
+    }
+    // lzma, zstd... are not supported.
+    log::error!("pbf: unsupported blob compression");
+    Err(Error::PBFInvalid)
+}
+
+fn read_exact_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> Result<bool, Error> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(_) => return Err(Error::PBFInvalid),
+        }
+    }
+    match read {
+        0 => Ok(false),
+        n if n == buffer.len() => Ok(true),
+        _ => Err(Error::PBFInvalid),
+    }
+}
+
+// the POIs of the pbf file in the given tiles, the token is checked
+// between the blocks.
+pub fn extract(
+    mut reader: impl Read,
+    tiles: &Tiles,
+    cancel: &CancelToken,
+) -> Result<InputPointMap, Error> {
+    let mut extraction = Extraction {
+        tiles,
+        projection: WebMercatorProjection::make(),
+        points: InputPointMap::new(),
+    };
+    let mut size = [0u8; 4];
+    let mut blocks = 0;
+    while read_exact_or_eof(&mut reader, &mut size)? {
+        cancel.check()?;
+        let headersize = u32::from_be_bytes(size) as usize;
+        if headersize > MAX_HEADER_SIZE {
+            return Err(Error::PBFInvalid);
+        }
+        let mut header = vec![0u8; headersize];
+        if !read_exact_or_eof(&mut reader, &mut header)? {
+            return Err(Error::PBFInvalid);
+        }
+        let mut kind = String::new();
+        let mut datasize = 0usize;
+        let mut message = Message::new(&header);
+        while let Some((field, value)) = message.next_field()? {
+            match (field, value) {
+                (1, Value::Bytes(b)) => kind = String::from_utf8_lossy(b).to_string(),
+                (3, Value::Varint(v)) => datasize = v as usize,
+                _ => {}
+            }
+        }
+        if datasize > MAX_BLOB_SIZE {
+            return Err(Error::PBFInvalid);
+        }
+        let mut blob = vec![0u8; datasize];
+        if datasize > 0 && !read_exact_or_eof(&mut reader, &mut blob)? {
+            return Err(Error::PBFInvalid);
+        }
+        if kind != "OSMData" {
+            continue;
+        }
+        extraction.primitive_block(&blob_data(&blob)?)?;
+        blocks += 1;
+    }
+    log::info!(
+        "pbf: read {} blocks, found {} points",
+        blocks,
+        extraction.points.iter().count()
+    );
+    Ok(extraction.points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // protobuf encoding, for the test data
+    fn varint(mut v: u64, out: &mut Vec<u8>) {
+        while v >= 0x80 {
+            out.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn field_varint(field: u64, v: u64, out: &mut Vec<u8>) {
+        varint(field << 3, out);
+        varint(v, out);
+    }
+
+    fn field_bytes(field: u64, data: &[u8], out: &mut Vec<u8>) {
+        varint((field << 3) | 2, out);
+        varint(data.len() as u64, out);
+        out.extend_from_slice(data);
+    }
+
+    fn sint(v: i64) -> u64 {
+        ((v << 1) ^ (v >> 63)) as u64
+    }
+
+    fn packed_field(field: u64, values: &[u64], out: &mut Vec<u8>) {
+        let mut data = Vec::new();
+        values.iter().for_each(|v| varint(*v, &mut data));
+        field_bytes(field, &data, out);
+    }
+
+    fn blob(kind: &str, data: &[u8], compress: bool) -> Vec<u8> {
+        let mut blob = Vec::new();
+        if compress {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            field_varint(2, data.len() as u64, &mut blob);
+            field_bytes(3, &encoder.finish().unwrap(), &mut blob);
+        } else {
+            field_bytes(1, data, &mut blob);
+        }
+        let mut header = Vec::new();
+        field_bytes(1, kind.as_bytes(), &mut header);
+        field_varint(3, blob.len() as u64, &mut header);
+        let mut ret = (header.len() as u32).to_be_bytes().to_vec();
+        ret.extend_from_slice(&header);
+        ret.extend_from_slice(&blob);
+        ret
+    }
+
+    // coordinates in 1e-7 degrees (granularity 100)
+    fn test_file(compress: bool) -> Vec<u8> {
+        let strings = ["", "amenity", "drinking_water", "name", "Brunnen", "bench"];
+        let mut table = Vec::new();
+        strings
+            .iter()
+            .for_each(|s| field_bytes(1, s.as_bytes(), &mut table));
+
+        // three dense nodes: water, bench (no category), untagged
+        let mut dense = Vec::new();
+        packed_field(1, &[sint(1), sint(1), sint(1)], &mut dense);
+        let lats = [479_990_000i64, 10, 10];
+        let lons = [78_500_000i64, 10, 10];
+        packed_field(8, &lats.map(sint), &mut dense);
+        packed_field(9, &lons.map(sint), &mut dense);
+        packed_field(10, &[1, 2, 3, 4, 0, 1, 5, 0, 0], &mut dense);
+        // a plain node: water, far away
+        let mut node = Vec::new();
+        field_varint(1, sint(10), &mut node);
+        packed_field(2, &[1], &mut node);
+        packed_field(3, &[2], &mut node);
+        field_varint(8, sint(-339_000_000), &mut node);
+        field_varint(9, sint(184_000_000), &mut node);
+
+        let mut group = Vec::new();
+        field_bytes(2, &dense, &mut group);
+        field_bytes(1, &node, &mut group);
+        let mut block = Vec::new();
+        field_bytes(1, &table, &mut block);
+        field_bytes(2, &group, &mut block);
+
+        let mut ret = blob("OSMHeader", b"", compress);
+        ret.extend(blob("OSMData", &block, compress));
+        ret
+    }
+
+    #[test]
+    fn extract_nodes() {
+        let projection = WebMercatorProjection::make();
+        let freiburg = projection.project(&WGS84Point::new_lonlat(&7.85, &47.999));
+        let tiles = Tiles::from([Tile::for_point(&freiburg)]);
+        let cancel = CancelToken::new();
+        for compress in [false, true] {
+            let map = extract(test_file(compress).as_slice(), &tiles, &cancel).unwrap();
+            let points: Vec<_> = map.iter().collect();
+            assert_eq!(points.len(), 1);
+            assert_eq!(points[0].name(), "Brunnen");
+            assert!((points[0].wgs84.latitude() - 47.999).abs() < 1e-6);
+            assert!((points[0].wgs84.longitude() - 7.85).abs() < 1e-6);
+        }
+        assert!(extract(&b"\x00\x00\x00\x10abc"[..], &tiles, &cancel).is_err());
+        // sizes above the limits, not allocated
+        assert!(matches!(
+            extract(&b"\xff\xff\xff\xffabc"[..], &tiles, &cancel),
+            Err(Error::PBFInvalid)
+        ));
+        let mut header = Vec::new();
+        field_varint(3, 1 << 40, &mut header);
+        let mut file = (header.len() as u32).to_be_bytes().to_vec();
+        file.extend_from_slice(&header);
+        assert!(matches!(
+            extract(file.as_slice(), &tiles, &cancel),
+            Err(Error::PBFInvalid)
+        ));
+        cancel.cancel();
+        assert!(matches!(
+            extract(test_file(false).as_slice(), &tiles, &cancel),
+            Err(Error::Cancelled)
+        ));
+    }
+}
@@ -1,17 +1,40 @@
-use crate::speed;
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+use crate::{
+    brevet, elevation,
+    error::Error,
+    format,
+    inputpoint::{InputPoint, InputType, OSMType},
+    mercator::DateTime,
+    osm::category,
+    pdf, speed,
+};
+
+#[derive(Debug, Clone, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ProfileIndication {
     None,
     GainTicks,
     NumericSlope,
 }
 
-#[derive(Clone)]
+// sets are written sorted, so that the same parameters give the same file.
+fn sorted<S: Serializer, T: Serialize + Ord>(
+    set: &std::collections::HashSet<T>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut items: Vec<_> = set.iter().collect();
+    items.sort();
+    serializer.collect_seq(items)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct UserStepsOptions {
     pub step_distance: Option<f64>,
     pub step_elevation_gain: Option<f64>,
     pub gpx_name_format: String,
+    // seconds spent at each user step
+    pub dwell: f64,
 }
 
 impl Default for UserStepsOptions {
@@ -20,12 +43,15 @@ impl Default for UserStepsOptions {
             step_distance: Some(10_000.0),
             step_elevation_gain: None,
             gpx_name_format: "NAME[*]-TIME[%H:%M]-SLOPE[4.1%]".to_string(),
+            dwell: 0f64,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProfileOptions {
+    #[serde(serialize_with = "sorted")]
     pub elevation_indicators: std::collections::HashSet<ProfileIndication>,
     pub max_area_ratio: f64,
 }
@@ -39,7 +65,38 @@ impl Default for ProfileOptions {
     }
 }
 
-#[derive(Clone)]
+// a column of the points table of the pdf.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableColumn {
+    pub title: String,
+    // the cell, with the placeholders of the gpx names, e.g. "TIME[%H:%M]"
+    pub format: String,
+}
+
+impl TableColumn {
+    fn new(title: &str, format: &str) -> TableColumn {
+        TableColumn {
+            title: title.to_string(),
+            format: format.to_string(),
+        }
+    }
+}
+
+// BUFFER[%H:%M] is empty without brevet_rules, it is not in the defaults.
+pub fn default_table_columns() -> Vec<TableColumn> {
+    vec![
+        TableColumn::new("KM", "DISTANCE[.0]"),
+        TableColumn::new("TIME", "TIME[%H:%M]"),
+        TableColumn::new("HM", "ELEVATION[.0] m"),
+        TableColumn::new("DIST", "LEG[.1]"),
+        TableColumn::new("D+", "LEGGAIN[.0]"),
+        TableColumn::new("SLOPE", "SLOPE[.1%]"),
+        TableColumn::new("DESCRIPTION", "DESCRIPTION"),
+    ]
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MapOptions {
     pub max_area_ratio: f64,
 }
@@ -52,32 +109,260 @@ impl Default for MapOptions {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Parameters {
+    pub brevet_rules: Option<brevet::BrevetRules>,
+    // meters, inferred from the track length if None
+    pub brevet_distance: Option<f64>,
     pub control_gpx_name_format: String,
+    // seconds spent at each control
+    pub control_dwell: f64,
     pub debug: bool,
+    pub elevation_gain: elevation::Gain,
+    pub elevation_smoothing: elevation::Smoothing,
     pub map_options: MapOptions,
+    // rows of each points table of the pdf, the other points continue on
+    // the following pages
+    pub max_table_rows: usize,
+    // the OSM point categories shown on the map, profile and tables
+    #[serde(serialize_with = "sorted")]
+    pub osm_categories: std::collections::HashSet<OSMType>,
+    pub pdf: pdf::PdfConfig,
+    // the columns of the points table of the pdf, in order
+    pub points_table: Vec<TableColumn>,
     pub profile_options: ProfileOptions,
     pub segment_length: f64,
     pub segment_overlap: f64,
+    pub sleeps: Vec<speed::Stop>,
+    // meters on each side, for the elevation smoothing
     pub smooth_width: f64,
     pub speed: f64,
+    pub speed_model: speed::SpeedModel,
     pub start_time: String,
+    // directory with the typst templates of the pdf, see render.rs
+    pub templates: Option<String>,
     pub user_steps_options: UserStepsOptions,
 }
 
+impl Parameters {
+    // false for the OSM points of categories not selected.
+    pub fn shows(&self, point: &InputPoint) -> bool {
+        match point.kind() {
+            InputType::OSM => match point.osmkind() {
+                Some(kind) => self.osm_categories.contains(&kind),
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    pub fn elevation_model(&self) -> elevation::ElevationModel {
+        elevation::ElevationModel {
+            smoothing: self.elevation_smoothing.clone(),
+            width: self.smooth_width,
+            gain: self.elevation_gain.clone(),
+        }
+    }
+
+    pub fn start(&self) -> Result<DateTime, Error> {
+        self.start_time
+            .parse()
+            .map_err(|_| Error::InvalidParameter {
+                name: "start_time".to_string(),
+                reason: format!("'{}' is not a RFC 3339 time", self.start_time),
+            })
+    }
+
+    // the checks done by the backend before accepting the parameters.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Problems::default();
+        self.check(&mut problems);
+        problems.result()
+    }
+
+    // the conditions are written such that NaN values fail.
+    fn check(&self, problems: &mut Problems) {
+        let positive = |x: f64| x > 0f64;
+        problems.expect(
+            positive(self.segment_length),
+            "segment_length",
+            "must be positive",
+        );
+        problems.expect(
+            self.segment_overlap >= 0f64 && self.segment_overlap < self.segment_length,
+            "segment_overlap",
+            "must be at least 0 and less than the segment length",
+        );
+        problems.expect(positive(self.speed), "speed", "must be positive");
+        problems.expect(
+            self.max_table_rows > 0,
+            "max_table_rows",
+            "must be positive",
+        );
+        problems.expect(
+            positive(self.smooth_width),
+            "smooth_width",
+            "must be positive",
+        );
+        if let elevation::Gain::Hysteresis { threshold } = self.elevation_gain {
+            problems.expect(
+                threshold >= 0f64,
+                "elevation_gain.threshold",
+                "must not be negative",
+            );
+        }
+        problems.expect(
+            self.control_dwell >= 0f64,
+            "control_dwell",
+            "must not be negative",
+        );
+        problems.expect(
+            self.brevet_distance.is_none_or(positive),
+            "brevet_distance",
+            "must be positive",
+        );
+        problems.expect(
+            self.sleeps
+                .iter()
+                .all(|stop| stop.distance >= 0f64 && stop.duration >= 0f64),
+            "sleeps",
+            "distances and durations must not be negative",
+        );
+        if let Err(Error::InvalidParameter { name, reason }) = self.start() {
+            problems.push(&name, &reason);
+        }
+        problems.format("control_gpx_name_format", &self.control_gpx_name_format);
+        problems.expect(
+            !self.points_table.is_empty(),
+            "points_table",
+            "must have a column",
+        );
+        for (k, column) in self.points_table.iter().enumerate() {
+            problems.format(&format!("points_table[{}].format", k), &column.format);
+        }
+        self.user_steps_options.check(problems);
+        self.profile_options.check(problems);
+        self.map_options.check(problems);
+        for (name, reason) in self.pdf.problems() {
+            problems.push(name, reason);
+        }
+    }
+}
+
+// collects all invalid parameters, reported at once by validate().
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn push(&mut self, name: &str, reason: &str) {
+        self.0.push(format!("{}: {}", name, reason));
+    }
+    fn expect(&mut self, ok: bool, name: &str, reason: &str) {
+        if !ok {
+            self.push(name, reason);
+        }
+    }
+    fn format(&mut self, name: &str, format: &str) {
+        for reason in format::check_format(format) {
+            self.push(name, &reason);
+        }
+    }
+    fn result(self) -> Result<(), Error> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err(Error::InvalidParameters { problems: self.0 }),
+        }
+    }
+}
+
+impl UserStepsOptions {
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Problems::default();
+        self.check(&mut problems);
+        problems.result()
+    }
+
+    fn check(&self, problems: &mut Problems) {
+        problems.expect(
+            self.step_distance.is_none_or(|d| d > 0f64),
+            "user_steps_options.step_distance",
+            "must be positive",
+        );
+        problems.expect(
+            self.step_elevation_gain.is_none_or(|d| d > 0f64),
+            "user_steps_options.step_elevation_gain",
+            "must be positive",
+        );
+        problems.expect(
+            self.dwell >= 0f64,
+            "user_steps_options.dwell",
+            "must not be negative",
+        );
+        problems.format("user_steps_options.gpx_name_format", &self.gpx_name_format);
+    }
+}
+
+fn check_area_ratio(ratio: f64, name: &str, problems: &mut Problems) {
+    problems.expect(
+        ratio > 0f64 && ratio <= 1f64,
+        name,
+        "must be between 0 and 1",
+    );
+}
+
+impl ProfileOptions {
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Problems::default();
+        self.check(&mut problems);
+        problems.result()
+    }
+
+    fn check(&self, problems: &mut Problems) {
+        check_area_ratio(
+            self.max_area_ratio,
+            "profile_options.max_area_ratio",
+            problems,
+        );
+    }
+}
+
+impl MapOptions {
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Problems::default();
+        self.check(&mut problems);
+        problems.result()
+    }
+
+    fn check(&self, problems: &mut Problems) {
+        check_area_ratio(self.max_area_ratio, "map_options.max_area_ratio", problems);
+    }
+}
+
 impl Default for Parameters {
     fn default() -> Parameters {
         Parameters {
+            brevet_rules: None,
+            brevet_distance: None,
             control_gpx_name_format: "NAME[3]-TIME[%H:%M]-SLOPE[4.1%]".to_string(),
+            control_dwell: 0f64,
             start_time: chrono::Local::now().to_rfc3339(),
             speed: speed::mps(15f64),
+            speed_model: speed::SpeedModel::Constant,
             segment_length: 110f64 * 1000f64,
             segment_overlap: 10f64 * 1000f64,
+            sleeps: Vec::new(),
             smooth_width: 200f64,
             debug: false,
+            elevation_gain: elevation::Gain::Sum,
+            elevation_smoothing: elevation::Smoothing::MovingAverage,
             profile_options: ProfileOptions::default(),
             map_options: MapOptions::default(),
+            max_table_rows: 15,
+            osm_categories: category::default_selection(),
+            pdf: pdf::PdfConfig::default(),
+            points_table: default_table_columns(),
+            templates: None,
             user_steps_options: UserStepsOptions::default(),
         }
     }
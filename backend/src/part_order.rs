@@ -0,0 +1,276 @@
+use geo::Distance;
+
+use crate::error::Error;
+
+/*
+ * Order of the parts (tracks, segments or routes) of a GPX file.
+ * The parts are chained by endpoint continuity: the end of a part
+ * should be close to the start of the next one. A part is reversed
+ * if its end is closer than its start.
+ */
+
+// gaps larger than this are reported.
+pub const MAX_GAP: f64 = 1000f64;
+
+// two chains are equivalent if their total gap differ less than this.
+const TOLERANCE: f64 = 50f64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartOrdering {
+    Geometry,
+    // indices of the parts in file order.
+    Explicit(Vec<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderedPart {
+    pub index: usize,
+    pub reversed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartGap {
+    // the gap is between the end of parts[index-1] and the start of parts[index]
+    pub index: usize,
+    pub distance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartOrder {
+    pub parts: Vec<OrderedPart>,
+    pub gaps: Vec<PartGap>,
+}
+
+impl PartOrder {
+    pub fn large_gaps(&self) -> Vec<PartGap> {
+        self.gaps
+            .iter()
+            .filter(|gap| gap.distance > MAX_GAP)
+            .cloned()
+            .collect()
+    }
+}
+
+fn endpoints(track: &gpx::Track) -> Option<(geo::Point, geo::Point)> {
+    let points = &track.segments.first()?.points;
+    Some((points.first()?.point(), points.last()?.point()))
+}
+
+fn distance(p1: &geo::Point, p2: &geo::Point) -> f64 {
+    geo::Haversine::distance(*p1, *p2)
+}
+
+fn oriented(ends: &(geo::Point, geo::Point), reversed: bool) -> (geo::Point, geo::Point) {
+    match reversed {
+        true => (ends.1, ends.0),
+        false => *ends,
+    }
+}
+
+fn chain_from(ends: &[(geo::Point, geo::Point)], first: &OrderedPart) -> PartOrder {
+    let mut parts = vec![first.clone()];
+    let mut gaps = Vec::new();
+    let mut remaining: Vec<usize> = (0..ends.len()).filter(|k| *k != first.index).collect();
+    let mut end = oriented(&ends[first.index], first.reversed).1;
+    while !remaining.is_empty() {
+        // closest start (or end, if reversed), file order on ties.
+        let mut best = (0usize, false, f64::MAX);
+        for (position, index) in remaining.iter().enumerate() {
+            for reversed in [false, true] {
+                let d = distance(&end, &oriented(&ends[*index], reversed).0);
+                if d < best.2 {
+                    best = (position, reversed, d);
+                }
+            }
+        }
+        let (position, reversed, d) = best;
+        let index = remaining.remove(position);
+        gaps.push(PartGap {
+            index: parts.len(),
+            distance: d,
+        });
+        parts.push(OrderedPart { index, reversed });
+        end = oriented(&ends[index], reversed).1;
+    }
+    PartOrder { parts, gaps }
+}
+
+// For a closed loop, the gap between the last and the first part is counted,
+// so that all the ways to cut the loop are equivalent.
+fn score(ends: &[(geo::Point, geo::Point)], order: &PartOrder) -> f64 {
+    let first = order.parts.first().unwrap();
+    let last = order.parts.last().unwrap();
+    let closing = distance(
+        &oriented(&ends[last.index], last.reversed).1,
+        &oriented(&ends[first.index], first.reversed).0,
+    );
+    let total: f64 = order.gaps.iter().map(|gap| gap.distance).sum();
+    total + closing.min(MAX_GAP)
+}
+
+fn reversed_count(order: &PartOrder) -> usize {
+    order.parts.iter().filter(|part| part.reversed).count()
+}
+
+fn looks_like_start(track: &gpx::Track) -> bool {
+    match &track.name {
+        Some(name) => name.to_lowercase().starts_with("start"),
+        None => false,
+    }
+}
+
+// Tries every part as the first one and keeps the chain with the smallest gaps.
+// On ties (closed loops, or reversed copies of the same chain), prefers fewer reversed
+// parts, then a first part named "start..." (where to cut a loop), then the file order.
+pub fn order_by_geometry(tracks: &[gpx::Track]) -> PartOrder {
+    let ends: Vec<_> = tracks
+        .iter()
+        .map(|t| endpoints(t).unwrap_or((geo::Point::new(0.0, 0.0), geo::Point::new(0.0, 0.0))))
+        .collect();
+    if ends.is_empty() {
+        return PartOrder {
+            parts: Vec::new(),
+            gaps: Vec::new(),
+        };
+    }
+    let mut best: Option<PartOrder> = None;
+    for index in 0..ends.len() {
+        for reversed in [false, true] {
+            let candidate = chain_from(&ends, &OrderedPart { index, reversed });
+            let better = match &best {
+                None => true,
+                Some(current) => {
+                    let (d1, d0) = (score(&ends, &candidate), score(&ends, current));
+                    if d1 < d0 - TOLERANCE {
+                        true
+                    } else if d1 > d0 + TOLERANCE {
+                        false
+                    } else if reversed_count(&candidate) != reversed_count(current) {
+                        reversed_count(&candidate) < reversed_count(current)
+                    } else {
+                        looks_like_start(&tracks[index])
+                            && !looks_like_start(&tracks[current.parts[0].index])
+                    }
+                }
+            };
+            if better {
+                best = Some(candidate);
+            }
+        }
+    }
+    best.unwrap()
+}
+
+pub fn order_explicit(tracks: &[gpx::Track], order: &[usize]) -> Result<PartOrder, Error> {
+    let mut sorted = order.to_vec();
+    sorted.sort();
+    if sorted != (0..tracks.len()).collect::<Vec<_>>() {
+        return Err(Error::InvalidPartOrder {
+            parts: tracks.len(),
+        });
+    }
+    let mut parts = Vec::new();
+    let mut gaps = Vec::new();
+    for index in order {
+        if let Some(prev) = parts.last().map(|p: &OrderedPart| p.index) {
+            let d = match (endpoints(&tracks[prev]), endpoints(&tracks[*index])) {
+                (Some(a), Some(b)) => distance(&a.1, &b.0),
+                _ => 0f64,
+            };
+            gaps.push(PartGap {
+                index: parts.len(),
+                distance: d,
+            });
+        }
+        parts.push(OrderedPart {
+            index: *index,
+            reversed: false,
+        });
+    }
+    Ok(PartOrder { parts, gaps })
+}
+
+pub fn apply(tracks: Vec<gpx::Track>, order: &PartOrder) -> Vec<gpx::Track> {
+    let mut slots: Vec<Option<gpx::Track>> = tracks.into_iter().map(Some).collect();
+    let mut ret = Vec::new();
+    for part in &order.parts {
+        let mut track = slots[part.index].take().unwrap();
+        if part.reversed {
+            for segment in &mut track.segments {
+                segment.points.reverse();
+            }
+        }
+        ret.push(track);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: &str, lons: &[f64]) -> gpx::Track {
+        let mut segment = gpx::TrackSegment::new();
+        for lon in lons {
+            segment
+                .points
+                .push(gpx::Waypoint::new(geo::Point::new(*lon, 48.0)));
+        }
+        let mut ret = gpx::Track::new();
+        ret.name = Some(name.to_string());
+        ret.segments.push(segment);
+        ret
+    }
+
+    fn indices(order: &PartOrder) -> Vec<(usize, bool)> {
+        order.parts.iter().map(|p| (p.index, p.reversed)).collect()
+    }
+
+    #[test]
+    fn chain_shuffled() {
+        // "Day 10" comes before "Day 2" alphabetically.
+        let tracks = vec![
+            part("Day 10", &[9.0, 10.0]),
+            part("Day 1", &[0.0, 1.0, 2.0]),
+            part("Day 2", &[2.0, 5.0]),
+            part("Day 3", &[9.0, 5.0]),
+        ];
+        let order = order_by_geometry(&tracks);
+        assert_eq!(
+            indices(&order),
+            vec![(1, false), (2, false), (3, true), (0, false)]
+        );
+        assert!(order.large_gaps().is_empty());
+    }
+
+    #[test]
+    fn chain_loop() {
+        let tracks = vec![
+            part("K1-K2", &[1.0, 2.0]),
+            part("K2-Ziel", &[2.0, 3.0, 0.0]),
+            part("Start-K1", &[0.0, 1.0]),
+        ];
+        let order = order_by_geometry(&tracks);
+        assert_eq!(indices(&order), vec![(2, false), (0, false), (1, false)]);
+    }
+
+    #[test]
+    fn chain_gap() {
+        let tracks = vec![part("A", &[0.0, 1.0]), part("B", &[1.5, 2.0])];
+        let order = order_by_geometry(&tracks);
+        assert_eq!(indices(&order), vec![(0, false), (1, false)]);
+        let gaps = order.large_gaps();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].index, 1);
+    }
+
+    #[test]
+    fn explicit() {
+        let tracks = vec![part("A", &[0.0, 1.0]), part("B", &[1.0, 2.0])];
+        let order = order_explicit(&tracks, &[1, 0]).unwrap();
+        assert_eq!(indices(&order), vec![(1, false), (0, false)]);
+        assert!(order_explicit(&tracks, &[1, 1]).is_err());
+        let ordered = apply(tracks, &order);
+        assert_eq!(ordered[0].name.as_ref().unwrap(), "B");
+    }
+}
@@ -0,0 +1,74 @@
+use typst_as_lib::typst_kit_options::TypstKitFontOptions;
+use typst_as_lib::TypstEngine;
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+
+/*
+ * The document is compiled in the process, with the fonts embedded in
+ * typst-kit (Libertinus Serif, New Computer Modern, DejaVu Sans Mono). The
+ * svg images are part of the document, no file or package is read and the
+ * system fonts are not searched, so that the pdf is the same on all hosts.
+ * The compilation takes seconds, it runs on a blocking thread. It cannot be
+ * interrupted: when the token is cancelled, the result is not waited for.
+ */
+
+fn compile_error(what: &str, message: String) -> Error {
+    Error::PdfCompile {
+        message: format!("{}: {}", what, message),
+    }
+}
+
+pub async fn compile(document: &str, cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+    let document = document.to_string();
+    let task = tokio::task::spawn_blocking(move || compile_blocking(&document));
+    cancel
+        .run(task)
+        .await?
+        .map_err(|e| compile_error("typst", e.to_string()))?
+}
+
+fn compile_blocking(document: &str) -> Result<Vec<u8>, Error> {
+    log::trace!("compile embedded");
+    let fonts = TypstKitFontOptions::default()
+        .include_system_fonts(false)
+        .include_embedded_fonts(true);
+    let engine = TypstEngine::builder()
+        .main_file(document)
+        .search_fonts_with(fonts)
+        .build();
+    let compiled = engine.compile();
+    for warning in &compiled.warnings {
+        log::warn!("typst: {}", warning.message);
+    }
+    let paged = compiled
+        .output
+        .map_err(|e| compile_error("typst", format!("{:?}", e)))?;
+    let options = typst_pdf::PdfOptions::default();
+    let pdf = typst_pdf::pdf(&paged, &options).map_err(|diagnostics| {
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.to_string()).collect();
+        compile_error("pdf", messages.join(", "))
+    })?;
+    Ok(pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::Backend;
+    use crate::cancel::CancelToken;
+
+    // the document made with the built-in templates compiles.
+    #[tokio::test]
+    async fn builtin_templates() {
+        let mut backend = Backend::make();
+        backend
+            .load_filename("data/blackforest.gpx")
+            .await
+            .expect("fail");
+        let document = crate::render::make_typst_document(&backend).unwrap();
+        let pdf = super::compile(&document.source, &CancelToken::new())
+            .await
+            .unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+    }
+}
@@ -1,81 +1,99 @@
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-fn get_host() -> Option<String> {
-    // TODO: load from config file.
-    Some("https://vps-e637d6c5.vps.ovh.net:8123".to_string())
-}
-
-fn get_client() -> reqwest::Client {
-    reqwest::ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap()
-}
+use crate::cancel::{self, CancelToken};
+use crate::error::Error;
 
-async fn compile_remote(document: &str) -> Vec<u8> {
-    log::trace!("compile remote");
-    let url = format!("{}/api/typst", get_host().unwrap());
-    let client = get_client();
-    let response = client
-        .post(url)
-        .header("User-Agent", "jbo/WPX")
-        .header("Content-Type", "text/plain; charset=UTF-8")
-        .body(format!("{}", document))
-        .send()
-        .await
-        .unwrap();
-    let data = response.bytes().await;
-    let data = data.ok();
-    data.unwrap().to_vec()
-}
+use super::{check_pdf, compile_error, PdfConfig};
 
-fn compile_local(body: &str) -> Option<Vec<u8>> {
-    let temp_dir = match tempfile::Builder::new().prefix("typst_").tempdir() {
-        Ok(dir) => dir,
-        Err(_) => {
-            return None;
-        }
-    };
+// where the typst release used to be installed.
+const FORMER_LOCATION: &str = "/opt/typst/typst-x86_64-unknown-linux-musl/typst";
 
-    // Generate unique filenames
-    let input_path = temp_dir.path().join(format!("{}.typst", "document"));
-    let pdf_path = temp_dir.path().join(format!("{}.pdf", "document"));
+fn executable(name: &str) -> String {
+    match cfg!(windows) {
+        true => format!("{}.exe", name),
+        false => name.to_string(),
+    }
+}
 
-    // Write content to temporary file
-    if let Err(_) = std::fs::write(&input_path, body) {
-        return None;
+// the configured binary, or typst in PATH.
+pub fn find_binary(config: &PdfConfig) -> Option<PathBuf> {
+    if let Some(binary) = &config.typst_binary {
+        return Some(PathBuf::from(binary));
     }
+    let name = executable("typst");
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&name))
+        .chain(std::iter::once(PathBuf::from(FORMER_LOCATION)))
+        .find(|candidate| candidate.is_file())
+}
 
-    log::trace!("compile local");
+/*
+ * Err if typst could not be started, Ok(Err) if it failed, timed out or
+ * was cancelled (it is killed then).
+ */
+pub async fn compile(
+    body: &str,
+    binary: &Path,
+    config: &PdfConfig,
+    cancel: &CancelToken,
+) -> std::io::Result<Result<Vec<u8>, Error>> {
+    let temp_dir = tempfile::Builder::new().prefix("typst_").tempdir()?;
+    let input_path = temp_dir.path().join("document.typst");
+    let pdf_path = temp_dir.path().join("document.pdf");
+    std::fs::write(&input_path, body)?;
 
-    // create tyspt command
-    let status = Command::new("/opt/typst/typst-x86_64-unknown-linux-musl/typst")
+    log::trace!("compile with {}", binary.display());
+    let mut child = Command::new(binary)
         .arg("compile")
         .arg(&input_path)
         .arg(&pdf_path)
-        .status();
-
-    if let Err(_) = status {
-        return None;
-    }
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // read while typst runs, a full pipe would block it.
+    let reader = child.stderr.take().map(|mut pipe| {
+        std::thread::spawn(move || {
+            use std::io::Read;
+            let mut ret = String::new();
+            let _ = pipe.read_to_string(&mut ret);
+            ret
+        })
+    });
 
-    // read and return the result
-    match std::fs::read(&pdf_path) {
-        Ok(pdf_content) => {
-            return Some(pdf_content);
+    let start = std::time::Instant::now();
+    let timeout = std::time::Duration::from_secs(config.timeout);
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
         }
-        Err(_) => {
-            return None;
+        let error = if cancel.is_cancelled() {
+            Some(Error::Cancelled)
+        } else if start.elapsed() > timeout {
+            Some(compile_error(format!(
+                "typst did not finish within {} s",
+                config.timeout
+            )))
+        } else {
+            None
+        };
+        if let Some(e) = error {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(Err(e));
         }
-    }
-}
+        cancel::sleep(std::time::Duration::from_millis(50)).await;
+    };
 
-pub async fn compile(document: &str, debug: bool) -> Vec<u8> {
-    if debug {
-        std::fs::write("/tmp/document.typst", &document).unwrap();
-    }
-    match compile_local(document) {
-        Some(ret) => ret,
-        None => compile_remote(document).await,
+    let stderr = reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    if !status.success() {
+        return Ok(Err(compile_error(format!(
+            "typst failed ({}): {}",
+            status,
+            stderr.trim()
+        ))));
     }
+    Ok(check_pdf(std::fs::read(&pdf_path)?))
 }
@@ -1,17 +1,193 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::Error;
+
+#[cfg(all(feature = "typstpdf", not(target_arch = "wasm32")))]
+mod embedded;
 #[cfg(not(target_arch = "wasm32"))]
 mod local;
+#[cfg(not(target_arch = "wasm32"))]
+mod remote;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
+/*
+ * How the typst document is compiled to pdf:
+ *  - Embedded: in the process (typstpdf feature),
+ *  - Binary: the typst binary, typst_binary or found in PATH,
+ *  - Remote: posted to remote_url/api/typst,
+ *  - Auto: the first available of the above. The remote server is also
+ *    tried when the binary cannot be started.
+ * The web build always posts to remote_url, or to its own server.
+ */
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PdfBackend {
+    Auto,
+    Embedded,
+    Binary,
+    Remote,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PdfConfig {
+    pub backend: PdfBackend,
+    pub typst_binary: Option<String>,
+    pub remote_url: Option<String>,
+    // pem file with the certificates trusted for the remote server, in
+    // addition to the system ones (or instead of them, with pin_certificate)
+    pub ca_certificate: Option<String>,
+    pub pin_certificate: bool,
+    // for self signed test servers only
+    pub accept_invalid_certificates: bool,
+    // seconds, for the binary and the server
+    pub timeout: u64,
+}
+
+impl Default for PdfConfig {
+    fn default() -> PdfConfig {
+        PdfConfig {
+            backend: PdfBackend::Auto,
+            typst_binary: None,
+            remote_url: None,
+            ca_certificate: None,
+            pin_certificate: false,
+            accept_invalid_certificates: false,
+            timeout: 120,
+        }
+    }
+}
+
+impl PdfConfig {
+    // the invalid settings, as (name, reason).
+    pub fn problems(&self) -> Vec<(&'static str, &'static str)> {
+        let mut ret = Vec::new();
+        if self.timeout == 0 {
+            ret.push(("pdf.timeout", "must be positive"));
+        }
+        if let Some(url) = &self.remote_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                ret.push(("pdf.remote_url", "must be a http(s) url"));
+            }
+        }
+        match self.backend {
+            PdfBackend::Remote if self.remote_url.is_none() => {
+                ret.push(("pdf.remote_url", "is required by the remote backend"));
+            }
+            PdfBackend::Embedded if !cfg!(feature = "typstpdf") => {
+                ret.push(("pdf.backend", "this build has no embedded typst"));
+            }
+            _ => {}
+        }
+        if self.pin_certificate && self.ca_certificate.is_none() {
+            ret.push(("pdf.pin_certificate", "requires ca_certificate"));
+        }
+        ret
+    }
+}
+
+fn compile_error(message: String) -> Error {
+    Error::PdfCompile { message }
+}
+
+// the servers answer with an error page or an error message.
+fn check_pdf(bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if bytes.starts_with(b"%PDF-") {
+        return Ok(bytes);
+    }
+    let start = String::from_utf8_lossy(&bytes[..bytes.len().min(200)]).to_string();
+    Err(compile_error(format!(
+        "the result is not a pdf: {}",
+        start.trim()
+    )))
+}
+
 #[cfg(not(target_arch = "wasm32"))]
-pub async fn compile(document: &str, debug: bool) -> Vec<u8> {
+pub async fn compile(
+    document: &str,
+    config: &PdfConfig,
+    debug: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, Error> {
     if debug {
         std::fs::write("/tmp/document.typst", &document).unwrap();
     }
-    local::compile(document, debug).await
+    match config.backend {
+        PdfBackend::Embedded => compile_embedded(document, cancel).await,
+        PdfBackend::Binary => match local::find_binary(config) {
+            Some(binary) => local::compile(document, &binary, config, cancel)
+                .await
+                .map_err(|e| compile_error(format!("could not run {}: {}", binary.display(), e)))?,
+            None => Err(compile_error(
+                "typst was not found, set the typst binary or add it to PATH".to_string(),
+            )),
+        },
+        PdfBackend::Remote => remote::compile(document, config, cancel).await,
+        PdfBackend::Auto => {
+            if cfg!(feature = "typstpdf") {
+                return compile_embedded(document, cancel).await;
+            }
+            if let Some(binary) = local::find_binary(config) {
+                match local::compile(document, &binary, config, cancel).await {
+                    Ok(ret) => return ret,
+                    Err(e) => log::error!("could not run {}: {}", binary.display(), e),
+                }
+            }
+            match config.remote_url {
+                Some(_) => remote::compile(document, config, cancel).await,
+                None => Err(compile_error(
+                    "no typst binary and no server to compile the pdf".to_string(),
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "typstpdf", not(target_arch = "wasm32")))]
+async fn compile_embedded(document: &str, cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+    embedded::compile(document, cancel).await
+}
+
+#[cfg(all(not(feature = "typstpdf"), not(target_arch = "wasm32")))]
+async fn compile_embedded(_document: &str, _cancel: &CancelToken) -> Result<Vec<u8>, Error> {
+    Err(compile_error(
+        "this build has no embedded typst".to_string(),
+    ))
 }
 
 #[cfg(target_arch = "wasm32")]
-pub async fn compile(document: &str, debug: bool) -> Vec<u8> {
-    wasm::compile(document).await
+pub async fn compile(
+    document: &str,
+    config: &PdfConfig,
+    _debug: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, Error> {
+    cancel.run(wasm::compile(document, config)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config() {
+        assert!(PdfConfig::default().problems().is_empty());
+        assert!(!PdfConfig::default().accept_invalid_certificates);
+        assert!(PdfConfig::default().remote_url.is_none());
+        let config = PdfConfig {
+            backend: PdfBackend::Remote,
+            remote_url: None,
+            pin_certificate: true,
+            timeout: 0,
+            ..PdfConfig::default()
+        };
+        assert_eq!(config.problems().len(), 3);
+        assert!(check_pdf(b"%PDF-1.7 ...".to_vec()).is_ok());
+        match check_pdf(b"<html>502 Bad Gateway</html>".to_vec()) {
+            Err(Error::PdfCompile { message }) => assert!(message.contains("502")),
+            _ => assert!(false),
+        }
+    }
 }
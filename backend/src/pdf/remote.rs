@@ -0,0 +1,67 @@
+use crate::cancel::CancelToken;
+use crate::error::Error;
+
+use super::{check_pdf, compile_error, PdfConfig};
+
+fn make_client(config: &PdfConfig) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::ClientBuilder::new()
+        .timeout(std::time::Duration::from_secs(config.timeout))
+        .danger_accept_invalid_certs(config.accept_invalid_certificates);
+    if let Some(path) = &config.ca_certificate {
+        let pem = std::fs::read(path).map_err(|e| Error::IOError {
+            message: format!("could not read {}: {}", path, e),
+        })?;
+        let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(|e| compile_error(format!("invalid certificate {}: {}", path, e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+        // only the given certificates are trusted.
+        if config.pin_certificate {
+            builder = builder.tls_built_in_root_certs(false);
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| compile_error(format!("could not create the http client: {}", e)))
+}
+
+async fn post(document: &str, config: &PdfConfig) -> Result<Vec<u8>, Error> {
+    let host = match &config.remote_url {
+        Some(url) => url.trim_end_matches('/'),
+        None => return Err(compile_error("there is no pdf server".to_string())),
+    };
+    let url = format!("{}/api/typst", host);
+    log::trace!("compile remote {}", url);
+    let response = make_client(config)?
+        .post(&url)
+        .header("User-Agent", "jbo/WPX")
+        .header("Content-Type", "text/plain; charset=UTF-8")
+        .body(document.to_string())
+        .send()
+        .await
+        .map_err(|e| compile_error(format!("{}: {}", url, e)))?;
+    let status = response.status();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| compile_error(format!("{}: {}", url, e)))?;
+    if !status.is_success() {
+        let text = String::from_utf8_lossy(&body[..body.len().min(200)]).to_string();
+        return Err(compile_error(format!(
+            "{} answered {}: {}",
+            url,
+            status,
+            text.trim()
+        )));
+    }
+    check_pdf(body.to_vec())
+}
+
+pub async fn compile(
+    document: &str,
+    config: &PdfConfig,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, Error> {
+    cancel.run(post(document, config)).await?
+}
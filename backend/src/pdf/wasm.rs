@@ -1,32 +1,41 @@
-use wasm_bindgen::prelude::*;
+use crate::error::Error;
 
-fn get_host() -> Option<String> {
-    let h = web_sys::window()
-        .and_then(|win| win.location().host().ok())
-        .unwrap();
-    Some(format!("https://{}", h))
-}
+use super::{check_pdf, compile_error, PdfConfig};
 
-fn get_client() -> reqwest::Client {
-    reqwest::Client::new()
+// the configured server, or the one serving the application.
+fn get_host(config: &PdfConfig) -> Result<String, Error> {
+    if let Some(url) = &config.remote_url {
+        return Ok(url.trim_end_matches('/').to_string());
+    }
+    web_sys::window()
+        .and_then(|win| win.location().host().ok())
+        .map(|host| format!("https://{}", host))
+        .ok_or_else(|| compile_error("there is no pdf server".to_string()))
 }
 
-async fn compile_remote(document: &str) -> Vec<u8> {
-    let url = format!("{}/api/typst", get_host().unwrap());
-    let client = get_client();
-    let response = client
-        .post(url)
+pub async fn compile(document: &str, config: &PdfConfig) -> Result<Vec<u8>, Error> {
+    let url = format!("{}/api/typst", get_host(config)?);
+    let response = reqwest::Client::new()
+        .post(&url)
         .header("User-Agent", "jbo/WPX")
         .header("Content-Type", "text/plain; charset=UTF-8")
-        .body(format!("{}", document))
+        .body(document.to_string())
         .send()
         .await
-        .unwrap();
-    let data = response.bytes().await;
-    let data = data.ok();
-    data.unwrap().to_vec()
-}
-
-pub async fn compile(document: &str) -> Vec<u8> {
-    compile_remote(document).await
+        .map_err(|e| compile_error(format!("{}: {}", url, e)))?;
+    let status = response.status();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| compile_error(format!("{}: {}", url, e)))?;
+    if !status.is_success() {
+        let text = String::from_utf8_lossy(&body[..body.len().min(200)]).to_string();
+        return Err(compile_error(format!(
+            "{} answered {}: {}",
+            url,
+            status,
+            text.trim()
+        )));
+    }
+    check_pdf(body.to_vec())
 }
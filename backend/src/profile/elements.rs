@@ -2,6 +2,7 @@ use crate::math::Point2D;
 
 pub type Group = svg::node::element::Group;
 pub type Path = svg::node::element::Path;
+pub type Rectangle = svg::node::element::Rectangle;
 
 type Data = svg::node::element::path::Data;
 type Text = svg::node::element::Text;
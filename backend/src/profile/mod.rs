@@ -397,6 +397,25 @@ impl ProfileView {
         }
     }
 
+    pub fn add_night_bands(&mut self, dark: &[std::ops::Range<f64>]) {
+        for range in dark {
+            let x0 = self.toSD(&Point2D::new(range.start, 0f64)).x.max(0f64);
+            let x1 = self.toSD(&Point2D::new(range.end, 0f64)).x.min(self.WD());
+            if x1 <= x0 {
+                continue;
+            }
+            let band = elements::Rectangle::new()
+                .set("x", x0)
+                .set("y", 0f64)
+                .set("width", x1 - x0)
+                .set("height", self.yticks_end())
+                .set("fill", "#000")
+                .set("fill-opacity", 0.1f64)
+                .set("id", format!("night-{:.0}-{:.0}", range.start, range.end));
+            self.SD.append(band);
+        }
+    }
+
     pub fn render_model(&mut self) {
         let model = self.model.as_ref().unwrap();
         for polyline in &model.polylines {
@@ -524,7 +543,7 @@ impl CandidatesGenerator for ProfileGenerator {
     fn gen(&self, feature: &PointFeature) -> Vec<LabelBoundingBox> {
         match feature.input_point.as_ref().unwrap().kind() {
             InputType::OSM => self.cardinal(feature),
-            InputType::UserStep => self.extended_cardinal(feature),
+            InputType::UserStep | InputType::Sun => self.extended_cardinal(feature),
             //InputType::UserStep => self.generate_column(feature),
             //InputType::UserStep => self.generate_header(feature, vec![25f64, self.HD - 20f64]),
             InputType::GPX | InputType::Control => self.header(feature, vec![5f64]),
@@ -652,6 +671,7 @@ pub fn profile(
         ProfileBoundingBox::from_track(&segment.track, &segment.start(), &segment.end());
     let mut view = ProfileView::init(&profile_bbox, size, &segment.parameters.profile_options);
     view.add_canvas();
+    view.add_night_bands(&segment.daylight().dark);
     view.add_segment(&segment, kinds);
     view.render_model();
     view.render()
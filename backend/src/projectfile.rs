@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+
+use serde::{Deserialize, Serialize};
+use zip::write::{SimpleFileOptions, ZipWriter};
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::error::Error;
+use crate::inputpoint::{InputPointMap, InputType};
+use crate::parameters::Parameters;
+
+/*
+ * A project file is a zip archive with:
+ *  - project.json: the format version, the parameters and the part order,
+ *  - input: the original file (GPX, KML, ...),
+ *  - points/<kind>.json: the GPX waypoints, the (edited) controls and
+ *    the OSM points, so that no download is needed to reload it.
+ * User steps are not saved, they are computed from the parameters.
+ * The settings of the host (the typst binary, the trust of invalid
+ * certificates) are not saved either, the ones of the loading host are
+ * used.
+ */
+
+pub const VERSION: u32 = 1;
+
+const MANIFEST: &str = "project.json";
+const INPUT: &str = "input";
+
+// the point kinds stored in the project.
+pub const KINDS: [InputType; 3] = [InputType::GPX, InputType::Control, InputType::OSM];
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    parameters: Parameters,
+    part_order: Option<Vec<usize>>,
+}
+
+pub struct Project {
+    pub content: Vec<u8>,
+    pub parameters: Parameters,
+    pub part_order: Option<Vec<usize>>,
+    pub points: BTreeMap<InputType, InputPointMap>,
+}
+
+// the parameters without the settings of the host.
+fn portable(parameters: &Parameters) -> Parameters {
+    let mut ret = parameters.clone();
+    let default = crate::pdf::PdfConfig::default();
+    ret.pdf.typst_binary = default.typst_binary;
+    ret.pdf.accept_invalid_certificates = default.accept_invalid_certificates;
+    ret
+}
+
+fn points_filename(kind: &InputType) -> String {
+    format!("points/{:?}.json", kind)
+}
+
+pub fn write(project: &Project) -> Vec<u8> {
+    let buffer = Cursor::new(Vec::new());
+    let mut zip = ZipWriter::new(buffer);
+    // fixed timestamp, see zipexport.rs
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .last_modified_time(zip::DateTime::from_date_and_time(2024, 1, 1, 0, 0, 0).unwrap());
+
+    let manifest = Manifest {
+        version: VERSION,
+        parameters: portable(&project.parameters),
+        part_order: project.part_order.clone(),
+    };
+    zip.start_file(MANIFEST, options).unwrap();
+    zip.write_all(serde_json::to_string_pretty(&manifest).unwrap().as_bytes())
+        .unwrap();
+
+    zip.start_file(INPUT, options).unwrap();
+    zip.write_all(&project.content).unwrap();
+
+    for (kind, map) in &project.points {
+        zip.start_file(points_filename(kind), options).unwrap();
+        zip.write_all(map.as_string().unwrap().as_bytes()).unwrap();
+    }
+
+    zip.finish().unwrap().into_inner()
+}
+
+fn read_file(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<Vec<u8>, Error> {
+    let mut file = match archive.by_name(name) {
+        Ok(f) => f,
+        Err(e) => {
+            log::error!("project: cannot find {}: {}", name, e);
+            return Err(Error::ProjectInvalid);
+        }
+    };
+    let mut ret = Vec::new();
+    file.read_to_end(&mut ret)
+        .map_err(|_| Error::ProjectInvalid)?;
+    Ok(ret)
+}
+
+pub fn read(bytes: &[u8]) -> Result<Project, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| {
+        log::error!("project: not a zip archive: {}", e);
+        Error::ProjectInvalid
+    })?;
+
+    // check the version before parsing the rest.
+    let data = read_file(&mut archive, MANIFEST)?;
+    let value: serde_json::Value =
+        serde_json::from_slice(&data).map_err(|_| Error::ProjectInvalid)?;
+    let version = value
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .ok_or(Error::ProjectInvalid)? as u32;
+    if version > VERSION {
+        return Err(Error::ProjectVersion { version });
+    }
+    let manifest: Manifest = serde_json::from_value(value).map_err(|e| {
+        log::error!("project: invalid manifest: {}", e);
+        Error::ProjectInvalid
+    })?;
+
+    let content = read_file(&mut archive, INPUT)?;
+
+    let mut points = BTreeMap::new();
+    for kind in KINDS {
+        let name = points_filename(&kind);
+        if archive.index_for_name(&name).is_none() {
+            continue;
+        }
+        let data = read_file(&mut archive, &name)?;
+        let text = String::from_utf8(data).map_err(|_| Error::ProjectInvalid)?;
+        let map = InputPointMap::from_string(&text).map_err(|e| {
+            log::error!("project: invalid points in {}: {}", name, e);
+            Error::ProjectInvalid
+        })?;
+        points.insert(kind, map);
+    }
+
+    Ok(Project {
+        content,
+        parameters: portable(&manifest.parameters),
+        part_order: manifest.part_order,
+        points,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut parameters = Parameters::default();
+        parameters.speed = 7.5;
+        parameters.control_gpx_name_format = "NAME[5]".to_string();
+        parameters.pdf.typst_binary = Some("/opt/typst".to_string());
+        parameters.pdf.accept_invalid_certificates = true;
+        let project = Project {
+            content: b"<gpx></gpx>".to_vec(),
+            parameters,
+            part_order: Some(vec![1, 0]),
+            points: BTreeMap::from([(InputType::Control, InputPointMap::new())]),
+        };
+        let bytes = write(&project);
+        let copy = read(&bytes).unwrap();
+        assert_eq!(copy.content, project.content);
+        assert_eq!(copy.parameters.speed, 7.5);
+        assert_eq!(copy.parameters.control_gpx_name_format, "NAME[5]");
+        // the host settings are not saved
+        assert!(!String::from_utf8_lossy(&bytes).contains("/opt/typst"));
+        assert_eq!(copy.parameters.pdf.typst_binary, None);
+        assert!(!copy.parameters.pdf.accept_invalid_certificates);
+        assert_eq!(copy.part_order, Some(vec![1, 0]));
+        assert!(copy.points.contains_key(&InputType::Control));
+        assert!(!copy.points.contains_key(&InputType::OSM));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(read(b"hello"), Err(Error::ProjectInvalid)));
+        // a newer version
+        let buffer = Cursor::new(Vec::new());
+        let mut zip = ZipWriter::new(buffer);
+        zip.start_file(MANIFEST, SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"{\"version\": 99}").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        assert!(matches!(
+            read(&bytes),
+            Err(Error::ProjectVersion { version: 99 })
+        ));
+    }
+}
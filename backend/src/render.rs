@@ -1,112 +1,343 @@
 #![allow(non_snake_case)]
 
 use euclid::Size2D;
+use serde::Serialize;
 
 use crate::backend::Backend;
+use crate::error::Error;
 use crate::inputpoint::{self, InputType};
-use crate::{track, waypoint};
+use crate::parameters::TableColumn;
+use crate::segment::SegmentStatistics;
+use crate::waypoint;
 
 use std::collections::{BTreeMap, HashSet};
-use std::str::FromStr;
+use std::path::Path;
 
-struct Templates {
+/*
+ * The typst document is made of
+ *   #let wpx = (...)       the data below
+ *   header.typ             page and text settings
+ *   table-points.typ       must define points-table(points)
+ *   table-large.typ        must define segment-page(segment)
+ *   #for segment in wpx.segments {
+ *     segment-page(segment)
+ *     for points in segment.overflow { points-table(points) }
+ *   }
+ * each call on a new page.
+ *
+ * The templates are read from a directory (parameter "templates"), the
+ * missing files are replaced by the built-in ones. The data is:
+ *   wpx.columns             the titles of the points table columns
+ *   wpx.start_time          rfc3339
+ *   wpx.statistics          (length, elevation_gain, distance_start,
+ *                            distance_end), whole track, meters
+ *   wpx.segments            one per page:
+ *     id, start, end        meters
+ *     statistics            as above, for the segment
+ *     profile, map          svg strings, use image(bytes(..), format: "svg")
+ *     points                the first controls and user steps, at most
+ *                           max_table_rows, each one:
+ *       name, description, kind ("Control" or "UserStep"),
+ *       distance, elevation, inter_distance, inter_elevation_gain,
+ *       elevation_gain, remaining_distance, remaining_elevation_gain,
+ *       next_control (meters, none without a following control),
+ *       inter_slope (ratio), elapsed (seconds),
+ *       time, open, close (rfc3339, none if unknown),
+ *       cells               the text of each column, formatted with the
+ *                           parameter points_table
+ *     overflow              the following points, by pages of at most
+ *                           max_table_rows (usually empty)
+ */
+
+const HEADER: &str = "header.typ";
+const TABLE_LARGE: &str = "table-large.typ";
+const TABLE_POINTS: &str = "table-points.typ";
+
+// the markers that were substituted before the data was passed. The
+// placeholders of the columns ({name}, {time}...) are also valid typst code
+// blocks, they are not checked.
+const LEGACY_PLACEHOLDERS: [&str; 4] = [
+    "{table-points}",
+    "{profile.svg}",
+    "{map.svg}",
+    "#line-template",
+];
+
+pub struct Templates {
     header: String,
     table_large: String,
     table_points: String,
 }
 
 impl Templates {
-    fn new() -> Templates {
+    pub fn builtin() -> Templates {
         Templates {
-            header: String::from_str(include_str!("../templates/header.typ")).unwrap(),
-            table_large: String::from_str(include_str!("../templates/table-large.typ")).unwrap(),
-            table_points: String::from_str(include_str!("../templates/table-points.typ")).unwrap(),
+            header: include_str!("../templates/header.typ").to_string(),
+            table_large: include_str!("../templates/table-large.typ").to_string(),
+            table_points: include_str!("../templates/table-points.typ").to_string(),
         }
     }
-}
 
-fn points_table(
-    templates: &Templates,
-    _track: &track::Track,
-    waypoints: &Vec<&waypoint::Waypoint>,
-) -> String {
-    let table = templates.table_points.clone();
-    let mut template_line_orig = String::new();
-    let mut template_line = String::new();
-    for line in table.split("\n") {
-        if line.contains("/* #line-template") {
-            template_line_orig = String::from_str(line).unwrap();
-            template_line = template_line_orig.clone();
-            template_line = template_line.replace("/* #line-template", "");
-            template_line = template_line.replace("*/", "");
+    // the templates of the directory, the built-in ones for missing files.
+    pub fn load(directory: &Path) -> Result<Templates, Error> {
+        if !directory.is_dir() {
+            return Err(Error::TemplateInvalid {
+                problems: vec![format!("{} is not a directory", directory.display())],
+            });
+        }
+        let mut problems = Vec::new();
+        let mut read = |name: &str, builtin: String| {
+            let path = directory.join(name);
+            if !path.exists() {
+                return builtin;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    problems.push(format!("{}: {}", name, e));
+                    builtin
+                }
+            }
+        };
+        let builtin = Templates::builtin();
+        let ret = Templates {
+            header: read(HEADER, builtin.header),
+            table_large: read(TABLE_LARGE, builtin.table_large),
+            table_points: read(TABLE_POINTS, builtin.table_points),
+        };
+        problems.extend(ret.problems());
+        match problems.is_empty() {
+            true => Ok(ret),
+            false => Err(Error::TemplateInvalid { problems }),
         }
     }
-    debug_assert!(!template_line.is_empty());
-    // TODO: avoid recomputing the automatic points
-    let mut lines = Vec::new();
-    for k in 0..waypoints.len() {
-        let info = &waypoints[k].get_info();
-        let mut copy = template_line.clone();
-        copy = copy.replace("{name}", info.name.as_str());
-        let datetime = chrono::DateTime::parse_from_rfc3339(info.time.as_str()).unwrap();
-        let time_str = format!("{}", datetime.format("%H:%M"));
-
-        copy = copy.replace("{time}", &time_str);
-
-        copy = copy.replace(
-            "{distance}",
-            format!("{:4.0}", info.distance / 1000f64).as_str(),
-        );
-        let elevation = info.elevation;
-        copy = copy.replace("{elevation}", format!("{:5.0} m", elevation).as_str());
-        let hm = info.inter_elevation_gain;
-        let percent = 100f64 * info.inter_slope;
-        copy = copy.replace("{d+}", format!("{:5.0}", hm).as_str());
-        copy = copy.replace("{slope}", format!("{:2.1}%", percent).as_str());
-        copy = copy.replace("{desc}", info.description.as_str());
-        let dist = info.inter_distance / 1000f64;
-        copy = copy.replace("{dist}", format!("{:2.1}", dist).as_str());
-        lines.push(copy);
+
+    pub fn write(&self, directory: &Path) -> Result<(), Error> {
+        let files = [
+            (HEADER, &self.header),
+            (TABLE_LARGE, &self.table_large),
+            (TABLE_POINTS, &self.table_points),
+        ];
+        std::fs::create_dir_all(directory)
+            .and_then(|_| {
+                files
+                    .iter()
+                    .try_for_each(|(name, content)| std::fs::write(directory.join(name), content))
+            })
+            .map_err(|e| Error::IOError {
+                message: format!(
+                    "could not write the templates to {}: {}",
+                    directory.display(),
+                    e
+                ),
+            })
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        let required = [
+            (TABLE_LARGE, &self.table_large, "segment-page(segment)"),
+            (TABLE_POINTS, &self.table_points, "points-table(points)"),
+        ];
+        for (name, content, function) in required {
+            let (f, _) = function.split_once('(').unwrap_or_default();
+            if !content.contains(&format!("#let {}(", f)) {
+                ret.push(format!("{}: must define {}", name, function));
+            }
+        }
+        let files = [
+            (HEADER, &self.header),
+            (TABLE_LARGE, &self.table_large),
+            (TABLE_POINTS, &self.table_points),
+        ];
+        for (name, content) in files {
+            if content.contains("#let wpx ") || content.contains("#let wpx=") {
+                ret.push(format!("{}: wpx is reserved for the data", name));
+            }
+            for placeholder in LEGACY_PLACEHOLDERS {
+                if content.contains(placeholder) {
+                    ret.push(format!(
+                        "{}: {} is no longer replaced, use the wpx data",
+                        name, placeholder
+                    ));
+                }
+            }
+        }
+        ret
     }
-    let joined = lines.join("\n");
-    table.replace(&template_line_orig, joined.as_str())
 }
 
-fn get_typst_bytes(utf8: &str) -> String {
-    let mut ret = Vec::new();
-    let chars = utf8.as_bytes();
-    for c in chars {
-        let code = format!("{}", *c as u32);
-        ret.push(code);
+#[derive(Serialize)]
+struct Statistics {
+    length: f64,
+    elevation_gain: f64,
+    distance_start: f64,
+    distance_end: f64,
+}
+
+impl Statistics {
+    fn new(s: &SegmentStatistics) -> Statistics {
+        Statistics {
+            length: s.length,
+            elevation_gain: s.elevation_gain,
+            distance_start: s.distance_start,
+            distance_end: s.distance_end,
+        }
     }
-    let rc = ret.join(",");
-    format!("bytes(({}))", rc)
-}
-
-fn link(
-    templates: &Templates,
-    profilesvg: &str,
-    mapsvg: &str,
-    points_table: &String,
-    document: &mut String,
-) {
-    let mut table = templates.table_large.clone();
-    table = table.replace("{table-points}", points_table.as_str());
-    table = table.replace("{profile.svg}", get_typst_bytes(profilesvg).as_str());
-    table = table.replace("{map.svg}", get_typst_bytes(mapsvg).as_str());
-    //table = table.replace("{map.svg}", format!("\"{}\"", "map-0.svg").as_str());
-    document.push_str(table.as_str());
-}
-
-pub fn make_typst_document(backend: &Backend) -> String {
-    let debug = backend.get_parameters().debug;
-    let templates = Templates::new();
-    let mut document = templates.header.clone();
-    let fsegments = backend.segments();
-    let segments: Vec<_> = fsegments
+}
+
+#[derive(Serialize)]
+struct Point {
+    name: String,
+    description: String,
+    kind: InputType,
+    distance: f64,
+    elevation: f64,
+    inter_distance: f64,
+    inter_elevation_gain: f64,
+    inter_slope: f64,
+    elevation_gain: f64,
+    remaining_distance: f64,
+    remaining_elevation_gain: f64,
+    next_control: Option<f64>,
+    time: Option<String>,
+    elapsed: f64,
+    open: Option<String>,
+    close: Option<String>,
+    cells: Vec<String>,
+}
+
+impl Point {
+    fn new(info: &waypoint::WaypointInfo, columns: &[TableColumn]) -> Point {
+        let valid = chrono::DateTime::parse_from_rfc3339(info.time.as_str()).is_ok();
+        Point {
+            name: info.name.clone(),
+            description: info.description.clone(),
+            kind: info.origin.clone(),
+            distance: info.distance,
+            elevation: info.elevation,
+            inter_distance: info.inter_distance,
+            inter_elevation_gain: info.inter_elevation_gain,
+            inter_slope: info.inter_slope,
+            elevation_gain: info.elevation_gain,
+            remaining_distance: info.remaining_distance,
+            remaining_elevation_gain: info.remaining_elevation_gain,
+            next_control: info.next_control,
+            time: valid.then(|| info.time.clone()),
+            elapsed: info.elapsed,
+            open: info.open.clone(),
+            close: info.close.clone(),
+            cells: columns
+                .iter()
+                .map(|column| info.format(&column.format).trim().to_string())
+                .collect(),
+        }
+    }
+}
+
+// the rows of the points table, a cell per column.
+fn points_table(waypoints: &[&waypoint::Waypoint], columns: &[TableColumn]) -> Vec<Point> {
+    waypoints
         .iter()
-        .map(|f| backend.make_segment_data(&f))
-        .collect();
+        .map(|w| Point::new(w.get_info(), columns))
+        .collect()
+}
+
+#[derive(Serialize)]
+struct Segment {
+    id: i32,
+    start: f64,
+    end: f64,
+    statistics: Statistics,
+    profile: String,
+    map: String,
+    points: Vec<Point>,
+    overflow: Vec<Vec<Point>>,
+}
+
+#[derive(Serialize)]
+struct Document {
+    columns: Vec<String>,
+    start_time: String,
+    statistics: Statistics,
+    segments: Vec<Segment>,
+}
+
+fn typst_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// a typst literal for the value (arrays and dictionaries are in parentheses).
+fn typst_value(value: &serde_json::Value, out: &mut String) {
+    use serde_json::Value;
+    match value {
+        Value::Null => out.push_str("none"),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::Number(n) => out.push_str(&n.to_string()),
+        Value::String(s) => typst_string(s, out),
+        Value::Array(a) => {
+            out.push('(');
+            for v in a {
+                typst_value(v, out);
+                out.push(',');
+            }
+            out.push(')');
+        }
+        Value::Object(o) => {
+            if o.is_empty() {
+                out.push_str("(:)");
+                return;
+            }
+            out.push('(');
+            for (k, v) in o {
+                typst_string(k, out);
+                out.push(':');
+                typst_value(v, out);
+                out.push(',');
+            }
+            out.push(')');
+        }
+    }
+}
+
+fn typst_data(document: &Document) -> Result<String, Error> {
+    let value = serde_json::to_value(document).map_err(|e| Error::IOError {
+        message: format!("could not convert the pdf data: {}", e),
+    })?;
+    let mut ret = String::from("#let wpx = ");
+    typst_value(&value, &mut ret);
+    ret.push('\n');
+    Ok(ret)
+}
+
+// the points of a segment that did not fit in its table.
+pub struct Overflow {
+    pub segment: i32,
+    pub points: usize,
+    pub pages: usize,
+}
+
+pub struct TypstDocument {
+    pub source: String,
+    pub overflows: Vec<Overflow>,
+}
+
+fn document_data(backend: &Backend) -> Result<Document, Error> {
+    let parameters = backend.get_parameters()?;
+    let debug = parameters.debug;
+    let fsegments = backend.segments()?;
 
     let pacing_and_controls = HashSet::from([InputType::UserStep, InputType::Control]);
     let mut all_points = BTreeMap::new();
@@ -122,21 +353,31 @@ pub fn make_typst_document(backend: &Backend) -> String {
     let all_waypoints = backend.export_points(&vector);
     let allkinds = inputpoint::allkinds();
 
-    for segment in &segments {
+    let mut segments = Vec::new();
+    for fsegment in &fsegments {
+        let segment = backend.make_segment_data(fsegment);
         let range = segment.range();
         if range.is_empty() {
             continue;
         }
-        let mut waypoints_table: Vec<_> = all_waypoints
+        let waypoints: Vec<_> = all_waypoints
             .iter()
             .filter(|w| range.contains(&w.track_index.unwrap()))
             .collect();
-        waypoints_table.truncate(15);
-        let table = points_table(&templates, &backend.d().track, &waypoints_table);
+        let mut points = points_table(&waypoints, &parameters.points_table);
+        let mut overflow = Vec::new();
+        if points.len() > parameters.max_table_rows {
+            let mut rest = points.split_off(parameters.max_table_rows);
+            while !rest.is_empty() {
+                let next = rest.split_off(rest.len().min(parameters.max_table_rows));
+                overflow.push(rest);
+                rest = next;
+            }
+        }
         let profile_size = Size2D::new(1420, 400);
         let map_size = Size2D::new(400, 400);
         let rendered_profile = segment.render_profile(&profile_size, &allkinds);
-        if backend.get_parameters().debug {
+        if debug {
             let f = format!("/tmp/segment-{}.svg", segment.id());
             std::fs::write(&f, &rendered_profile.svg).unwrap();
         }
@@ -145,11 +386,168 @@ pub fn make_typst_document(backend: &Backend) -> String {
             let f = format!("/tmp/map-{}.svg", segment.id());
             std::fs::write(&f, &m).unwrap();
         }
-        log::trace!("link segment {}", segment.id());
-        link(&templates, &rendered_profile.svg, &m, &table, &mut document);
+        log::trace!("data of segment {}", segment.id());
+        segments.push(Segment {
+            id: segment.id(),
+            start: segment.start(),
+            end: segment.end(),
+            statistics: Statistics::new(&backend.segment_statistics(fsegment)?),
+            profile: rendered_profile.svg,
+            map: m,
+            points,
+            overflow,
+        });
         if range.end == backend.d().track.len() {
             break;
         }
     }
-    document
+    Ok(Document {
+        columns: parameters
+            .points_table
+            .iter()
+            .map(|column| column.title.clone())
+            .collect(),
+        start_time: parameters.start_time.clone(),
+        statistics: Statistics::new(&backend.statistics()?),
+        segments,
+    })
+}
+
+pub fn make_typst_document(backend: &Backend) -> Result<TypstDocument, Error> {
+    let templates = match &backend.get_parameters()?.templates {
+        Some(directory) => Templates::load(Path::new(directory))?,
+        None => Templates::builtin(),
+    };
+    let data = document_data(backend)?;
+    let overflows = data
+        .segments
+        .iter()
+        .filter(|segment| !segment.overflow.is_empty())
+        .map(|segment| Overflow {
+            segment: segment.id,
+            points: segment.overflow.iter().map(|page| page.len()).sum(),
+            pages: segment.overflow.len(),
+        })
+        .collect();
+    let mut document = typst_data(&data)?;
+    document.push_str(&templates.header);
+    document.push('\n');
+    document.push_str(&templates.table_points);
+    document.push('\n');
+    document.push_str(&templates.table_large);
+    document.push_str(
+        "
+#for segment in wpx.segments {
+    pagebreak(weak: true)
+    segment-page(segment)
+    for points in segment.overflow {
+        pagebreak(weak: true)
+        points-table(points)
+    }
+}
+",
+    );
+    Ok(TypstDocument {
+        source: document,
+        overflows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals() {
+        let value = serde_json::json!({
+            "a": [1, 2.5, 3.0, -4],
+            "b": "say \"hi\"\n\\",
+            "c": null,
+            "d": [true],
+            "e": {},
+            "f": [],
+        });
+        let mut out = String::new();
+        typst_value(&value, &mut out);
+        assert_eq!(
+            out,
+            "(\"a\":(1,2.5,3.0,-4,),\"b\":\"say \\\"hi\\\"\\n\\\\\",\"c\":none,\
+             \"d\":(true,),\"e\":(:),\"f\":(),)"
+        );
+    }
+
+    #[test]
+    fn templates() {
+        assert!(Templates::builtin().problems().is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = "#table([*KM*], /* #line-template [{distance}], */)";
+        std::fs::write(dir.path().join(TABLE_POINTS), legacy).unwrap();
+        match Templates::load(dir.path()) {
+            Err(Error::TemplateInvalid { problems }) => {
+                assert_eq!(problems.len(), 2);
+                assert!(problems[0].contains("points-table(points)"));
+                assert!(problems[1].contains("#line-template"));
+            }
+            _ => assert!(false),
+        }
+        let code = "#let points-table(points) = {\n  let f(name) = {name}\n}\n";
+        std::fs::write(dir.path().join(TABLE_POINTS), code).unwrap();
+        assert!(Templates::load(dir.path()).is_ok());
+
+        let header = "#set text(size: 12pt)\n";
+        std::fs::write(dir.path().join(HEADER), header).unwrap();
+        std::fs::remove_file(dir.path().join(TABLE_POINTS)).unwrap();
+        let loaded = Templates::load(dir.path()).unwrap();
+        assert_eq!(loaded.header, header);
+        assert_eq!(loaded.table_points, Templates::builtin().table_points);
+
+        let exported = dir.path().join("exported");
+        Templates::builtin().write(&exported).unwrap();
+        assert!(Templates::load(&exported).is_ok());
+        assert!(Templates::load(&dir.path().join("missing")).is_err());
+    }
+
+    #[tokio::test]
+    async fn document() {
+        let mut backend = Backend::make();
+        backend.load_filename("data/blackforest.gpx").await.unwrap();
+        let document = make_typst_document(&backend).unwrap().source;
+        assert!(document.starts_with("#let wpx = (\"columns\":(\"KM\","));
+        assert!(document.contains("\"segments\":((\""));
+        assert!(document.contains("\"start_time\":"));
+        assert!(document.contains("#let points-table(points)"));
+        assert!(document.contains("points-table(points)\n    }\n}\n"));
+
+        // 10 km steps on the 150 km track, 4 rows per table.
+        let mut parameters = backend.get_parameters().unwrap();
+        parameters.user_steps_options.step_distance = Some(10_000f64);
+        parameters.max_table_rows = 4;
+        backend.set_parameters(&parameters).unwrap();
+        let data = document_data(&backend).unwrap();
+        let document = make_typst_document(&backend).unwrap();
+        assert_eq!(data.columns.len(), parameters.points_table.len());
+        for segment in &data.segments {
+            assert!(segment.points.len() <= 4);
+            assert!(segment
+                .points
+                .iter()
+                .all(|p| p.cells.len() == data.columns.len()));
+            assert!(segment.overflow.iter().all(|page| page.len() <= 4));
+        }
+        let count = |segment: &Segment| {
+            segment.points.len() + segment.overflow.iter().map(|p| p.len()).sum::<usize>()
+        };
+        let shown: usize = data.segments.iter().map(count).sum();
+        assert!(shown > 15);
+        assert!(!document.overflows.is_empty());
+
+        let mut parameters = backend.get_parameters().unwrap();
+        parameters.templates = Some("/nonexistent".to_string());
+        backend.set_parameters(&parameters).unwrap();
+        match make_typst_document(&backend) {
+            Err(Error::TemplateInvalid { .. }) => {}
+            _ => assert!(false),
+        }
+    }
 }
@@ -3,9 +3,10 @@ use crate::inputpoint::{InputPoint, InputType, Kinds, SharedPointMaps};
 use crate::math::IntegerSize2D;
 use crate::parameters::Parameters;
 use crate::profile::ProfileRenderResult;
+use crate::speed::{self, TimeTable};
 use crate::tile::Tiles;
 use crate::track::SharedTrack;
-use crate::{profile, svgmap, tile};
+use crate::{profile, sun, svgmap, tile};
 
 #[derive(Clone)]
 pub struct Segment {
@@ -20,6 +21,8 @@ pub struct SegmentData {
     pub boxes: Tiles,
     _pointmaps: SharedPointMaps,
     pub parameters: Parameters,
+    pub timetable: TimeTable,
+    daylight: sun::Daylight,
 }
 
 pub struct SegmentStatistics {
@@ -37,6 +40,14 @@ impl SegmentData {
         parameters: Parameters,
     ) -> SegmentData {
         let boxes = track.subboxes(segment.start, segment.end);
+        let timetable = speed::make_timetable(&track, &parameters, &inputpoints.read().unwrap());
+        let daylight = match parameters.start_time.parse() {
+            Ok(start) => sun::daylight(&track, &timetable, &start),
+            Err(_) => sun::Daylight {
+                events: Vec::new(),
+                dark: Vec::new(),
+            },
+        };
         SegmentData {
             segment: segment.clone(),
             track,
@@ -44,6 +55,8 @@ impl SegmentData {
             _pointmaps: inputpoints.clone(),
             //pointmaps: SharedPointMaps::new(InputPointMaps::new().into()),
             parameters: parameters.clone(),
+            timetable,
+            daylight,
         }
     }
 
@@ -60,6 +73,9 @@ impl SegmentData {
     }
 
     pub fn points(&self, kind: &InputType) -> Vec<InputPoint> {
+        if *kind == InputType::Sun {
+            return self.sun_points();
+        }
         let bbox = tile::bounding_box(&self.boxes);
         let range = self.range();
         let lock = self._pointmaps.read().unwrap();
@@ -71,6 +87,7 @@ impl SegmentData {
         map.unwrap()
             .points_in(&bbox)
             .filter(|w| w.track_projections.is_empty() || w.is_in_range(&range))
+            .filter(|w| self.parameters.shows(w))
             .map(|w| w.clone())
             .collect()
     }
@@ -83,6 +100,21 @@ impl SegmentData {
         self.track.subrange(self.segment.start, self.segment.end)
     }
 
+    pub fn daylight(&self) -> &sun::Daylight {
+        &self.daylight
+    }
+
+    // sunrise and sunset on the segment
+    pub fn sun_points(&self) -> Vec<InputPoint> {
+        let range = self.range();
+        self.daylight()
+            .events
+            .iter()
+            .filter(|e| range.contains(&e.track_index))
+            .map(|e| InputPoint::create_sun_on_track(&self.track, e.track_index, &e.kind.name()))
+            .collect()
+    }
+
     pub fn map_box(&self) -> BoundingBox {
         svgmap::euclidean_bounding_box(&self.track, &self.range())
     }
@@ -1,4 +1,11 @@
-use crate::{mercator::DateTime, parameters::Parameters};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    inputpoint::{InputPointMaps, InputType},
+    mercator::DateTime,
+    parameters::Parameters,
+    track::Track,
+};
 
 // from mps to kmh
 pub fn _kmh(_mps: f64) -> f64 {
@@ -11,10 +18,435 @@ pub fn mps(_kmh: f64) -> f64 {
     _kmh / 3.6f64
 }
 
-pub fn time_at_distance(distance: &f64, parameters: &Parameters) -> DateTime {
-    use chrono::*;
-    let start_time: DateTime<Utc> = parameters.start_time.parse().unwrap();
-    let dt = (distance / parameters.speed).ceil() as i64;
+const GRAVITY: f64 = 9.81;
+const AIR_DENSITY: f64 = 1.225;
+// below this speed, we consider the rider walks.
+const MIN_SPEED: f64 = 1.0;
+// no dwell at controls closer than this to the start or the end.
+const STARTEND_DISTANCE: f64 = 1000.0;
+
+// speed (m/s) on a given slope (ratio, 0.05 means 5%)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlopeSpeed {
+    pub slope: f64,
+    pub speed: f64,
+}
+
+// rider and bike (SI units: W, kg, m^2)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PhysicsModel {
+    pub power: f64,
+    pub mass: f64,
+    pub cda: f64,
+    pub crr: f64,
+    pub max_speed: f64,
+}
+
+impl Default for PhysicsModel {
+    fn default() -> PhysicsModel {
+        PhysicsModel {
+            power: 150f64,
+            mass: 85f64,
+            cda: 0.4f64,
+            crr: 0.005f64,
+            max_speed: mps(50f64),
+        }
+    }
+}
+
+// a pause of `duration` seconds at `distance` meters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stop {
+    pub distance: f64,
+    pub duration: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SpeedModel {
+    // Parameters::speed everywhere
+    Constant,
+    // piecewise linear in the slope, constant outside of the table
+    SlopeTable(Vec<SlopeSpeed>),
+    Physics(PhysicsModel),
+}
+
+// the table is sorted by slope (see SpeedModel::sorted).
+fn table_speed(sorted: &[SlopeSpeed], slope: f64) -> f64 {
+    let first = sorted.first().unwrap();
+    let last = sorted.last().unwrap();
+    if slope <= first.slope {
+        return first.speed;
+    }
+    if slope >= last.slope {
+        return last.speed;
+    }
+    let k = sorted.partition_point(|s| s.slope < slope);
+    let (a, b) = (&sorted[k - 1], &sorted[k]);
+    let t = (slope - a.slope) / (b.slope - a.slope);
+    a.speed + t * (b.speed - a.speed)
+}
+
+impl PhysicsModel {
+    /*
+     * Solve power = v * (m*g*(crr*cos + sin) + 0.5*rho*CdA*v^2) for v.
+     * The left side minus the right side is negative at v=0 and
+     * increasing after its (only) minimum, so there is a single
+     * positive root, which we find by bisection.
+     */
+    pub fn speed(&self, slope: f64) -> f64 {
+        let theta = slope.atan();
+        let force = self.mass * GRAVITY * (self.crr * theta.cos() + theta.sin());
+        let a = 0.5 * AIR_DENSITY * self.cda;
+        let f = |v: f64| a * v * v * v + force * v - self.power;
+        let (mut lo, mut hi) = (0f64, 100f64);
+        for _ in 0..60 {
+            let mid = 0.5 * (lo + hi);
+            if f(mid) < 0f64 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo.min(self.max_speed)
+    }
+}
+
+impl SpeedModel {
+    // the model with its slope table sorted, as speed() expects it.
+    pub fn sorted(&self) -> SpeedModel {
+        match self {
+            SpeedModel::SlopeTable(table) => {
+                let mut sorted = table.clone();
+                sorted.sort_by(|a, b| a.slope.total_cmp(&b.slope));
+                SpeedModel::SlopeTable(sorted)
+            }
+            _ => self.clone(),
+        }
+    }
+
+    // self must be sorted().
+    pub fn speed(&self, slope: f64, parameters: &Parameters) -> f64 {
+        let ret = match self {
+            SpeedModel::Constant => parameters.speed,
+            SpeedModel::SlopeTable(table) if !table.is_empty() => table_speed(table, slope),
+            SpeedModel::SlopeTable(_) => parameters.speed,
+            SpeedModel::Physics(model) => model.speed(slope),
+        };
+        ret.max(MIN_SPEED)
+    }
+}
+
+/*
+ * Elapsed time (seconds) at each point of the track, integrated
+ * with the speed model along the smoothed elevation, plus the stops.
+ * The time at a stop is the arrival time, the stop shifts the times
+ * of the points after it.
+ */
+#[derive(Clone, Debug)]
+pub struct TimeTable {
+    distance: Vec<f64>,
+    seconds: Vec<f64>,
+    stops: Vec<Stop>,
+}
+
+// index of the last element <= x in a sorted vector (0 if none)
+fn lower_index(v: &[f64], x: f64) -> usize {
+    v.partition_point(|d| *d <= x).max(1) - 1
+}
+
+fn interpolate(xs: &[f64], ys: &[f64], x: f64) -> f64 {
+    if xs.is_empty() {
+        return 0f64;
+    }
+    let k = lower_index(xs, x);
+    if k + 1 >= xs.len() {
+        return ys[xs.len() - 1];
+    }
+    let dx = xs[k + 1] - xs[k];
+    if dx <= 0f64 {
+        return ys[k];
+    }
+    let t = ((x - xs[k]) / dx).clamp(0f64, 1f64);
+    ys[k] + t * (ys[k + 1] - ys[k])
+}
+
+impl TimeTable {
+    pub fn new(track: &Track, parameters: &Parameters) -> TimeTable {
+        let model = parameters.speed_model.sorted();
+        let mut distance = Vec::with_capacity(track.len());
+        let mut seconds = Vec::with_capacity(track.len());
+        for k in 0..track.len() {
+            let d = track.distance(k);
+            let t = match k {
+                0 => 0f64,
+                _ => {
+                    let dx = d - distance[k - 1];
+                    let dt = match dx > 0f64 {
+                        true => {
+                            let dy = track.smooth_elevation[k] - track.smooth_elevation[k - 1];
+                            let v = model.speed(dy / dx, parameters);
+                            dx / v
+                        }
+                        false => 0f64,
+                    };
+                    seconds[k - 1] + dt
+                }
+            };
+            distance.push(d);
+            seconds.push(t);
+        }
+        TimeTable {
+            distance,
+            seconds,
+            stops: Vec::new(),
+        }
+    }
+
+    pub fn with_stops(mut self, stops: &[Stop]) -> TimeTable {
+        self.stops = stops
+            .iter()
+            .filter(|s| s.duration > 0f64)
+            .cloned()
+            .collect();
+        self.stops.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        self
+    }
+
+    // used for tests and when there is no track.
+    pub fn constant(total_distance: f64, speed: f64) -> TimeTable {
+        TimeTable {
+            distance: vec![0f64, total_distance],
+            seconds: vec![0f64, total_distance / speed],
+            stops: Vec::new(),
+        }
+    }
+
+    fn total_distance(&self) -> f64 {
+        match self.distance.last() {
+            Some(d) => *d,
+            None => 0f64,
+        }
+    }
+
+    fn moving_seconds_at(&self, distance: f64) -> f64 {
+        interpolate(&self.distance, &self.seconds, distance)
+    }
+
+    fn stop_seconds_before(&self, distance: f64) -> f64 {
+        self.stops
+            .iter()
+            .filter(|s| s.distance < distance)
+            .map(|s| s.duration)
+            .sum()
+    }
+
+    pub fn seconds_at(&self, distance: f64) -> f64 {
+        self.moving_seconds_at(distance) + self.stop_seconds_before(distance)
+    }
+
+    pub fn distance_at(&self, seconds: f64) -> f64 {
+        let mut offset = 0f64;
+        for stop in &self.stops {
+            let arrival = self.moving_seconds_at(stop.distance) + offset;
+            if seconds <= arrival {
+                break;
+            }
+            if seconds <= arrival + stop.duration {
+                return stop.distance;
+            }
+            offset += stop.duration;
+        }
+        interpolate(&self.seconds, &self.distance, seconds - offset)
+    }
+
+    pub fn total_seconds(&self) -> f64 {
+        let total = self.total_distance();
+        self.moving_seconds_at(total) + self.stop_seconds_before(total)
+    }
+}
+
+fn point_stops(
+    track: &Track,
+    pointmaps: &InputPointMaps,
+    kind: InputType,
+    duration: f64,
+) -> Vec<Stop> {
+    let mut ret = Vec::new();
+    if duration <= 0f64 {
+        return ret;
+    }
+    let map = match pointmaps.maps.get(&kind) {
+        Some(map) => map,
+        None => {
+            return ret;
+        }
+    };
+    let total = track.total_distance();
+    // on loops and out-and-back routes a point has several projections,
+    // the rider stops at the first one.
+    for point in map.iter() {
+        let first = point
+            .track_projections
+            .iter()
+            .map(|proj| track.distance(proj.track_index))
+            .find(|d| *d >= STARTEND_DISTANCE && *d <= total - STARTEND_DISTANCE);
+        if let Some(distance) = first {
+            ret.push(Stop { distance, duration });
+        }
+    }
+    ret
+}
+
+// the dwell times at the controls and user steps, and the sleep breaks.
+pub fn stops(track: &Track, parameters: &Parameters, pointmaps: &InputPointMaps) -> Vec<Stop> {
+    let mut ret = parameters.sleeps.clone();
+    ret.extend(point_stops(
+        track,
+        pointmaps,
+        InputType::Control,
+        parameters.control_dwell,
+    ));
+    ret.extend(point_stops(
+        track,
+        pointmaps,
+        InputType::UserStep,
+        parameters.user_steps_options.dwell,
+    ));
+    ret
+}
+
+pub fn make_timetable(
+    track: &Track,
+    parameters: &Parameters,
+    pointmaps: &InputPointMaps,
+) -> TimeTable {
+    TimeTable::new(track, parameters).with_stops(&stops(track, parameters, pointmaps))
+}
+
+pub fn time_at_distance(
+    distance: &f64,
+    timetable: &TimeTable,
+    parameters: &Parameters,
+) -> DateTime {
+    // the backend only accepts parameters with a valid start time.
+    let start_time = parameters.start().unwrap_or_default();
+    let dt = timetable.seconds_at(*distance).ceil() as i64;
     let delta = chrono::TimeDelta::new(dt, 0).unwrap();
     start_time + delta
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slope_table() {
+        let table = vec![
+            SlopeSpeed {
+                slope: 0.05,
+                speed: 3.0,
+            },
+            SlopeSpeed {
+                slope: -0.05,
+                speed: 12.0,
+            },
+            SlopeSpeed {
+                slope: 0.0,
+                speed: 7.0,
+            },
+        ];
+        let table = match SpeedModel::SlopeTable(table).sorted() {
+            SpeedModel::SlopeTable(sorted) => sorted,
+            _ => unreachable!(),
+        };
+        assert_eq!(table_speed(&table, 0.0), 7.0);
+        assert_eq!(table_speed(&table, 0.025), 5.0);
+        assert_eq!(table_speed(&table, 0.2), 3.0);
+        assert_eq!(table_speed(&table, -0.2), 12.0);
+    }
+
+    #[test]
+    fn physics() {
+        let model = PhysicsModel::default();
+        let flat = model.speed(0.0);
+        let climb = model.speed(0.08);
+        let descent = model.speed(-0.08);
+        // about 28 km/h on the flat with 150W
+        assert!(_kmh(flat) > 25.0 && _kmh(flat) < 31.0);
+        assert!(climb < flat && _kmh(climb) < 10.0);
+        assert!(descent > flat && descent <= model.max_speed);
+    }
+
+    #[test]
+    fn stops() {
+        let timetable = TimeTable::constant(10000.0, 10.0).with_stops(&[
+            Stop {
+                distance: 5000.0,
+                duration: 600.0,
+            },
+            Stop {
+                distance: 2000.0,
+                duration: 0.0,
+            },
+        ]);
+        assert_eq!(timetable.total_seconds(), 1600.0);
+        assert_eq!(timetable.seconds_at(4000.0), 400.0);
+        // arrival time at the stop
+        assert_eq!(timetable.seconds_at(5000.0), 500.0);
+        assert_eq!(timetable.seconds_at(6000.0), 1200.0);
+        assert_eq!(timetable.distance_at(800.0), 5000.0);
+        assert_eq!(timetable.distance_at(1200.0), 6000.0);
+        assert_eq!(timetable.distance_at(300.0), 3000.0);
+    }
+
+    #[test]
+    fn point_projected_twice() {
+        let gpx = std::fs::read("data/blackforest.gpx").unwrap();
+        let data = crate::gpsdata::read_content(&gpx).unwrap();
+        let track = Track::from_tracks(&data.tracks).unwrap();
+        let (a, b) = (track.len() / 3, 2 * track.len() / 3);
+        let mut point = crate::inputpoint::InputPoint::create_control_on_track(&track, a, "K1", "");
+        let mut second = point.track_projections.first().unwrap().clone();
+        second.track_index = b;
+        second.track_floating_index = b as f64;
+        point.track_projections.insert(second);
+        let mut map = crate::inputpoint::InputPointMap::new();
+        map.insert_point(&point);
+        let mut pointmaps = InputPointMaps::new();
+        pointmaps.maps.insert(InputType::Control, map);
+        let stops = point_stops(&track, &pointmaps, InputType::Control, 600.0);
+        assert_eq!(
+            stops,
+            vec![Stop {
+                distance: track.distance(a),
+                duration: 600.0
+            }]
+        );
+    }
+
+    #[test]
+    fn timetable() {
+        let gpx = std::fs::read("data/blackforest.gpx").unwrap();
+        let data = crate::gpsdata::read_content(&gpx).unwrap();
+        let track = Track::from_tracks(&data.tracks).unwrap();
+        let mut parameters = Parameters::default();
+        let constant = TimeTable::new(&track, &parameters);
+        let total = track.total_distance();
+        assert!((constant.total_seconds() - total / parameters.speed).abs() < 1e-3);
+        assert!(
+            (constant.distance_at(constant.seconds_at(total / 3.0)) - total / 3.0).abs() < 1e-3
+        );
+
+        parameters.speed_model = SpeedModel::Physics(PhysicsModel::default());
+        let physics = TimeTable::new(&track, &parameters);
+        let flat = PhysicsModel::default().speed(0.0);
+        // the climbing costs more than the descents give back
+        assert!(physics.total_seconds() > total / flat);
+        for k in 1..track.len() {
+            assert!(
+                physics.seconds_at(track.distance(k)) >= physics.seconds_at(track.distance(k - 1))
+            );
+        }
+    }
+}
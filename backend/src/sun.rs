@@ -0,0 +1,176 @@
+use crate::{mercator::DateTime, speed::TimeTable, track::Track};
+
+/*
+ * Position of the sun (low precision formulas from the Astronomical
+ * Almanac, good to about 0.01 degree), evaluated at each point of
+ * the track at its predicted time.
+ */
+
+// the sun is below the horizon (refraction and radius of the disc included).
+const HORIZON: f64 = -0.833;
+
+fn degrees_mod(x: f64) -> f64 {
+    x.rem_euclid(360f64)
+}
+
+// elevation of the sun above the horizon in degrees.
+pub fn elevation(lon: f64, lat: f64, time: &DateTime) -> f64 {
+    let julian = time.timestamp() as f64 / 86400f64 + 2440587.5;
+    let n = julian - 2451545.0;
+    let mean_longitude = degrees_mod(280.460 + 0.9856474 * n);
+    let mean_anomaly = degrees_mod(357.528 + 0.9856003 * n).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.0000004 * n).to_radians();
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let sidereal = degrees_mod(280.46061837 + 360.98564736629 * n + lon);
+    let hour_angle = sidereal.to_radians() - right_ascension;
+    let lat = lat.to_radians();
+    (lat.sin() * declination.sin() + lat.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+pub fn is_dark(lon: f64, lat: f64, time: &DateTime) -> bool {
+    elevation(lon, lat, time) < HORIZON
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SunEventKind {
+    Sunrise,
+    Sunset,
+}
+
+impl SunEventKind {
+    pub fn name(&self) -> String {
+        match self {
+            SunEventKind::Sunrise => "Sunrise".to_string(),
+            SunEventKind::Sunset => "Sunset".to_string(),
+        }
+    }
+}
+
+// first track point after the sunrise/sunset
+#[derive(Clone, Debug)]
+pub struct SunEvent {
+    pub kind: SunEventKind,
+    pub track_index: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Daylight {
+    pub events: Vec<SunEvent>,
+    // distance ranges (meters) ridden in the dark
+    pub dark: Vec<std::ops::Range<f64>>,
+}
+
+// the sun moves slowly compared to a bike: sample the track every
+// SAMPLE_DISTANCE and only look at every point where the state changes.
+const SAMPLE_DISTANCE: f64 = 500f64;
+
+fn dark_at(track: &Track, timetable: &TimeTable, start: &DateTime, k: usize) -> bool {
+    let seconds = timetable.seconds_at(track.distance(k)).round() as i64;
+    let time = *start + chrono::TimeDelta::seconds(seconds);
+    let wgs = &track.wgs84[k];
+    is_dark(wgs.longitude(), wgs.latitude(), &time)
+}
+
+pub fn daylight(track: &Track, timetable: &TimeTable, start: &DateTime) -> Daylight {
+    let mut events = Vec::new();
+    let mut dark = Vec::new();
+    if track.len() == 0 {
+        return Daylight { events, dark };
+    }
+    let mut dark_start = None;
+    let mut prev = 0;
+    let mut prev_dark = dark_at(track, timetable, start, prev);
+    if prev_dark {
+        dark_start = Some(track.distance(prev));
+    }
+    while prev + 1 < track.len() {
+        let mut next = prev + 1;
+        while next + 1 < track.len()
+            && track.distance(next) - track.distance(prev) < SAMPLE_DISTANCE
+        {
+            next += 1;
+        }
+        let next_dark = dark_at(track, timetable, start, next);
+        if next_dark != prev_dark {
+            // first point with the new state
+            let mut k = prev + 1;
+            while k < next && dark_at(track, timetable, start, k) == prev_dark {
+                k += 1;
+            }
+            let distance = track.distance(k);
+            match dark_start {
+                None => {
+                    events.push(SunEvent {
+                        kind: SunEventKind::Sunset,
+                        track_index: k,
+                    });
+                    dark_start = Some(distance);
+                }
+                Some(begin) => {
+                    events.push(SunEvent {
+                        kind: SunEventKind::Sunrise,
+                        track_index: k,
+                    });
+                    dark.push(begin..distance);
+                    dark_start = None;
+                }
+            }
+        }
+        prev = next;
+        prev_dark = next_dark;
+    }
+    if let Some(begin) = dark_start {
+        dark.push(begin..track.total_distance());
+    }
+    Daylight { events, dark }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elevation_at_noon() {
+        // equinox, near solar noon on the prime meridian
+        let time: DateTime = "2024-03-20T12:07:00Z".parse().unwrap();
+        let e = elevation(0.0, 0.0, &time);
+        assert!(e > 88.0, "{}", e);
+        // Freiburg in summer: sunrise about 5:20 local (3:20 UTC), sunset 21:35 (19:35 UTC)
+        let (lon, lat) = (7.85, 48.0);
+        assert!(is_dark(lon, lat, &"2024-06-21T03:05:00Z".parse().unwrap()));
+        assert!(!is_dark(lon, lat, &"2024-06-21T03:35:00Z".parse().unwrap()));
+        assert!(!is_dark(lon, lat, &"2024-06-21T19:20:00Z".parse().unwrap()));
+        assert!(is_dark(lon, lat, &"2024-06-21T19:50:00Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn daylight() {
+        let gpx = std::fs::read("data/blackforest.gpx").unwrap();
+        let data = crate::gpsdata::read_content(&gpx).unwrap();
+        let track = Track::from_tracks(&data.tracks).unwrap();
+        let parameters = crate::parameters::Parameters::default();
+        let timetable = TimeTable::new(&track, &parameters);
+        // start one hour before sunset
+        let start: DateTime = "2024-06-21T18:30:00Z".parse().unwrap();
+        let result = super::daylight(&track, &timetable, &start);
+        assert_eq!(result.events[0].kind, SunEventKind::Sunset);
+        assert_eq!(result.events[1].kind, SunEventKind::Sunrise);
+        // sampling finds the same point as looking at every point
+        let first_dark = (0..track.len())
+            .find(|k| dark_at(&track, &timetable, &start, *k))
+            .unwrap();
+        assert_eq!(result.events[0].track_index, first_dark);
+        let sunset = track.distance(result.events[0].track_index);
+        // one hour at 15 km/h
+        assert!((sunset - 15000.0).abs() < 5000.0, "{}", sunset);
+        assert_eq!(result.dark[0].start, sunset);
+        assert!(result.dark[0].end > result.dark[0].start);
+    }
+}
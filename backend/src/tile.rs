@@ -2,10 +2,7 @@ use std::{cmp::Ordering, collections::BTreeSet};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    bbox::*, error::GenericResult, inputpoint::InputPointMap, math::Point2D,
-    mercator::MercatorPoint,
-};
+use crate::{bbox::*, inputpoint::InputPointMap, math::Point2D, mercator::MercatorPoint};
 
 fn floor_snap_index(x: f64, step: f64) -> isize {
     (x / step).floor() as isize
@@ -91,14 +88,6 @@ impl Chunk {
     pub fn basename(&self) -> String {
         format!("{:03}-{:03}", self.coord.0, self.coord.1)
     }
-    pub fn load_map(&mut self, data: &str) -> GenericResult<()> {
-        let data = InputPointMap::from_string(data)?;
-        self.data = data;
-        Ok(())
-    }
-    pub fn map_as_string(&self) -> String {
-        self.data.as_string().unwrap()
-    }
     pub fn contains(&self, tile: &Tile) -> bool {
         tile.chunk_coord() == self.coord
     }
@@ -16,16 +16,20 @@ use crate::tile::Tiles;
 
 use super::elevation;
 
+#[derive(Clone)]
 pub struct TrackPart {
     pub name: String,
     pub begin: usize,
     pub end: usize,
 }
 
+#[derive(Clone)]
 pub struct Track {
     pub wgs84: Vec<WGS84Point>,
+    // computed with elevation_model
     pub smooth_elevation: Vec<f64>,
     pub smooth_elevation_gain: Vec<f64>,
+    pub elevation_model: elevation::ElevationModel,
     pub euclidean: Vec<MercatorPoint>,
     _distance: Vec<f64>,
     pub parts: Vec<TrackPart>,
@@ -163,22 +167,12 @@ impl Track {
         ret
     }
 
-    fn compute_elevation_gain(smooth_elevation: &Vec<f64>) -> Vec<f64> {
-        let mut ret = vec![0f64; smooth_elevation.len()];
-        let range = std::ops::Range {
-            start: 0,
-            end: smooth_elevation.len(),
-        };
-        for k in range.start + 1..range.end {
-            let d = smooth_elevation[k] - smooth_elevation[k - 1];
-            if d > 0.0 {
-                ret[k] = ret[k - 1] + d;
-            } else {
-                ret[k] = ret[k - 1];
-            }
-        }
-        assert_eq!(ret.len(), smooth_elevation.len());
-        ret
+    pub fn set_elevation_model(&mut self, model: &elevation::ElevationModel) {
+        let elevation: Vec<_> = self.wgs84.iter().map(|w| w.z()).collect();
+        self.smooth_elevation = elevation::smooth_with(model, &self._distance, &elevation);
+        self.smooth_elevation_gain =
+            elevation::cumulative_gain(&self.smooth_elevation, &model.gain);
+        self.elevation_model = model.clone();
     }
 
     pub fn from_tracks(gpxtracks: &Vec<gpx::Track>) -> Result<Track, error::Error> {
@@ -207,8 +201,10 @@ impl Track {
                     euclidean.push(projection.project(&w));
                     wgs.push(w);
 
-                    if k > 0 {
-                        dacc += distance_wgs84(&wgs[k - 1], &wgs[k]);
+                    // the distance between two consecutive parts is counted.
+                    let n = wgs.len();
+                    if n > 1 {
+                        dacc += distance_wgs84(&wgs[n - 2], &wgs[n - 1]);
                     }
                     _distance.push(dacc);
                 }
@@ -223,16 +219,6 @@ impl Track {
         }
         assert_eq!(_distance.len(), wgs.len());
 
-        let track_smooth_elevation = elevation::smooth(
-            200f64,
-            wgs.len(),
-            |index: usize| -> f64 { _distance[index] },
-            |index: usize| -> f64 { wgs[index].z() },
-        );
-        assert_eq!(track_smooth_elevation.len(), wgs.len());
-
-        let smooth_elevation_gain = Self::compute_elevation_gain(&track_smooth_elevation);
-
         let mut boxes = Tiles::new();
         for e in &euclidean {
             boxes.insert(tile::Tile::for_point(&e));
@@ -246,16 +232,18 @@ impl Track {
         }
 
         let tree = locate::IndexedPointsTree::from_track(&euclidean, &(0..euclidean.len()));
-        let ret = Track {
+        let mut ret = Track {
             wgs84: wgs,
             euclidean,
-            smooth_elevation: track_smooth_elevation,
-            smooth_elevation_gain,
+            smooth_elevation: Vec::new(),
+            smooth_elevation_gain: Vec::new(),
+            elevation_model: elevation::ElevationModel::default(),
             _distance,
             parts,
             tiles: boxes,
             tree,
         };
+        ret.set_elevation_model(&elevation::ElevationModel::default());
         Ok(ret)
     }
 
@@ -5,6 +5,7 @@ use crate::{
     inputpoint::{InputPoint, InputPointMap, InputType, OSMType},
     locate,
     mercator::MercatorPoint,
+    osm::category,
     track::Track,
 };
 
@@ -57,19 +58,26 @@ pub fn is_close_to_track(w: &InputPoint) -> bool {
         return false;
     }
     let d = w.track_projections.first().unwrap().track_distance;
+    // points of interest other than places have their own max distance
+    let osmkind = match w.kind() {
+        InputType::OSM => w.osmkind(),
+        _ => None,
+    };
+    if let Some(kind) = &osmkind {
+        let category = category::get(kind);
+        if !category.is_place() {
+            return d < category.max_distance;
+        }
+    }
     let dmin = 300f64;
     if d < dmin {
         return true;
     }
-    match w.kind() {
-        InputType::OSM => {
-            let kind = w.osmkind().unwrap();
-            let pop = w.population().unwrap_or(population_estimate(&kind));
-            // the factor 20 was suggested by gemini
-            let radius = 20f64 * (pop as f64).sqrt();
-            return d < radius;
-        }
-        _ => {}
+    if let Some(kind) = osmkind {
+        let pop = w.population().unwrap_or(population_estimate(&kind));
+        // the factor 20 was suggested by gemini
+        let radius = 20f64 * (pop as f64).sqrt();
+        return d < radius;
     }
     return d < dmin;
 }
@@ -77,11 +85,13 @@ pub fn is_close_to_track(w: &InputPoint) -> bool {
 fn dmax(kind: &InputType, osmkind: &Option<OSMType>, population: &Option<i32>) -> f64 {
     match kind {
         InputType::OSM => {
-            let okind = osmkind.as_ref().unwrap();
             let pop = population.unwrap_or(0);
-            if *okind == OSMType::City || pop > 1000 {
+            if pop > 1000 {
                 return 2000.0;
             }
+            if let Some(okind) = osmkind {
+                return category::get(okind).max_distance;
+            }
         }
         _ => {}
     }
@@ -1,6 +1,6 @@
 use crate::{
-    elevation, inputpoint::InputType, mercator::MercatorPoint, parameters::Parameters, speed,
-    track, wgs84point::WGS84Point,
+    brevet, inputpoint::InputType, mercator::MercatorPoint, parameters::Parameters, speed, track,
+    wgs84point::WGS84Point,
 };
 
 #[derive(Clone)]
@@ -11,9 +11,20 @@ pub struct WaypointInfo {
     pub inter_distance: f64,
     pub inter_elevation_gain: f64,
     pub inter_slope: f64,
+    // from the start, and to the end of the track (meters)
+    pub elevation_gain: f64,
+    pub remaining_distance: f64,
+    pub remaining_elevation_gain: f64,
+    // meters to the next control of the exported points
+    pub next_control: Option<f64>,
     pub name: String,
     pub origin: InputType,
     pub time: String,
+    // seconds since the start
+    pub elapsed: f64,
+    // brevet control opening and closing times (rfc3339), for controls only
+    pub open: Option<String>,
+    pub close: Option<String>,
     pub track_index: Option<usize>,
     pub description: String,
 }
@@ -25,6 +36,33 @@ impl WaypointInfo {
         use crate::format;
         format::make_gpx_name(data, parameters)
     }
+    // the format (see format.rs) with the values of this point.
+    pub fn format(&self, format: &str) -> String {
+        let parse = |t: &str| {
+            chrono::DateTime::parse_from_rfc3339(t)
+                .ok()
+                .map(|t| t.with_timezone(&chrono::Utc))
+        };
+        let data = WaypointInfoData {
+            distance: self.distance,
+            elevation: self.elevation,
+            inter_distance: self.inter_distance,
+            inter_elevation_gain: self.inter_elevation_gain,
+            inter_slope: self.inter_slope,
+            elevation_gain: self.elevation_gain,
+            remaining_distance: self.remaining_distance,
+            remaining_elevation_gain: self.remaining_elevation_gain,
+            next_control: self.next_control,
+            name: self.name.clone(),
+            description: self.description.clone(),
+            origin: self.origin.clone(),
+            time: parse(&self.time).unwrap_or_default(),
+            elapsed: self.elapsed,
+            open: self.open.as_deref().and_then(parse),
+            close: self.close.as_deref().and_then(parse),
+        };
+        crate::format::format_waypoint(format, &data)
+    }
     pub fn profile_label(&self) -> String {
         if !self.name.is_empty() {
             return self.name.clone();
@@ -73,22 +111,45 @@ impl Waypoint {
 }
 
 impl WaypointInfo {
+    fn control_times(
+        track: &track::Track,
+        parameters: &Parameters,
+        w: &Waypoint,
+        distance: f64,
+    ) -> Option<brevet::ControlTimes> {
+        if w.origin != InputType::Control {
+            return None;
+        }
+        let rules = parameters.brevet_rules.as_ref()?;
+        let nominal = match parameters.brevet_distance {
+            Some(d) => d,
+            None => rules.nominal_distance(track.total_distance()),
+        };
+        let start = parameters.start_time.parse().ok()?;
+        Some(rules.control_times(distance, nominal, &start))
+    }
+
     fn create_waypoint_info(
         track: &track::Track,
-        smooth: &Vec<f64>,
+        timetable: &speed::TimeTable,
         parameters: &Parameters,
         w: &Waypoint,
         wprev: Option<&Waypoint>,
+        next_control: Option<&Waypoint>,
     ) -> WaypointInfo {
         assert!(w.get_track_index() < track.len());
         let distance = track.distance(w.get_track_index());
+        let elevation_gain = track.elevation_gain(w.get_track_index());
+        let remaining_distance = track.total_distance() - distance;
+        let remaining_elevation_gain = track.elevation_gain(track.len() - 1) - elevation_gain;
+        let next_control = next_control.map(|c| track.distance(c.get_track_index()) - distance);
         let (inter_distance, inter_elevation_gain, inter_slope) = match wprev {
             None => (0f64, 0f64, 0f64),
             Some(prev) => {
                 let dx =
                     track.distance(w.get_track_index()) - track.distance(prev.get_track_index());
-                let dy =
-                    elevation::elevation_gain(&smooth, prev.get_track_index(), w.get_track_index());
+                let dy = track.elevation_gain(w.get_track_index())
+                    - track.elevation_gain(prev.get_track_index());
                 let slope = match dx {
                     0f64 => 0f64,
                     _ => dy / dx,
@@ -96,7 +157,12 @@ impl WaypointInfo {
                 (dx, dy, slope)
             }
         };
-        let time = speed::time_at_distance(&distance, parameters);
+        let time = speed::time_at_distance(&distance, timetable, parameters);
+        let elapsed = match parameters.start() {
+            Ok(start) => (time - start).num_seconds() as f64,
+            Err(_) => 0f64,
+        };
+        let control_times = Self::control_times(track, parameters, w, distance);
         let name = w.name.clone();
         let description = w.description.clone();
         let elevation = track.elevation(w.get_track_index());
@@ -107,9 +173,17 @@ impl WaypointInfo {
             inter_distance,
             inter_elevation_gain,
             inter_slope,
+            elevation_gain,
+            remaining_distance,
+            remaining_elevation_gain,
+            next_control,
             name: name.clone(),
             description: description.clone(),
             origin: origin.clone(),
+            time,
+            elapsed,
+            open: control_times.as_ref().map(|t| t.open),
+            close: control_times.as_ref().map(|t| t.close),
         };
         let gpx_name = Self::make_gpx_name(&data, parameters);
         WaypointInfo {
@@ -120,8 +194,15 @@ impl WaypointInfo {
             inter_distance,
             inter_elevation_gain,
             inter_slope,
+            elevation_gain,
+            remaining_distance,
+            remaining_elevation_gain,
+            next_control,
             name,
             time: time.to_rfc3339(),
+            elapsed,
+            open: control_times.as_ref().map(|t| t.open.to_rfc3339()),
+            close: control_times.as_ref().map(|t| t.close.to_rfc3339()),
             track_index: w.track_index,
             origin,
         }
@@ -129,6 +210,7 @@ impl WaypointInfo {
     pub fn make_waypoint_infos(
         waypoints: &mut Waypoints,
         track: &track::Track,
+        timetable: &speed::TimeTable,
         parameters: &Parameters,
     ) {
         waypoints.sort_by_key(|w| w.get_track_index());
@@ -139,8 +221,11 @@ impl WaypointInfo {
                 0 => None,
                 _ => Some(&waypoints[k - 1]),
             };
+            let next_control = waypoints[k + 1..]
+                .iter()
+                .find(|next| next.origin == InputType::Control);
             let step =
-                Self::create_waypoint_info(track, &track.smooth_elevation, parameters, w, wprev);
+                Self::create_waypoint_info(track, timetable, parameters, w, wprev, next_control);
             infos.push(step.clone());
         }
         for k in 0..waypoints.len() {
@@ -367,6 +367,19 @@ pub fn render(total_size: &IntegerSize2D, model: &model::WheelModel) -> String {
 
     let features = features(&page, &model);
 
+    // night, on the ring
+    let mut dark_group = Group::new();
+    let ring_radius = 0.5 * (page.wheel_outer_radius() + page.wheel_inner_radius()) as f64;
+    for m in &model.dark_arcs {
+        let a = arc::Arc::from_model(&center, ring_radius, m);
+        let path = Path::new()
+            .set("d", a.open_path())
+            .set("fill", "none")
+            .set("stroke", "#555")
+            .set("stroke-width", page.wheel_width);
+        dark_group = dark_group.add(path);
+    }
+
     // 6. Add the central hub circle
     let center_dot = Circle::new()
         .set("cx", center.x)
@@ -375,12 +388,14 @@ pub fn render(total_size: &IntegerSize2D, model: &model::WheelModel) -> String {
         .set("fill", "#333");
 
     // 7. Assemble the final SVG
-    let assembled_group = main_group
+    let mut assembled_group = main_group
         .add(outer_circle)
         .add(inner_circle)
-        .add(middle_arc)
-        .add(features)
-        .add(center_dot);
+        .add(middle_arc);
+    if !model.dark_arcs.is_empty() {
+        assembled_group = assembled_group.add(dark_group);
+    }
+    let assembled_group = assembled_group.add(features).add(center_dot);
 
     document = document.add(assembled_group);
     document.to_string()
@@ -466,7 +481,7 @@ mod tests {
 
         let time_parameters = model::TimeParameters {
             start: DateTime::from_timestamp_nanos(0),
-            speed: 1f64,
+            timetable: crate::speed::TimeTable::constant(1f64, 1f64),
             total_distance: 1f64,
         };
 
@@ -478,6 +493,7 @@ mod tests {
             has_end_control: true,
             time_points,
             outer_arcs: arcs,
+            dark_arcs: Vec::new(),
         }
     }
 
@@ -6,6 +6,7 @@ use crate::{
     inputpoint::{InputPoint, InputType},
     mercator::DateTime,
     segment::SegmentData,
+    speed::TimeTable,
     track::Track,
     wheel::time_points,
 };
@@ -21,13 +22,18 @@ pub fn angle(x: f64, total: f64) -> f64 {
 #[derive(Clone)]
 pub struct TimeParameters {
     pub start: DateTime,
-    pub speed: f64,
+    pub timetable: TimeTable,
     pub total_distance: f64,
 }
 
 impl TimeParameters {
     pub fn duration_seconds(&self) -> f64 {
-        self.total_distance / self.speed
+        self.timetable.total_seconds()
+    }
+    // the wheel is linear in distance, not in time.
+    pub fn angle_at(&self, seconds: f64) -> f64 {
+        let distance = self.timetable.distance_at(seconds);
+        angle(distance.min(self.total_distance), self.total_distance)
     }
 }
 
@@ -100,6 +106,7 @@ pub struct WheelModel {
     pub has_end_control: bool,
     pub time_points: Vec<CirclePoint>,
     pub outer_arcs: Vec<Arc>,
+    pub dark_arcs: Vec<Arc>,
 }
 
 fn angles(point: &InputPoint, track: &Track) -> Vec<f64> {
@@ -133,6 +140,7 @@ impl WheelModel {
             has_end_control: false,
             time_points: time_points::generate(time_parameters),
             outer_arcs: Vec::new(),
+            dark_arcs: Vec::new(),
         }
     }
     pub fn add_points(&mut self, segment: &SegmentData, kinds: HashSet<InputType>) {
@@ -164,6 +172,17 @@ impl WheelModel {
             self.mid_points.sort_by_key(|p| p.angle.floor() as i32);
         }
     }
+    pub fn add_daylight(&mut self, segment: &SegmentData) {
+        let total = self.time_parameters.total_distance;
+        for range in &segment.daylight().dark {
+            self.dark_arcs.push(Arc {
+                start_angle: Arc::clamp_angle(range.start, total),
+                middle_angle: None,
+                end_angle: Arc::clamp_angle(range.end, total),
+                label: String::new(),
+            });
+        }
+    }
     pub fn add_pages(&mut self, segments: &Vec<Segment>) {
         self.outer_arcs = Arc::from_segments(segments, &self.time_parameters);
     }
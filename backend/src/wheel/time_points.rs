@@ -48,7 +48,7 @@ use mercator::DateTime;
 
 use crate::{
     mercator,
-    wheel::model::{angle, CirclePoint, TimeParameters},
+    wheel::model::{CirclePoint, TimeParameters},
 };
 
 fn generate_time_intervals(
@@ -110,9 +110,10 @@ fn format_time(time: &DateTime, force: bool) -> String {
     }
 }
 
-fn make(times: &Vec<DateTime>, start_time: &DateTime, duration_seconds: f64) -> Vec<CirclePoint> {
+fn make(times: &[DateTime], time_parameters: &TimeParameters) -> Vec<CirclePoint> {
+    let start_time = &time_parameters.start;
     let mut ret = Vec::new();
-    let a_start = angle(0.0, duration_seconds);
+    let a_start = time_parameters.angle_at(0.0);
     let a_end = 360.0 - super::constants::ARCANGLE / 2.0;
     for (index, time) in times.iter().enumerate() {
         let force = index == 0 || index == times.len() - 1;
@@ -120,7 +121,7 @@ fn make(times: &Vec<DateTime>, start_time: &DateTime, duration_seconds: f64) ->
             .signed_duration_since(start_time)
             .as_seconds_f64()
             .floor();
-        let a = angle(x, duration_seconds);
+        let a = time_parameters.angle_at(x);
         let margin = 10.0;
         // this condition is needed if we include the start time (or the end time)
         // to ensure no label overlap
@@ -145,5 +146,5 @@ pub fn generate(time_parameters: &TimeParameters) -> Vec<CirclePoint> {
     let duration = std::time::Duration::from_secs_f64(duration_seconds);
     let interval = nice_interval(duration, 12);
     let times = generate_time_intervals(start_time, duration, interval);
-    make(&times, &start_time, duration_seconds)
+    make(&times, time_parameters)
 }
@@ -1,14 +1,11 @@
-#table(
+// segment-page(segment): the profile, the map and the points of a segment.
+#let segment-page(segment) = table(
     columns: 2,
     inset: 0pt,
     stroke: 1pt,
     align: (center + horizon,center+horizon),
-    table.cell(colspan:2,inset:3pt,image({profile.svg}, format: "svg", width: 100%)),
-    table.cell(colspan:1,inset:10pt,image({map.svg}, format: "svg", height: 200pt, width:200pt)),
-    table.cell(colspan:1,inset:10pt,[
-        {table-points}
-    ]),
+    table.cell(colspan:2,inset:3pt,image(bytes(segment.profile), format: "svg", width: 100%)),
+    table.cell(colspan:1,inset:10pt,image(bytes(segment.map), format: "svg", height: 200pt, width:200pt)),
+    table.cell(colspan:1,inset:10pt,points-table(segment.points)),
     table.hline()
 )
-
-
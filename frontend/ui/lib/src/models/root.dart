@@ -5,13 +5,13 @@ import 'package:flutter/widgets.dart';
 import 'package:ui/src/rust/api/bridge.dart' as bridge;
 
 class EventModel extends ChangeNotifier {
-  late Stream<String> stream;
+  late Stream<bridge.Event> stream;
   EventModel(bridge.Bridge bridge) {
     stream = bridge.setSink();
     //events.listen(onEvents);
   }
   /*
-  void onEvents(String data) {
+  void onEvents(bridge.Event data) {
     developer.log("event:$data");
     notifyListeners();
   }
@@ -22,9 +22,16 @@ class RootModel extends ChangeNotifier {
   late bridge.Bridge _bridge;
   EventModel? _eventModel;
   bridge.Segment? _trackSegment;
+  bridge.Cancellation? _cancellation;
 
   RootModel() {
     _bridge = bridge.Bridge.make();
+    // the defaults for new tracks, from the user configuration file.
+    try {
+      _bridge.loadUserConfig();
+    } catch (e) {
+      developer.log("could not read the user configuration: $e");
+    }
   }
 
   bridge.Bridge getBridge() {
@@ -42,10 +49,23 @@ class RootModel extends ChangeNotifier {
     await _bridge.loadDemo();
   }
 
+  // a new cancellation for each long running operation.
+  bridge.Cancellation _makeCancellation() {
+    _cancellation = bridge.Cancellation.make();
+    return _cancellation!;
+  }
+
+  void cancel() {
+    _cancellation?.cancel();
+  }
+
   Future<void> loadContent(List<int> bytes) async {
     developer.log("load ${bytes.length} bytes");
     _trackSegment = null;
-    await _bridge.loadContent(content: bytes);
+    await _bridge.loadContent(
+      content: bytes,
+      cancellation: _makeCancellation(),
+    );
   }
 
   Future<void> loadFilename(String filename) async {
@@ -73,11 +93,11 @@ class RootModel extends ChangeNotifier {
   }
 
   Future<List<int>> generatePdf() {
-    return _bridge.generatePdf();
+    return _bridge.generatePdf(cancellation: _makeCancellation());
   }
 
   Future<List<int>> generateZip() {
-    return _bridge.generateZip();
+    return _bridge.generateZip(cancellation: _makeCancellation());
   }
 
   bridge.SegmentStatistics statistics() {
@@ -110,9 +130,21 @@ class ParameterChanger {
       smoothWidth: init.smoothWidth,
       profileOptions: init.profileOptions,
       mapOptions: init.mapOptions,
+      templates: init.templates,
+      maxTableRows: init.maxTableRows,
       userStepsOptions: init.userStepsOptions,
       debug: init.debug,
       controlGpxNameFormat: init.controlGpxNameFormat,
+      brevetRules: init.brevetRules,
+      brevetDistance: init.brevetDistance,
+      controlDwell: init.controlDwell,
+      sleeps: init.sleeps,
+      speedModel: init.speedModel,
+      osmCategories: init.osmCategories,
+      pdf: init.pdf,
+      pointsTable: init.pointsTable,
+      elevationGain: init.elevationGain,
+      elevationSmoothing: init.elevationSmoothing,
     );
     init = ret;
     return ret;
@@ -131,9 +163,21 @@ class ParameterChanger {
       smoothWidth: init.smoothWidth,
       profileOptions: init.profileOptions,
       mapOptions: init.mapOptions,
+      templates: init.templates,
+      maxTableRows: init.maxTableRows,
       userStepsOptions: init.userStepsOptions,
       debug: init.debug,
       controlGpxNameFormat: init.controlGpxNameFormat,
+      brevetRules: init.brevetRules,
+      brevetDistance: init.brevetDistance,
+      controlDwell: init.controlDwell,
+      sleeps: init.sleeps,
+      speedModel: init.speedModel,
+      osmCategories: init.osmCategories,
+      pdf: init.pdf,
+      pointsTable: init.pointsTable,
+      elevationGain: init.elevationGain,
+      elevationSmoothing: init.elevationSmoothing,
     );
     init = ret;
     return ret;
@@ -148,9 +192,21 @@ class ParameterChanger {
       smoothWidth: init.smoothWidth,
       profileOptions: init.profileOptions,
       mapOptions: init.mapOptions,
+      templates: init.templates,
+      maxTableRows: init.maxTableRows,
       userStepsOptions: init.userStepsOptions,
       debug: init.debug,
       controlGpxNameFormat: init.controlGpxNameFormat,
+      brevetRules: init.brevetRules,
+      brevetDistance: init.brevetDistance,
+      controlDwell: init.controlDwell,
+      sleeps: init.sleeps,
+      speedModel: init.speedModel,
+      osmCategories: init.osmCategories,
+      pdf: init.pdf,
+      pointsTable: init.pointsTable,
+      elevationGain: init.elevationGain,
+      elevationSmoothing: init.elevationSmoothing,
     );
     init = ret;
     return ret;
@@ -165,9 +221,51 @@ class ParameterChanger {
       smoothWidth: init.smoothWidth,
       profileOptions: init.profileOptions,
       mapOptions: init.mapOptions,
+      templates: init.templates,
+      maxTableRows: init.maxTableRows,
+      userStepsOptions: init.userStepsOptions,
+      debug: init.debug,
+      controlGpxNameFormat: init.controlGpxNameFormat,
+      brevetRules: init.brevetRules,
+      brevetDistance: init.brevetDistance,
+      controlDwell: init.controlDwell,
+      sleeps: init.sleeps,
+      speedModel: init.speedModel,
+      osmCategories: init.osmCategories,
+      pdf: init.pdf,
+      pointsTable: init.pointsTable,
+      elevationGain: init.elevationGain,
+      elevationSmoothing: init.elevationSmoothing,
+    );
+    init = ret;
+    return ret;
+  }
+
+  // null: the points have no control times (not a brevet).
+  bridge.Parameters changeBrevetRules(bridge.BrevetRules? rules) {
+    bridge.Parameters ret = bridge.Parameters(
+      speed: init.speed,
+      startTime: init.startTime,
+      segmentLength: init.segmentLength,
+      segmentOverlap: init.segmentOverlap,
+      smoothWidth: init.smoothWidth,
+      profileOptions: init.profileOptions,
+      mapOptions: init.mapOptions,
+      templates: init.templates,
+      maxTableRows: init.maxTableRows,
       userStepsOptions: init.userStepsOptions,
       debug: init.debug,
       controlGpxNameFormat: init.controlGpxNameFormat,
+      brevetRules: rules,
+      brevetDistance: init.brevetDistance,
+      controlDwell: init.controlDwell,
+      sleeps: init.sleeps,
+      speedModel: init.speedModel,
+      osmCategories: init.osmCategories,
+      pdf: init.pdf,
+      pointsTable: init.pointsTable,
+      elevationGain: init.elevationGain,
+      elevationSmoothing: init.elevationSmoothing,
     );
     init = ret;
     return ret;
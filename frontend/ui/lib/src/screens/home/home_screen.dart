@@ -46,7 +46,7 @@ class _ChooseDataState extends State<ChooseData> {
   void chooseGPX(RootModel rootModel) async {
     FilePickerResult? result = await FilePicker.platform.pickFiles(
       type: FileType.custom,
-      allowedExtensions: ["gpx"],
+      allowedExtensions: ["gpx", "kml", "geojson", "json", "tcx", "fit"],
     );
     if (result == null) {
       return;
@@ -177,6 +177,18 @@ class _ChooseDataState extends State<ChooseData> {
   }
 }
 
+String describe(bridge.Event event) {
+  return switch (event) {
+    bridge.Event_StageStarted(:final stage) => stage.name,
+    bridge.Event_Progress(:final stage, :final fraction) =>
+      "${stage.name} ${(100 * fraction).round()}%",
+    bridge.Event_TilesFetched(:final done, :final total) =>
+      "osm tiles $done/$total",
+    bridge.Event_Warning(:final message) => "warning: $message",
+    _ => event.toString(),
+  };
+}
+
 class StreamWidget extends StatefulWidget {
   const StreamWidget({super.key});
 
@@ -206,7 +218,7 @@ class _StreamWidgetState extends State<StreamWidget> {
     if (model == null) {
       return Text("loading..");
     }
-    return StreamBuilder<String>(
+    return StreamBuilder<bridge.Event>(
       stream: model!.stream,
       builder: (context, snap) {
         final error = snap.error;
@@ -217,7 +229,7 @@ class _StreamWidgetState extends State<StreamWidget> {
         }
         final data = snap.data;
         if (data != null) {
-          text = data;
+          text = describe(data);
         }
         return Text('text=$text');
       },
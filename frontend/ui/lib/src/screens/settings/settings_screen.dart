@@ -150,6 +150,31 @@ class SliderWidget extends StatelessWidget {
   }
 }
 
+class BrevetRulesWidget extends StatelessWidget {
+  const BrevetRulesWidget({super.key});
+
+  void onChanged(BuildContext context, BrevetRules? rules) {
+    RootModel root = Provider.of<RootModel>(context, listen: false);
+    ParameterChanger changer = ParameterChanger(init: root.parameters());
+    root.setParameters(changer.changeBrevetRules(rules));
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    RootModel root = Provider.of<RootModel>(context);
+    List<DropdownMenuItem<BrevetRules?>> items = [
+      DropdownMenuItem(value: null, child: Text("no brevet")),
+      for (BrevetRules rules in BrevetRules.values)
+        DropdownMenuItem(value: rules, child: Text(rules.name.toUpperCase())),
+    ];
+    return DropdownButton<BrevetRules?>(
+      value: root.parameters().brevetRules,
+      items: items,
+      onChanged: (value) => onChanged(context, value),
+    );
+  }
+}
+
 class SettingsWidget extends StatelessWidget {
   final VoidCallback? onShowPressed;
   final bool show;
@@ -225,6 +250,17 @@ class SettingsWidget extends StatelessWidget {
               ),
             ],
           ),
+          Row(
+            mainAxisAlignment: MainAxisAlignment.center,
+            crossAxisAlignment: CrossAxisAlignment.center,
+            children: [
+              Padding(
+                padding: const EdgeInsets.all(8.0),
+                child: Text("control times"),
+              ),
+              BrevetRulesWidget(),
+            ],
+          ),
         ],
       ),
     );
@@ -112,12 +112,14 @@ class UserStepsModel extends ChangeNotifier {
         stepDistance: null,
         stepElevationGain: null,
         gpxNameFormat: currentOptions!.gpxNameFormat,
+        dwell: currentOptions!.dwell,
       );
     } else if (parameter == SelectedParameter.distance) {
       currentOptions = UserStepsOptions(
         stepDistance: value!,
         stepElevationGain: null,
         gpxNameFormat: currentOptions!.gpxNameFormat,
+        dwell: currentOptions!.dwell,
       );
     } else {
       assert(parameter == SelectedParameter.elevation);
@@ -125,6 +127,7 @@ class UserStepsModel extends ChangeNotifier {
         stepDistance: null,
         stepElevationGain: value!,
         gpxNameFormat: currentOptions!.gpxNameFormat,
+        dwell: currentOptions!.dwell,
       );
     }
     _sendParameterToBackend();
@@ -5,16 +5,35 @@ use flutter_rust_bridge::frb;
 use std::collections::HashSet;
 // must be exported for mirroring Segment.
 pub use std::ops::Range;
+pub use tracks::backend::CacheConfig;
+pub use tracks::backend::CacheStats;
+pub use tracks::backend::Event;
+pub use tracks::backend::OverpassConfig;
+pub use tracks::backend::Recording;
 pub use tracks::backend::Segment as SegmentImplementation;
 pub use tracks::backend::SegmentStatistics;
+pub use tracks::backend::Stage;
+pub use tracks::brevet::BrevetRules;
+pub use tracks::elevation::Gain;
+pub use tracks::elevation::Smoothing;
 pub use tracks::error::Error;
 pub use tracks::inputpoint::InputType;
+pub use tracks::inputpoint::OSMCategory;
+pub use tracks::inputpoint::OSMTagFilter;
+pub use tracks::inputpoint::OSMType;
 pub use tracks::mercator::MercatorPoint;
 pub use tracks::parameters::MapOptions;
 pub use tracks::parameters::Parameters;
 pub use tracks::parameters::ProfileIndication;
 pub use tracks::parameters::ProfileOptions;
+pub use tracks::parameters::TableColumn;
 pub use tracks::parameters::UserStepsOptions;
+pub use tracks::pdf::PdfBackend;
+pub use tracks::pdf::PdfConfig;
+pub use tracks::speed::PhysicsModel;
+pub use tracks::speed::SlopeSpeed;
+pub use tracks::speed::SpeedModel;
+pub use tracks::speed::Stop;
 pub use tracks::waypoint::Waypoint;
 pub use tracks::waypoint::WaypointInfo;
 pub use tracks::wgs84point::WGS84Point;
@@ -30,14 +49,56 @@ use crate::frb_generated::StreamSink;
 #[frb(opaque)]
 #[derive(Clone)]
 pub struct EventSender {
-    sink: StreamSink<String>,
+    sink: StreamSink<Event>,
 }
 
 use tracks::backend::Sender;
 
 impl Sender for EventSender {
-    fn send(&mut self, data: &String) {
-        let _ = self.sink.add(data.clone());
+    fn send(&mut self, event: &Event) {
+        let _ = self.sink.add(event.clone());
+    }
+}
+
+#[frb(mirror(Stage))]
+pub enum _Stage {
+    Load,
+    ReadTrack,
+    OSM,
+    Controls,
+    Pdf,
+}
+
+#[frb(mirror(Event))]
+pub enum _Event {
+    StageStarted { stage: Stage },
+    StageFinished { stage: Stage },
+    Progress { stage: Stage, fraction: f64 },
+    BytesDownloaded { bytes: u64 },
+    TilesFetched { done: usize, total: usize },
+    Warning { message: String },
+}
+
+// cancels load_content, generate_pdf and generate_zip from the app.
+#[frb(opaque)]
+pub struct Cancellation {
+    token: tracks::cancel::CancelToken,
+}
+
+impl Cancellation {
+    #[frb(sync)]
+    pub fn make() -> Cancellation {
+        Cancellation {
+            token: tracks::cancel::CancelToken::new(),
+        }
+    }
+    #[frb(sync)]
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+    #[frb(sync)]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
     }
 }
 
@@ -70,6 +131,7 @@ pub enum _InputType {
     OSM,
     UserStep,
     Control,
+    Sun,
 }
 
 #[frb(sync)]
@@ -89,6 +151,7 @@ pub struct _UserStepsOptions {
     pub step_distance: Option<f64>,
     pub step_elevation_gain: Option<f64>,
     pub gpx_name_format: String,
+    pub dwell: f64,
 }
 
 #[frb(mirror(ProfileOptions))]
@@ -102,17 +165,167 @@ pub struct _MapOptions {
     pub max_area_ratio: f64,
 }
 
+#[frb(mirror(SlopeSpeed))]
+pub struct _SlopeSpeed {
+    pub slope: f64,
+    pub speed: f64,
+}
+
+#[frb(mirror(PhysicsModel))]
+pub struct _PhysicsModel {
+    pub power: f64,
+    pub mass: f64,
+    pub cda: f64,
+    pub crr: f64,
+    pub max_speed: f64,
+}
+
+#[frb(mirror(SpeedModel))]
+pub enum _SpeedModel {
+    Constant,
+    SlopeTable(Vec<SlopeSpeed>),
+    Physics(PhysicsModel),
+}
+
+#[frb(mirror(Stop))]
+pub struct _Stop {
+    pub distance: f64,
+    pub duration: f64,
+}
+
+#[frb(mirror(BrevetRules))]
+pub enum _BrevetRules {
+    ACP,
+    RUSA,
+    LRM,
+}
+
+#[frb(mirror(OSMType))]
+pub enum _OSMType {
+    City,
+    MountainPass,
+    Peak,
+    Village,
+    Hamlet,
+    DrinkingWater,
+    Fuel,
+    Supermarket,
+    Bakery,
+    BicycleShop,
+    Toilets,
+    Shelter,
+    Campsite,
+}
+
+#[frb(mirror(OSMTagFilter))]
+pub struct _OSMTagFilter {
+    pub element: String,
+    pub key: String,
+    pub value: String,
+}
+
+#[frb(mirror(OSMCategory))]
+pub struct _OSMCategory {
+    pub kind: OSMType,
+    pub name: String,
+    pub icon: String,
+    pub filters: Vec<OSMTagFilter>,
+    pub max_distance: f64,
+    pub priority: i32,
+}
+
+#[frb(mirror(Recording))]
+pub enum _Recording {
+    Off,
+    Record(String),
+    Replay(String),
+}
+
+#[frb(mirror(OverpassConfig))]
+pub struct _OverpassConfig {
+    pub endpoints: Vec<String>,
+    pub user_agent: String,
+    pub max_attempts: usize,
+    pub initial_backoff_ms: u64,
+    pub recording: Recording,
+}
+
+#[frb(mirror(CacheConfig))]
+pub struct _CacheConfig {
+    pub max_age: Option<u64>,
+    pub max_size: Option<u64>,
+}
+
+#[frb(mirror(CacheStats))]
+pub struct _CacheStats {
+    pub chunks: usize,
+    pub size: u64,
+    pub expired: usize,
+    pub oldest: Option<i64>,
+    pub newest: Option<i64>,
+}
+
+#[frb(mirror(Smoothing))]
+pub enum _Smoothing {
+    MovingAverage,
+    SavitzkyGolay,
+    Resampled,
+}
+
+#[frb(mirror(Gain))]
+pub enum _Gain {
+    Sum,
+    Hysteresis { threshold: f64 },
+}
+
+#[frb(mirror(PdfBackend))]
+pub enum _PdfBackend {
+    Auto,
+    Embedded,
+    Binary,
+    Remote,
+}
+
+#[frb(mirror(PdfConfig))]
+pub struct _PdfConfig {
+    pub backend: PdfBackend,
+    pub typst_binary: Option<String>,
+    pub remote_url: Option<String>,
+    pub ca_certificate: Option<String>,
+    pub pin_certificate: bool,
+    pub accept_invalid_certificates: bool,
+    pub timeout: u64,
+}
+
+#[frb(mirror(TableColumn))]
+pub struct _TableColumn {
+    pub title: String,
+    pub format: String,
+}
+
 #[frb(mirror(Parameters))]
 pub struct _Parameters {
+    pub brevet_rules: Option<BrevetRules>,
+    pub brevet_distance: Option<f64>,
     pub control_gpx_name_format: String,
+    pub control_dwell: f64,
     pub debug: bool,
+    pub elevation_gain: Gain,
+    pub elevation_smoothing: Smoothing,
     pub map_options: MapOptions,
+    pub max_table_rows: usize,
+    pub osm_categories: std::collections::HashSet<OSMType>,
+    pub pdf: PdfConfig,
+    pub points_table: Vec<TableColumn>,
     pub profile_options: ProfileOptions,
     pub segment_length: f64,
     pub segment_overlap: f64,
+    pub sleeps: Vec<Stop>,
     pub smooth_width: f64,
     pub speed: f64,
+    pub speed_model: SpeedModel,
     pub start_time: String,
+    pub templates: Option<String>,
     pub user_steps_options: UserStepsOptions,
 }
 
@@ -124,9 +337,16 @@ pub struct _WaypointInfo {
     pub inter_distance: f64,
     pub inter_elevation_gain: f64,
     pub inter_slope: f64,
+    pub elevation_gain: f64,
+    pub remaining_distance: f64,
+    pub remaining_elevation_gain: f64,
+    pub next_control: Option<f64>,
     pub name: String,
     pub origin: InputType,
     pub time: String,
+    pub elapsed: f64,
+    pub open: Option<String>,
+    pub close: Option<String>,
     pub track_index: Option<usize>,
     pub description: String,
 }
@@ -156,6 +376,30 @@ pub enum _Error {
     GPXInvalid,
     GPXHasNoSegment,
     MissingElevation { index: usize },
+    InvalidPartOrder { parts: usize },
+    UnknownFormat,
+    KMLInvalid,
+    GeoJSONInvalid,
+    TCXInvalid,
+    FITInvalid,
+    NoTrack { format: String },
+    ProjectInvalid,
+    ProjectVersion { version: u32 },
+    ControlNotFound { index: usize },
+    ControlNotOnTrack,
+    DistanceOutOfTrack { distance: f64 },
+    PBFInvalid,
+    OSMDownload,
+    Cancelled,
+    NotLoaded,
+    InvalidParameter { name: String, reason: String },
+    InvalidParameters { problems: Vec<String> },
+    InvalidArgument { argument: String, reason: String },
+    ConfigInvalid { message: String },
+    UnknownRenderTarget { what: String },
+    IOError { message: String },
+    PdfCompile { message: String },
+    TemplateInvalid { problems: Vec<String> },
 }
 
 use tracks::backend;
@@ -167,60 +411,187 @@ impl Bridge {
         }
     }
     #[frb(sync)]
-    pub fn set_sink(&mut self, sink: StreamSink<String>) -> anyhow::Result<()> {
+    pub fn set_sink(&mut self, sink: StreamSink<Event>) -> anyhow::Result<()> {
         let cell = Box::new(EventSender { sink });
         self.backend.set_sink(cell);
         Ok(())
     }
+    #[frb(sync)]
+    pub fn set_dem_directory(&mut self, directory: &String, replace_all: bool) {
+        let fill = match replace_all {
+            true => tracks::dem::ElevationFill::All,
+            false => tracks::dem::ElevationFill::Missing,
+        };
+        self.backend.set_dem_directory(directory, fill);
+    }
+    #[frb(sync)]
+    pub fn set_part_order(&mut self, order: Option<Vec<usize>>) {
+        self.backend.set_part_order(order);
+    }
+    #[frb(sync)]
+    pub fn get_overpass_config(&mut self) -> OverpassConfig {
+        self.backend.get_overpass_config()
+    }
+    #[frb(sync)]
+    pub fn set_overpass_config(&mut self, config: &OverpassConfig) {
+        self.backend.set_overpass_config(config);
+    }
+    #[frb(sync)]
+    pub fn get_cache_config(&mut self) -> CacheConfig {
+        self.backend.get_cache_config()
+    }
+    #[frb(sync)]
+    pub fn set_cache_config(&mut self, config: &CacheConfig) {
+        self.backend.set_cache_config(config);
+    }
+    #[frb(sync)]
+    pub fn get_default_parameters(&mut self) -> Parameters {
+        self.backend.get_default_parameters()
+    }
+    #[frb(sync)]
+    pub fn set_default_parameters(&mut self, parameters: &Parameters) -> Result<(), Error> {
+        self.backend.set_default_parameters(parameters)
+    }
+    #[frb(sync)]
+    pub fn load_user_config(&mut self) -> Result<bool, Error> {
+        self.backend.load_user_config()
+    }
+    pub async fn osm_cache_stats(&mut self) -> CacheStats {
+        self.backend.osm_cache_stats().await
+    }
+    pub async fn clear_osm_cache(&mut self) {
+        self.backend.clear_osm_cache().await
+    }
+    pub async fn prefetch_osm(
+        &mut self,
+        min_longitude: f64,
+        min_latitude: f64,
+        max_longitude: f64,
+        max_latitude: f64,
+    ) -> Result<(), Error> {
+        self.backend
+            .prefetch_osm(
+                &WGS84Point::new(&min_longitude, &min_latitude, &0f64),
+                &WGS84Point::new(&max_longitude, &max_latitude, &0f64),
+            )
+            .await
+    }
+    #[frb(sync)]
+    pub fn set_osm_pbf_file(&mut self, filename: Option<String>) {
+        self.backend.set_osm_pbf_file(filename);
+    }
     pub async fn load_filename(&mut self, filename: &str) -> Result<(), Error> {
         self.backend.load_filename(filename).await
     }
-    pub async fn load_content(&mut self, content: &Vec<u8>) -> Result<(), Error> {
-        self.backend.load_content(content).await
+    pub async fn load_content(
+        &mut self,
+        content: &Vec<u8>,
+        cancellation: &Cancellation,
+    ) -> Result<(), Error> {
+        self.backend
+            .load_content(content, &cancellation.token)
+            .await
     }
     pub async fn load_demo(&mut self) -> Result<(), Error> {
         self.backend.load_demo().await
     }
-    pub async fn generatePdf(&mut self) -> Vec<u8> {
-        self.backend.generatePdf().await
+    pub async fn load_project(&mut self, content: &Vec<u8>) -> Result<(), Error> {
+        self.backend.load_project(content).await
+    }
+    pub async fn save_project(&mut self) -> Result<Vec<u8>, Error> {
+        self.backend.save_project()
+    }
+    pub async fn generatePdf(&mut self, cancellation: &Cancellation) -> Result<Vec<u8>, Error> {
+        self.backend.generatePdf(&cancellation.token).await
+    }
+    pub fn check_templates(&mut self, directory: &String) -> Result<(), Error> {
+        self.backend.check_templates(directory)
     }
-    pub async fn generateGpx(&mut self) -> Vec<u8> {
+    pub fn export_templates(&mut self, directory: &String) -> Result<(), Error> {
+        self.backend.export_templates(directory)
+    }
+    pub async fn generateGpx(&mut self) -> Result<Vec<u8>, Error> {
         self.backend.generateGpx()
     }
-    pub async fn generateZip(&mut self) -> Vec<u8> {
-        self.backend.generateZip().await
+    pub async fn generateZip(&mut self, cancellation: &Cancellation) -> Result<Vec<u8>, Error> {
+        self.backend.generateZip(&cancellation.token).await
     }
     #[frb(sync)]
-    pub fn get_waypoints(&mut self, segment: &Segment, kinds: HashSet<InputType>) -> Vec<Waypoint> {
+    pub fn get_waypoints(
+        &mut self,
+        segment: &Segment,
+        kinds: HashSet<InputType>,
+    ) -> Result<Vec<Waypoint>, Error> {
         self.backend.get_waypoints(&segment._impl, kinds)
     }
     #[frb(sync)]
-    pub fn get_parameters(&mut self) -> Parameters {
+    pub fn get_osm_categories(&mut self) -> Vec<OSMCategory> {
+        self.backend.get_osm_categories()
+    }
+    #[frb(sync)]
+    pub fn get_controls(&mut self) -> Result<Vec<Waypoint>, Error> {
+        self.backend.get_controls()
+    }
+    #[frb(sync)]
+    pub fn add_control_at_distance(&mut self, distance: f64, name: &str) -> Result<(), Error> {
+        self.backend.add_control_at_distance(distance, name)
+    }
+    #[frb(sync)]
+    pub fn add_control_near(
+        &mut self,
+        longitude: f64,
+        latitude: f64,
+        name: &str,
+    ) -> Result<(), Error> {
+        let wgs84 = WGS84Point::new(&longitude, &latitude, &0f64);
+        self.backend.add_control_near(&wgs84, name)
+    }
+    #[frb(sync)]
+    pub fn rename_control(&mut self, index: usize, name: &str) -> Result<(), Error> {
+        self.backend.rename_control(index, name)
+    }
+    #[frb(sync)]
+    pub fn move_control(&mut self, index: usize, distance: f64) -> Result<(), Error> {
+        self.backend.move_control(index, distance)
+    }
+    #[frb(sync)]
+    pub fn delete_control(&mut self, index: usize) -> Result<(), Error> {
+        self.backend.delete_control(index)
+    }
+    #[frb(sync)]
+    pub fn get_sun_waypoints(&mut self, segment: &Segment) -> Result<Vec<Waypoint>, Error> {
+        self.backend.get_sun_waypoints(&segment._impl)
+    }
+    #[frb(sync)]
+    pub fn get_parameters(&mut self) -> Result<Parameters, Error> {
         self.backend.get_parameters()
     }
     #[frb(sync)]
-    pub fn set_parameters(&mut self, parameters: &Parameters) {
-        self.backend.set_parameters(parameters);
+    pub fn set_parameters(&mut self, parameters: &Parameters) -> Result<(), Error> {
+        self.backend.set_parameters(parameters)
     }
 
     #[frb(sync)]
-    pub fn set_user_step_options(&mut self, user_steps_options: &UserStepsOptions) {
-        self.backend.set_user_step_options(user_steps_options);
+    pub fn set_user_step_options(
+        &mut self,
+        user_steps_options: &UserStepsOptions,
+    ) -> Result<(), Error> {
+        self.backend.set_user_step_options(user_steps_options)
     }
 
     #[frb(sync)]
-    pub fn set_profile_indication(&mut self, p: &ProfileIndication) {
-        self.backend.set_profile_indication(p);
+    pub fn set_profile_indication(&mut self, p: &ProfileIndication) -> Result<(), Error> {
+        self.backend.set_profile_indication(p)
     }
 
     #[frb(sync)]
-    pub fn set_userstep_gpx_name_format(&mut self, format: &String) {
-        self.backend.set_userstep_gpx_name_format(format);
+    pub fn set_userstep_gpx_name_format(&mut self, format: &String) -> Result<(), Error> {
+        self.backend.set_userstep_gpx_name_format(format)
     }
 
     #[frb(sync)]
-    pub fn set_control_gpx_name_format(&mut self, format: &String) {
-        self.backend.set_control_gpx_name_format(format);
+    pub fn set_control_gpx_name_format(&mut self, format: &String) -> Result<(), Error> {
+        self.backend.set_control_gpx_name_format(format)
     }
 
     #[frb(sync)]
@@ -234,8 +605,7 @@ impl Bridge {
         what: &String,
         size: &(i32, i32),
         kinds: HashSet<InputType>,
-    ) -> String {
-        assert!(self.backend.loaded());
+    ) -> Result<String, Error> {
         self.backend.render_segment_what(
             &segment._impl,
             what,
@@ -251,7 +621,7 @@ impl Bridge {
         what: &String,
         size: &(i32, i32),
         kinds: HashSet<InputType>,
-    ) -> String {
+    ) -> Result<String, Error> {
         self.backend.render_segment_what(
             &segment._impl,
             what,
@@ -261,29 +631,29 @@ impl Bridge {
     }
 
     #[frb(sync)]
-    pub fn statistics(&self) -> SegmentStatistics {
+    pub fn statistics(&self) -> Result<SegmentStatistics, Error> {
         self.backend.statistics()
     }
 
     #[frb(sync)]
-    pub fn segment_statistics(&self, segment: &Segment) -> SegmentStatistics {
+    pub fn segment_statistics(&self, segment: &Segment) -> Result<SegmentStatistics, Error> {
         self.backend.segment_statistics(&segment._impl)
     }
 
     #[frb(sync)]
-    pub fn segments(&self) -> Vec<Segment> {
-        let S = self.backend.segments();
+    pub fn segments(&self) -> Result<Vec<Segment>, Error> {
+        let S = self.backend.segments()?;
         let mut ret = Vec::new();
         for s in S {
             ret.push(Segment::create(s));
         }
-        ret
+        Ok(ret)
     }
 
     #[frb(sync)]
-    pub fn trackSegment(&self) -> Segment {
-        let backend_segment = self.backend.trackSegment();
-        Segment::create(backend_segment)
+    pub fn trackSegment(&self) -> Result<Segment, Error> {
+        let backend_segment = self.backend.trackSegment()?;
+        Ok(Segment::create(backend_segment))
     }
 }
 